@@ -0,0 +1,30 @@
+fn main() {
+    // the gRPC service of `aranet grpc`, defined in proto/aranet.proto. It's described here rather than compiled from
+    // the .proto, so building doesn't need protoc, with messages written to match in src/grpc.rs.
+    #[cfg(feature = "grpc")]
+    {
+        use tonic_build::manual::{Builder, Method, Service};
+
+        println!("cargo:rerun-if-changed=build.rs");
+        let method = |name: &str, route: &str, input: &str, output: &str, streaming: bool| {
+            let method = Method::builder()
+                .name(name)
+                .route_name(route)
+                .input_type(format!("crate::grpc::{}", input))
+                .output_type(format!("crate::grpc::{}", output))
+                .codec_path("tonic::codec::ProstCodec");
+            match streaming {
+                true => method.server_streaming().build(),
+                false => method.build(),
+            }
+        };
+        let service = Service::builder()
+            .name("Aranet")
+            .package("aranet.v1")
+            .method(method("get_current", "GetCurrent", "GetCurrentRequest", "Reading", false))
+            .method(method("list_devices", "ListDevices", "ListDevicesRequest", "ListDevicesResponse", false))
+            .method(method("watch_readings", "WatchReadings", "WatchReadingsRequest", "Reading", true))
+            .build();
+        Builder::new().build_client(false).compile(&[service]);
+    }
+}
@@ -0,0 +1,377 @@
+//! Shares this machine's Bluetooth radio over the network, for `aranet agent`, and reads advertisements from such an
+//! agent, for --remote.
+//!
+//! This lets aranet run on a desktop while the radio is on a Raspberry Pi near the devices. The protocol is JSON
+//! Lines over TCP:
+//!
+//! 1. The agent sends `{"agent": "aranet", "version": "<version>", "nonce": "<hex>"}`, with its aranet version and a
+//!    random nonce.
+//! 2. The client replies `{"auth": "<hex>", "nonce": "<hex>"}`, with a random nonce of its own, and the HMAC-SHA256
+//!    keyed with --agent-token of `client`, the agent's nonce, then its own. The token itself is never sent.
+//! 3. The agent replies `{"ok": true, "auth": "<hex>"}`, with the HMAC of `agent`, the client's nonce, then its own,
+//!    so the client knows it's talking to an agent with the token too. Otherwise it replies `{"error": "..."}` and
+//!    closes the connection.
+//! 4. The agent sends each advertisement it hears, in the format of `aranet record`.
+//!
+//! Each message is a line of at most 64 KiB.
+//!
+//! Only advertisements are shared: the client can't connect to devices through the agent, so --active and
+//! --prefer-connect can't be used with --remote. Advertisements aren't encrypted, so use a VPN or SSH tunnel across
+//! untrusted networks.
+
+use std::error::Error;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use btleplug::platform::Manager;
+use futures::StreamExt;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufStream};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+
+use crate::error::{CliError, ErrorKind};
+use crate::filter::DeviceFilter;
+use crate::record::RecordedAdvertisement;
+use crate::Advertisements;
+
+/// Default port of an agent
+pub const DEFAULT_PORT: u16 = 7070;
+
+/// How long the client has to authenticate, and the agent to reply
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait before reconnecting to an agent
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Advertisements buffered for each client, beyond which a slow client misses some
+const BUFFER: usize = 256;
+
+/// The longest line either side accepts, far longer than any message
+const MAX_LINE: u64 = 64 * 1024;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct AgentArgs {
+    /// The address to listen on, such as `:7070` for every interface, or `127.0.0.1:7070`
+    #[arg(long, value_name = "ADDR", default_value = ":7070", value_parser = crate::parse_addr)]
+    listen: SocketAddr,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Hello {
+    agent: String,
+    version: String,
+    nonce: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Auth {
+    auth: String,
+    nonce: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum AuthReply {
+    Ok { ok: bool, auth: String },
+    Error { error: String },
+}
+
+/// A random nonce unique to each connection, so a recorded handshake can't be replayed
+fn nonce() -> io::Result<[u8; 16]> {
+    let mut nonce = [0; 16];
+    getrandom::getrandom(&mut nonce).map_err(io::Error::from)?;
+    Ok(nonce)
+}
+
+/// The MAC one side proves it has the token with. Each side's is over a different label, and its peer's nonce
+/// first, so neither can be tricked into computing the other's.
+fn mac(token: &str, label: &str, peer_nonce: &[u8], own_nonce: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(token.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(label.as_bytes());
+    mac.update(peer_nonce);
+    mac.update(own_nonce);
+    mac
+}
+
+/// Whether `tag` is the hex of the MAC
+fn verify(mac: Hmac<Sha256>, tag: &str) -> bool {
+    hex::decode(tag).is_ok_and(|tag| mac.verify_slice(&tag).is_ok())
+}
+
+/// Reads a line of at most [`MAX_LINE`] bytes, returning 0 at the end of the connection
+async fn read_line(connection: &mut BufStream<TcpStream>, line: &mut String) -> io::Result<usize> {
+    let read = connection.take(MAX_LINE).read_line(line).await?;
+    if read as u64 == MAX_LINE && ! line.ends_with('\n') {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("line longer than {} bytes", MAX_LINE)));
+    }
+    Ok(read)
+}
+
+/// Reads a JSON line, within [`HANDSHAKE_TIMEOUT`]
+async fn read_json<T: for<'de> Deserialize<'de>>(connection: &mut BufStream<TcpStream>) -> io::Result<T> {
+    let mut line = String::new();
+    let read = tokio::time::timeout(HANDSHAKE_TIMEOUT, read_line(connection, &mut line)).await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "no reply within 10s"))??;
+    if read == 0 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"));
+    }
+    serde_json::from_str(&line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+async fn write_json<T: Serialize>(connection: &mut BufStream<TcpStream>, value: &T) -> io::Result<()> {
+    let line = serde_json::to_string(value).expect("unable to serialize agent message as JSON");
+    connection.write_all(line.as_bytes()).await?;
+    connection.write_all(b"\n").await?;
+    connection.flush().await
+}
+
+/// Authenticates a client, then sends it advertisements until it disconnects
+async fn serve(stream: TcpStream, token: &str, mut advertisements: broadcast::Receiver<String>) -> io::Result<()> {
+    let mut connection = BufStream::new(stream);
+    let nonce = nonce()?;
+    let hello = Hello { agent: "aranet".to_owned(), version: env!("CARGO_PKG_VERSION").to_owned(), nonce: hex::encode(nonce) };
+    write_json(&mut connection, &hello).await?;
+
+    let auth: Auth = read_json(&mut connection).await?;
+    let client_nonce = hex::decode(&auth.nonce).unwrap_or_default();
+    if client_nonce.is_empty() || ! verify(mac(token, "client", &nonce, &client_nonce), &auth.auth) {
+        write_json(&mut connection, &AuthReply::Error { error: "authentication failed, check --agent-token".to_owned() }).await?;
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, "authentication failed"));
+    }
+    let proof = hex::encode(mac(token, "agent", &client_nonce, &nonce).finalize().into_bytes());
+    write_json(&mut connection, &AuthReply::Ok { ok: true, auth: proof }).await?;
+
+    loop {
+        match advertisements.recv().await {
+            Ok(line) => {
+                connection.write_all(line.as_bytes()).await?;
+                connection.write_all(b"\n").await?;
+                // advertisements are sent as they're heard, rather than when the buffer fills
+                connection.flush().await?;
+            },
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                log::warn!("client fell behind, skipped {} advertisements", missed);
+            },
+            Err(broadcast::error::RecvError::Closed) => return Ok(()),
+        }
+    }
+}
+
+/// Listens for devices matching the filter, sending their advertisements to each authenticated client
+pub async fn run(args: AgentArgs, token: Option<String>, filter: DeviceFilter) -> Result<(), Box<dyn Error>> {
+    let token = token.filter(|token| ! token.is_empty())
+        .ok_or("an agent needs --agent-token, which clients authenticate with")?;
+    let listener = TcpListener::bind(args.listen).await
+        .map_err(|e| format!("unable to listen on {}: {}", args.listen, e))?;
+
+    let manager = Manager::new().await?;
+    let mut discovered = crate::lock::discover(&manager).await?;
+    log::info!("sharing advertisements on {}", args.listen);
+
+    let (sender, _) = broadcast::channel::<String>(BUFFER);
+    let listen = {
+        let sender = sender.clone();
+        async move {
+            while let Some(adv) = discovered.next().await {
+                if ! filter.matches(&adv) {
+                    continue;
+                }
+                let line = serde_json::to_string(&RecordedAdvertisement::new(&adv)).expect("unable to serialize advertisement as JSON");
+                // no clients connected isn't an error
+                let _ = sender.send(line);
+            }
+        }
+    };
+
+    let accept = async {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    log::warn!("unable to accept a connection: {}", e);
+                    continue;
+                },
+            };
+            log::info!("client {} connected", peer);
+            let token = token.clone();
+            let advertisements = sender.subscribe();
+            tokio::spawn(async move {
+                match serve(stream, &token, advertisements).await {
+                    Ok(()) => log::info!("client {} disconnected", peer),
+                    Err(e) => log::info!("client {} disconnected: {}", peer, e),
+                }
+            });
+        }
+    };
+
+    tokio::select! {
+        () = listen => Err("Unable to discover devices. No Bluetooth adapters present.".into()),
+        never = accept => never,
+    }
+}
+
+/// Connects and authenticates to an agent
+async fn connect(addr: &str, token: &str) -> io::Result<BufStream<TcpStream>> {
+    let mut connection = BufStream::new(TcpStream::connect(addr).await?);
+    let hello: Hello = read_json(&mut connection).await
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("not an aranet agent: {}", e)))?;
+    let agent_nonce = hex::decode(&hello.nonce).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let nonce = nonce()?;
+    let auth = Auth {
+        auth: hex::encode(mac(token, "client", &agent_nonce, &nonce).finalize().into_bytes()),
+        nonce: hex::encode(nonce),
+    };
+    write_json(&mut connection, &auth).await?;
+    match read_json(&mut connection).await? {
+        AuthReply::Ok { ok: true, auth } if verify(mac(token, "agent", &nonce, &agent_nonce), &auth) => {},
+        AuthReply::Ok { ok: true, .. } => {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "the agent couldn't prove it has --agent-token"));
+        },
+        AuthReply::Ok { ok: false, .. } => return Err(io::Error::other("the agent refused the connection")),
+        AuthReply::Error { error } => return Err(io::Error::new(io::ErrorKind::PermissionDenied, error)),
+    }
+    log::info!("connected to agent {} (aranet {})", addr, hello.version);
+    Ok(connection)
+}
+
+/// The advertisements heard by the agent at `addr`, in place of [`aranet::discover_aranet4`]'s. When the connection
+/// is lost after connecting, it's reconnected.
+pub async fn remote(addr: &str, token: Option<&str>) -> Result<Advertisements, Box<dyn Error>> {
+    let addr = match addr.rsplit_once(':').is_some_and(|(_, port)| port.parse::<u16>().is_ok()) {
+        true => addr.to_owned(),
+        false => format!("{}:{}", addr, DEFAULT_PORT),
+    };
+    let token = token.unwrap_or_default().to_owned();
+    let connection = connect(&addr, &token).await
+        .map_err(|e| CliError::new(ErrorKind::ConnectFailed, format!("Unable to connect to agent {}: {}", addr, e)))?;
+
+    let advertisements = futures::stream::unfold(Some(connection), move |mut connection| {
+        let (addr, token) = (addr.clone(), token.clone());
+        async move {
+            loop {
+                let current = match &mut connection {
+                    Some(current) => current,
+                    None => match connect(&addr, &token).await {
+                        Ok(reconnected) => connection.insert(reconnected),
+                        Err(e) => {
+                            log::warn!("unable to reconnect to agent {}: {}, retrying in {}s", addr, e, RECONNECT_DELAY.as_secs());
+                            tokio::time::sleep(RECONNECT_DELAY).await;
+                            continue;
+                        },
+                    },
+                };
+                let mut line = String::new();
+                match read_line(current, &mut line).await {
+                    Ok(0) => log::warn!("agent {} closed the connection, reconnecting", addr),
+                    Ok(_) => {
+                        let parsed = serde_json::from_str::<RecordedAdvertisement>(&line)
+                            .map_err(|e| e.to_string())
+                            .and_then(RecordedAdvertisement::parse);
+                        match parsed {
+                            Ok(adv) => return Some((adv, connection)),
+                            Err(e) => {
+                                log::warn!("skipping invalid advertisement from agent {}: {}", addr, e);
+                                continue;
+                            },
+                        }
+                    },
+                    Err(e) => log::warn!("lost the connection to agent {}: {}, reconnecting", addr, e),
+                }
+                connection = None;
+            }
+        }
+    });
+    Ok(Box::pin(advertisements))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An agent serving one client with the token, and the address to connect to it on
+    async fn agent(token: &'static str) -> (String, broadcast::Sender<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let (sender, _) = broadcast::channel(BUFFER);
+        let advertisements = sender.subscribe();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let _ = serve(stream, token, advertisements).await;
+        });
+        (addr, sender)
+    }
+
+    /// A server that sends each line once a client connects, ignoring what it sends
+    async fn impostor(lines: Vec<String>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut connection = BufStream::new(stream);
+            for line in lines {
+                connection.write_all(line.as_bytes()).await.unwrap();
+                connection.write_all(b"\n").await.unwrap();
+                connection.flush().await.unwrap();
+                let mut reply = String::new();
+                let _ = connection.read_line(&mut reply).await;
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn sends_advertisements_once_authenticated() {
+        let (addr, sender) = agent("secret").await;
+        let mut connection = connect(&addr, "secret").await.unwrap();
+        sender.send("{\"timestamp\": 1.0}".to_owned()).unwrap();
+        let mut line = String::new();
+        read_line(&mut connection, &mut line).await.unwrap();
+        assert_eq!(line, "{\"timestamp\": 1.0}\n");
+    }
+
+    #[tokio::test]
+    async fn skips_invalid_advertisements() {
+        let (addr, sender) = agent("secret").await;
+        let mut advertisements = remote(&addr, Some("secret")).await.unwrap();
+        let advertisement = |manufacturer_data: &str| serde_json::json!({
+            "timestamp": 1.0,
+            "peripheral_id": crate::simulate::peripheral_id(0),
+            "manufacturer_data": manufacturer_data,
+        }).to_string();
+        // an unknown CO2 status, then a valid advertisement
+        sender.send(advertisement("20 13 04 01 00 0c 0f 01 64 02 c9 01 8b 27 29 57 04 2c 01 2a 00")).unwrap();
+        sender.send("not json".to_owned()).unwrap();
+        sender.send(advertisement("20 13 04 01 00 0c 0f 01 64 02 c9 01 8b 27 29 57 01 2c 01 2a 00")).unwrap();
+
+        let adv = advertisements.next().await.unwrap();
+        assert_eq!(adv.current_reading.unwrap().status, aranet::DisplayStatus::Green);
+    }
+
+    #[tokio::test]
+    async fn rejects_the_wrong_token() {
+        let (addr, _sender) = agent("secret").await;
+        let e = connect(&addr, "guess").await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn rejects_an_agent_without_the_token() {
+        let hello = r#"{"agent": "aranet", "version": "0.1.1", "nonce": "00112233445566778899aabbccddeeff"}"#;
+        let reply = format!(r#"{{"ok": true, "auth": "{}"}}"#, "00".repeat(32));
+        let addr = impostor(vec![hello.to_owned(), reply]).await;
+        let e = connect(&addr, "secret").await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn rejects_overlong_lines() {
+        let addr = impostor(vec!["x".repeat(MAX_LINE as usize + 1)]).await;
+        let e = connect(&addr, "secret").await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(e.to_string().contains("longer than"), "{}", e);
+    }
+}
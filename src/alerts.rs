@@ -0,0 +1,246 @@
+//! Threshold alerts with hysteresis, evaluated over successive readings
+//!
+//! An alert fires once a metric has been past its threshold for the rule's minimum duration, and resolves
+//! once the metric comes back past the threshold by more than the hysteresis band. This avoids a flood of
+//! notifications when a value hovers around the threshold.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use aranet::DiscoveredAranet;
+use serde::{Deserialize, Serialize};
+
+use crate::metric::Metric;
+
+/// An alert rule, as configured in an `[[alerts]]` table of the config file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlertRule {
+    /// Name of the alert, used in notifications. Defaults to the condition, such as `co2>1000`.
+    pub name: Option<String>,
+    #[serde(deserialize_with = "crate::config::from_str")]
+    pub metric: Metric,
+    /// Fire when the value rises above this threshold
+    pub above: Option<f64>,
+    /// Fire when the value falls below this threshold
+    pub below: Option<f64>,
+    /// How far the value must come back past the threshold before the alert resolves
+    #[serde(default)]
+    pub hysteresis: f64,
+    /// How long the threshold must stay crossed before the alert fires
+    #[serde(default, rename = "for", deserialize_with = "crate::config::duration")]
+    pub min_duration: Duration,
+    /// Minimum time between two firings of this alert for the same device
+    #[serde(default, deserialize_with = "crate::config::duration")]
+    pub cooldown: Duration,
+}
+
+impl AlertRule {
+    pub fn validate(&self) -> Result<(), String> {
+        match (self.above, self.below) {
+            (Some(_), Some(_)) | (None, None) => Err(format!("alert {:?} must have exactly one of 'above' or 'below'", self.name())),
+            _ if self.hysteresis < 0.0 => Err(format!("alert {:?} has a negative hysteresis", self.name())),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match (&self.name, self.above, self.below) {
+            (Some(name), _, _) => name.clone(),
+            (None, Some(above), _) => format!("{}>{}", self.metric, above),
+            (None, _, Some(below)) => format!("{}<{}", self.metric, below),
+            (None, None, None) => self.metric.to_string(),
+        }
+    }
+
+    fn threshold(&self) -> f64 {
+        self.above.or(self.below).unwrap_or_default()
+    }
+
+    /// Whether the value is past the threshold
+    fn triggered(&self, value: f64) -> bool {
+        match (self.above, self.below) {
+            (Some(above), _) => value > above,
+            (_, Some(below)) => value < below,
+            _ => false,
+        }
+    }
+
+    /// Whether the value is back past the threshold, by more than the hysteresis band
+    fn cleared(&self, value: f64) -> bool {
+        match (self.above, self.below) {
+            (Some(above), _) => value <= above - self.hysteresis,
+            (_, Some(below)) => value >= below + self.hysteresis,
+            _ => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transition {
+    Firing,
+    Resolved,
+}
+
+impl fmt::Display for Transition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Transition::Firing => "firing",
+            Transition::Resolved => "resolved",
+        })
+    }
+}
+
+/// An alert changing state for a device
+#[derive(Debug, Clone, Serialize)]
+pub struct AlertEvent {
+    pub name: String,
+    pub device: String,
+    pub transition: Transition,
+    pub metric: Metric,
+    pub value: f64,
+    pub threshold: f64,
+}
+
+impl fmt::Display for AlertEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Alert {} {} for {}: {} is {:.*} (threshold {})",
+            self.name, self.transition, self.device, self.metric, self.metric.precision(), self.value, self.threshold)
+    }
+}
+
+#[derive(Debug, Default)]
+struct AlertState {
+    active: bool,
+    /// When the threshold was first seen crossed, while not yet active
+    pending_since: Option<Instant>,
+    last_fired: Option<Instant>,
+}
+
+/// Tracks the state of each alert rule, for each device
+pub struct AlertEngine {
+    rules: Vec<AlertRule>,
+    states: HashMap<(usize, String), AlertState>,
+}
+
+impl AlertEngine {
+    pub fn new(rules: Vec<AlertRule>) -> AlertEngine {
+        AlertEngine { rules, states: HashMap::new() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Updates the alert states with a new reading, returning any alerts that fired or resolved
+    pub fn evaluate(&mut self, adv: &DiscoveredAranet, now: Instant) -> Vec<AlertEvent> {
+        let Some(reading) = &adv.current_reading else { return Vec::new() };
+        let id = adv.peripheral_id.to_string();
+        let device = crate::alias::display(adv);
+        let mut events = Vec::new();
+
+        for (idx, rule) in self.rules.iter().enumerate() {
+            let Some(value) = rule.metric.value(reading) else { continue };
+            let state = self.states.entry((idx, id.clone())).or_default();
+            let event = |transition| AlertEvent {
+                name: rule.name(),
+                device: device.clone(),
+                transition,
+                metric: rule.metric,
+                value,
+                threshold: rule.threshold(),
+            };
+
+            if state.active {
+                if rule.cleared(value) {
+                    state.active = false;
+                    events.push(event(Transition::Resolved));
+                }
+            } else if rule.triggered(value) {
+                let since = *state.pending_since.get_or_insert(now);
+                let cooled_down = state.last_fired
+                    .map(|t| now.duration_since(t) >= rule.cooldown)
+                    .unwrap_or(true);
+                if now.duration_since(since) >= rule.min_duration && cooled_down {
+                    state.active = true;
+                    state.pending_since = None;
+                    state.last_fired = Some(now);
+                    events.push(event(Transition::Firing));
+                }
+            } else {
+                state.pending_since = None;
+            }
+        }
+
+        events
+    }
+}
+
+#[cfg(all(test, feature = "serde_json"))]
+mod tests {
+    use super::*;
+
+    fn reading(co2: u16) -> DiscoveredAranet {
+        let mut data = vec![0x20, 0x13, 0x04, 0x01, 0x00, 0x0c, 0x0f, 0x01];
+        data.extend(co2.to_le_bytes());
+        data.extend([0xc9, 0x01, 0x8b, 0x27, 0x29, 0x57, 0x01, 0x2c, 0x01, 0x2a, 0x00]);
+        DiscoveredAranet::parse(None, crate::simulate::peripheral_id(0), &data).unwrap()
+    }
+
+    fn rule(hysteresis: f64, min_duration: u64, cooldown: u64) -> AlertRule {
+        AlertRule {
+            name: None,
+            metric: Metric::Co2,
+            above: Some(1000.0),
+            below: None,
+            hysteresis,
+            min_duration: Duration::from_secs(min_duration),
+            cooldown: Duration::from_secs(cooldown),
+        }
+    }
+
+    /// Evaluates readings of `co2` ppm taken `at` seconds in, returning the transitions
+    fn evaluate(engine: &mut AlertEngine, readings: &[(u64, u16)]) -> Vec<Option<Transition>> {
+        let start = Instant::now();
+        readings.iter()
+            .map(|&(at, co2)| {
+                let events = engine.evaluate(&reading(co2), start + Duration::from_secs(at));
+                assert!(events.len() <= 1, "{:?}", events);
+                events.first().map(|event| event.transition)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn hysteresis() {
+        let mut engine = AlertEngine::new(vec![rule(100.0, 0, 0)]);
+        let transitions = evaluate(&mut engine, &[(0, 999), (60, 1001), (120, 950), (180, 1050), (240, 900), (300, 950)]);
+        // staying within the band neither resolves nor fires it again
+        assert_eq!(transitions, [None, Some(Transition::Firing), None, None, Some(Transition::Resolved), None]);
+    }
+
+    #[test]
+    fn min_duration() {
+        let mut engine = AlertEngine::new(vec![rule(0.0, 60, 0)]);
+        let transitions = evaluate(&mut engine, &[(0, 1100), (30, 1100), (40, 900), (50, 1100), (100, 1100), (110, 1100)]);
+        // dipping below the threshold restarts the minimum duration
+        assert_eq!(transitions, [None, None, None, None, None, Some(Transition::Firing)]);
+    }
+
+    #[test]
+    fn cooldown() {
+        let mut engine = AlertEngine::new(vec![rule(0.0, 0, 600)]);
+        let transitions = evaluate(&mut engine, &[(0, 1100), (60, 900), (120, 1100), (540, 1100), (600, 1100), (660, 900)]);
+        assert_eq!(transitions, [
+            Some(Transition::Firing),
+            Some(Transition::Resolved),
+            // within the cooldown of the first firing
+            None,
+            None,
+            Some(Transition::Firing),
+            Some(Transition::Resolved),
+        ]);
+    }
+}
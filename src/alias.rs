@@ -0,0 +1,102 @@
+//! Human names for devices, such as "Bedroom", as configured in the `[aliases]` section of the config file by address
+//! or serial number.
+
+// the outputs using these are optional features
+#![allow(dead_code)]
+
+use std::sync::OnceLock;
+
+use aranet::DiscoveredAranet;
+use btleplug::api::BDAddr;
+#[cfg(feature = "serde_json")]
+use btleplug::platform::PeripheralId;
+
+/// A device, as configured by its address or its serial number
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Device {
+    Address(BDAddr),
+    /// Matched against the serial number --registry read from the device, as macOS doesn't expose addresses
+    Serial(String),
+}
+
+impl Device {
+    /// Parses an address, or else a serial number of letters and digits
+    pub fn parse(s: &str) -> Result<Device, String> {
+        if let Ok(addr) = s.parse() {
+            return Ok(Device::Address(addr));
+        }
+        match ! s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric()) {
+            true => Ok(Device::Serial(s.to_owned())),
+            false => Err(format!("{:?} is neither a device address nor a serial number", s)),
+        }
+    }
+
+    /// Whether this device sent the advertisement
+    pub fn matches(&self, adv: &DiscoveredAranet) -> bool {
+        match self {
+            Device::Address(addr) => adv.is_device(*addr),
+            #[cfg(feature = "serde_json")]
+            Device::Serial(serial) => crate::registry::serial(adv).is_some_and(|s| s.eq_ignore_ascii_case(serial)),
+            #[cfg(not(feature = "serde_json"))]
+            Device::Serial(_) => false,
+        }
+    }
+
+    /// Whether this device has the peripheral ID, such as of a reading logged to an --output file
+    #[cfg(feature = "serde_json")]
+    pub fn matches_id(&self, id: &PeripheralId) -> bool {
+        match self {
+            // as DiscoveredAranet::is_device
+            Device::Address(addr) => {
+                let id = id.to_string().replace('_', ":").to_ascii_uppercase();
+                id.ends_with(&addr.to_string().to_ascii_uppercase())
+            },
+            Device::Serial(serial) => crate::registry::serial_of(id).is_some_and(|s| s.eq_ignore_ascii_case(serial)),
+        }
+    }
+}
+
+static ALIASES: OnceLock<Vec<(Device, String)>> = OnceLock::new();
+
+/// Sets the aliases for the rest of the process. Only the first call has any effect.
+pub fn init(aliases: Vec<(Device, String)>) {
+    let _ = ALIASES.set(aliases);
+}
+
+/// Whether any alias is of a serial number, which can only be matched with --registry
+pub fn any_serials() -> bool {
+    ALIASES.get().is_some_and(|aliases| aliases.iter().any(|(device, _)| matches!(device, Device::Serial(_))))
+}
+
+/// The alias of the device that sent this advertisement, if it has one
+pub fn name(adv: &DiscoveredAranet) -> Option<&'static str> {
+    ALIASES.get()?.iter()
+        .find(|(device, _)| device.matches(adv))
+        .map(|(_, name)| name.as_str())
+}
+
+/// The alias of a device address, if it has one
+pub fn name_of(addr: BDAddr) -> Option<&'static str> {
+    ALIASES.get()?.iter()
+        .find(|(device, _)| *device == Device::Address(addr))
+        .map(|(_, name)| name.as_str())
+}
+
+/// Parses a device given on the command line, as an address, an alias (ignoring case) or a serial number
+pub fn resolve(device: &str) -> Result<Device, String> {
+    if let Ok(addr) = device.parse() {
+        return Ok(Device::Address(addr));
+    }
+    let alias = ALIASES.get()
+        .and_then(|aliases| aliases.iter().find(|(_, name)| name.eq_ignore_ascii_case(device)));
+    match alias {
+        Some((device, _)) => Ok(device.clone()),
+        None => Device::parse(device)
+            .map_err(|_| format!("{:?} is neither a device address, a configured alias nor a serial number", device)),
+    }
+}
+
+/// The alias of the device that sent this advertisement, or its peripheral ID if it has none
+pub fn display(adv: &DiscoveredAranet) -> String {
+    name(adv).map(str::to_owned).unwrap_or_else(|| adv.peripheral_id.to_string())
+}
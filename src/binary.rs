@@ -0,0 +1,140 @@
+//! MessagePack and CBOR encodings of the JSON output, for --format msgpack and cbor.
+//!
+//! Values are encoded from the same structure as the JSON output. Each is written as one record, prefixed by its
+//! length in bytes as a 32-bit big-endian integer so a reader knows where it ends. Floats that fit in single precision
+//! without loss (such as every reading value) are encoded as such, to keep records small.
+
+use std::io::Write;
+
+use serde_json::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    MessagePack,
+    Cbor,
+}
+
+/// Encodes the value, then writes it as a length-prefixed record and flushes. Exits if stdout has been closed.
+pub fn print<T: serde::Serialize>(value: &T, encoding: Encoding) {
+    let value = serde_json::to_value(value).expect("unable to serialize output");
+    let mut record = vec![0; 4];
+    match encoding {
+        Encoding::MessagePack => msgpack(&mut record, &value),
+        Encoding::Cbor => cbor(&mut record, &value),
+    }
+    let len = u32::try_from(record.len() - 4).expect("record is larger than 4 GiB");
+    record[..4].copy_from_slice(&len.to_be_bytes());
+
+    let mut stdout = std::io::stdout().lock();
+    if let Err(e) = stdout.write_all(&record).and_then(|()| stdout.flush()) {
+        log::debug!("unable to write to stdout, exiting: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// The value as a single precision float, if it is one without loss
+fn as_f32(f: f64) -> Option<f32> {
+    let single = f as f32;
+    (single as f64 == f).then_some(single)
+}
+
+fn msgpack(out: &mut Vec<u8>, value: &Value) {
+    /// The length of an array or map: in the marker itself if it's below 16, otherwise as a 16 or 32 bit integer
+    fn collection(out: &mut Vec<u8>, len: usize, fix: u8, markers: [u8; 2]) {
+        match len {
+            len if len < 16 => out.push(fix | len as u8),
+            len if len <= u16::MAX as usize => { out.push(markers[0]); out.extend((len as u16).to_be_bytes()) },
+            len => { out.push(markers[1]); out.extend((len as u32).to_be_bytes()) },
+        }
+    }
+
+    match value {
+        Value::Null => out.push(0xc0),
+        Value::Bool(b) => out.push(if *b { 0xc3 } else { 0xc2 }),
+        Value::Number(n) => match (n.as_u64(), n.as_i64(), n.as_f64()) {
+            (Some(u), _, _) if u < 0x80 => out.push(u as u8),
+            (Some(u), _, _) if u <= u8::MAX as u64 => out.extend([0xcc, u as u8]),
+            (Some(u), _, _) if u <= u16::MAX as u64 => { out.push(0xcd); out.extend((u as u16).to_be_bytes()) },
+            (Some(u), _, _) if u <= u32::MAX as u64 => { out.push(0xce); out.extend((u as u32).to_be_bytes()) },
+            (Some(u), _, _) => { out.push(0xcf); out.extend(u.to_be_bytes()) },
+            (_, Some(i), _) if i >= -32 => out.push(i as i8 as u8),
+            (_, Some(i), _) if i >= i8::MIN as i64 => out.extend([0xd0, i as i8 as u8]),
+            (_, Some(i), _) if i >= i16::MIN as i64 => { out.push(0xd1); out.extend((i as i16).to_be_bytes()) },
+            (_, Some(i), _) if i >= i32::MIN as i64 => { out.push(0xd2); out.extend((i as i32).to_be_bytes()) },
+            (_, Some(i), _) => { out.push(0xd3); out.extend(i.to_be_bytes()) },
+            (_, _, Some(f)) => match as_f32(f) {
+                Some(single) => { out.push(0xca); out.extend(single.to_be_bytes()) },
+                None => { out.push(0xcb); out.extend(f.to_be_bytes()) },
+            },
+            (None, None, None) => unreachable!("JSON number is neither an integer nor a float"),
+        },
+        Value::String(s) => {
+            match s.len() {
+                len if len < 32 => out.push(0xa0 | len as u8),
+                len if len <= u8::MAX as usize => out.extend([0xd9, len as u8]),
+                len if len <= u16::MAX as usize => { out.push(0xda); out.extend((len as u16).to_be_bytes()) },
+                len => { out.push(0xdb); out.extend((len as u32).to_be_bytes()) },
+            }
+            out.extend(s.as_bytes());
+        },
+        Value::Array(items) => {
+            collection(out, items.len(), 0x90, [0xdc, 0xdd]);
+            for item in items {
+                msgpack(out, item);
+            }
+        },
+        Value::Object(fields) => {
+            collection(out, fields.len(), 0x80, [0xde, 0xdf]);
+            for (key, value) in fields {
+                msgpack(out, &Value::String(key.clone()));
+                msgpack(out, value);
+            }
+        },
+    }
+}
+
+fn cbor(out: &mut Vec<u8>, value: &Value) {
+    /// The initial byte(s) of a data item of the major type, with its argument
+    fn head(out: &mut Vec<u8>, major: u8, arg: u64) {
+        let major = major << 5;
+        match arg {
+            arg if arg < 24 => out.push(major | arg as u8),
+            arg if arg <= u8::MAX as u64 => out.extend([major | 24, arg as u8]),
+            arg if arg <= u16::MAX as u64 => { out.push(major | 25); out.extend((arg as u16).to_be_bytes()) },
+            arg if arg <= u32::MAX as u64 => { out.push(major | 26); out.extend((arg as u32).to_be_bytes()) },
+            arg => { out.push(major | 27); out.extend(arg.to_be_bytes()) },
+        }
+    }
+
+    match value {
+        Value::Null => out.push(0xf6),
+        Value::Bool(b) => out.push(if *b { 0xf5 } else { 0xf4 }),
+        Value::Number(n) => match (n.as_u64(), n.as_i64(), n.as_f64()) {
+            (Some(u), _, _) => head(out, 0, u),
+            (_, Some(i), _) => head(out, 1, (-1 - i) as u64),
+            (_, _, Some(f)) => match as_f32(f) {
+                Some(single) => { out.push(0xfa); out.extend(single.to_be_bytes()) },
+                None => { out.push(0xfb); out.extend(f.to_be_bytes()) },
+            },
+            (None, None, None) => unreachable!("JSON number is neither an integer nor a float"),
+        },
+        Value::String(s) => {
+            head(out, 3, s.len() as u64);
+            out.extend(s.as_bytes());
+        },
+        Value::Array(items) => {
+            head(out, 4, items.len() as u64);
+            for item in items {
+                cbor(out, item);
+            }
+        },
+        Value::Object(fields) => {
+            head(out, 5, fields.len() as u64);
+            for (key, value) in fields {
+                head(out, 3, key.len() as u64);
+                out.extend(key.as_bytes());
+                cbor(out, value);
+            }
+        },
+    }
+}
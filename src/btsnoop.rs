@@ -0,0 +1,596 @@
+//! Offline decoding of Bluetooth HCI captures, such as Android's `btsnoop_hci.log`, extracting Aranet advertisements
+//! and GATT reads and decoding them with the library's parsers. Useful when reverse-engineering new firmware versions
+//! or products, by capturing a session of the official app.
+//!
+//! Supported files are btsnoop (HCI un-encapsulated or UART datalinks) and pcap (HCI H4, with or without the
+//! direction pseudo-header). Wireshark's default of pcapng should first be saved as one of these.
+//!
+//! GATT reads are matched to characteristics using the service discovery within the capture. Android caches
+//! discovery results, so the app's storage may need to be cleared before capturing for UUIDs to be known.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::path::PathBuf;
+
+use aranet::{uuids, CurrentReadingDetailed, ManufacturerData};
+use btleplug::api::bleuuid::uuid_from_u16;
+use btleplug::api::BDAddr;
+use uuid::Uuid;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct ParseBtsnoopArgs {
+    /// The btsnoop or pcap capture to parse
+    #[arg(value_name = "FILE")]
+    file: PathBuf,
+}
+
+const BTSNOOP_MAGIC: &[u8; 8] = b"btsnoop\0";
+/// Microseconds between the btsnoop epoch (midnight, January 1st 0 AD) and the Unix epoch
+const BTSNOOP_EPOCH_OFFSET: i64 = 0x00dc_ddb3_0f2f_8000;
+const BTSNOOP_HCI_UNENCAPSULATED: u32 = 1001;
+const BTSNOOP_HCI_UART: u32 = 1002;
+
+const PCAP_BLUETOOTH_HCI_H4: u32 = 187;
+const PCAP_BLUETOOTH_HCI_H4_WITH_PHDR: u32 = 201;
+
+const H4_COMMAND: u8 = 0x01;
+const H4_ACL: u8 = 0x02;
+const H4_EVENT: u8 = 0x04;
+
+const EVENT_DISCONNECTION_COMPLETE: u8 = 0x05;
+const EVENT_LE_META: u8 = 0x3e;
+const LE_CONNECTION_COMPLETE: u8 = 0x01;
+const LE_ADVERTISING_REPORT: u8 = 0x02;
+const LE_ENHANCED_CONNECTION_COMPLETE: u8 = 0x0a;
+const LE_EXTENDED_ADVERTISING_REPORT: u8 = 0x0d;
+
+const AD_MANUFACTURER_SPECIFIC: u8 = 0xff;
+
+const L2CAP_CID_ATT: u16 = 0x0004;
+const ATT_ERROR_RESPONSE: u8 = 0x01;
+const ATT_READ_BY_TYPE_REQUEST: u8 = 0x08;
+const ATT_READ_BY_TYPE_RESPONSE: u8 = 0x09;
+const ATT_READ_REQUEST: u8 = 0x0a;
+const ATT_READ_RESPONSE: u8 = 0x0b;
+/// Attribute type of characteristic declarations, requested during characteristic discovery
+const GATT_CHARACTERISTIC: u16 = 0x2803;
+
+fn u16_le(data: &[u8], idx: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(idx..idx + 2)?.try_into().ok()?))
+}
+
+fn u32_at(data: &[u8], idx: usize, big_endian: bool) -> Option<u32> {
+    let bytes = data.get(idx..idx + 4)?.try_into().ok()?;
+    Some(if big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+}
+
+/// An address, as sent least significant byte first over HCI
+fn address(data: &[u8], idx: usize) -> Option<BDAddr> {
+    let mut addr: [u8; 6] = data.get(idx..idx + 6)?.try_into().ok()?;
+    addr.reverse();
+    Some(addr.into())
+}
+
+/// A UUID, as sent least significant byte first over ATT
+fn uuid(data: &[u8]) -> Option<Uuid> {
+    match data.len() {
+        2 => Some(uuid_from_u16(u16::from_le_bytes([data[0], data[1]]))),
+        16 => {
+            let mut bytes: [u8; 16] = data.try_into().ok()?;
+            bytes.reverse();
+            Some(Uuid::from_bytes(bytes))
+        },
+        _ => None,
+    }
+}
+
+/// An HCI packet in H4 format (prefixed with its packet type), and its timestamp in microseconds since the Unix epoch
+struct Packet {
+    timestamp: i64,
+    h4: Vec<u8>,
+}
+
+fn read_btsnoop(data: &[u8]) -> Result<Vec<Packet>, String> {
+    let datalink = u32_at(data, 12, true).ok_or("truncated btsnoop header")?;
+    if datalink != BTSNOOP_HCI_UNENCAPSULATED && datalink != BTSNOOP_HCI_UART {
+        return Err(format!("unsupported btsnoop datalink type {}", datalink));
+    }
+
+    let mut packets = Vec::new();
+    let mut idx = 16;
+    while idx < data.len() {
+        let Some(record) = data.get(idx..idx + 24) else {
+            log::warn!("capture ends with a truncated record");
+            break;
+        };
+        let included = u32_at(record, 4, true).unwrap() as usize;
+        let flags = u32_at(record, 8, true).unwrap();
+        let timestamp = i64::from_be_bytes(record[16..24].try_into().unwrap()) - BTSNOOP_EPOCH_OFFSET;
+        let Some(body) = data.get(idx + 24..idx + 24 + included) else {
+            log::warn!("capture ends with a truncated record");
+            break;
+        };
+        idx += 24 + included;
+
+        let h4 = match datalink {
+            BTSNOOP_HCI_UART => body.to_vec(),
+            // un-encapsulated packets have their type within the record flags
+            _ => {
+                let received = flags & 0x01 != 0;
+                let packet_type = match (flags & 0x02 != 0, received) {
+                    (true, true) => H4_EVENT,
+                    (true, false) => H4_COMMAND,
+                    (false, _) => H4_ACL,
+                };
+                std::iter::once(packet_type).chain(body.iter().copied()).collect()
+            },
+        };
+        packets.push(Packet { timestamp, h4 });
+    }
+    Ok(packets)
+}
+
+fn read_pcap(data: &[u8]) -> Result<Vec<Packet>, String> {
+    let (big_endian, nanoseconds) = match data.get(..4) {
+        Some([0xd4, 0xc3, 0xb2, 0xa1]) => (false, false),
+        Some([0xa1, 0xb2, 0xc3, 0xd4]) => (true, false),
+        Some([0x4d, 0x3c, 0xb2, 0xa1]) => (false, true),
+        Some([0xa1, 0xb2, 0x3c, 0x4d]) => (true, true),
+        _ => return Err("not a btsnoop or pcap capture. pcapng captures should be saved as pcap first".into()),
+    };
+    let linktype = u32_at(data, 20, big_endian).ok_or("truncated pcap header")?;
+    if linktype != PCAP_BLUETOOTH_HCI_H4 && linktype != PCAP_BLUETOOTH_HCI_H4_WITH_PHDR {
+        return Err(format!("unsupported pcap link type {}, expected Bluetooth HCI H4", linktype));
+    }
+
+    let mut packets = Vec::new();
+    let mut idx = 24;
+    while idx < data.len() {
+        let Some(record) = data.get(idx..idx + 16) else {
+            log::warn!("capture ends with a truncated record");
+            break;
+        };
+        let seconds = u32_at(record, 0, big_endian).unwrap() as i64;
+        let fraction = u32_at(record, 4, big_endian).unwrap() as i64;
+        let included = u32_at(record, 8, big_endian).unwrap() as usize;
+        let Some(body) = data.get(idx + 16..idx + 16 + included) else {
+            log::warn!("capture ends with a truncated record");
+            break;
+        };
+        idx += 16 + included;
+
+        let timestamp = seconds * 1_000_000 + if nanoseconds { fraction / 1000 } else { fraction };
+        // the pseudo-header only holds the direction, which the packet type already implies
+        let h4 = match linktype {
+            PCAP_BLUETOOTH_HCI_H4_WITH_PHDR => body.get(4..).unwrap_or_default(),
+            _ => body,
+        };
+        packets.push(Packet { timestamp, h4: h4.to_vec() });
+    }
+    Ok(packets)
+}
+
+/// A value read from a characteristic, decoded according to its UUID
+#[derive(Debug)]
+#[cfg_attr(feature = "serde_json", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde_json", serde(rename_all = "snake_case"))]
+enum Decoded {
+    Reading(CurrentReadingDetailed),
+    /// in percent
+    Battery(u8),
+    Seconds(u16),
+    Count(u16),
+    Text(String),
+}
+
+impl Decoded {
+    /// Decodes a value. Printable values of unknown characteristics are decoded as text.
+    fn new(uuid: Option<Uuid>, value: &[u8]) -> Option<Decoded> {
+        Some(match (uuid, value.len()) {
+            (Some(uuids::AR4_READ_CURRENT_READINGS_DET), 13) => {
+                Decoded::Reading(CurrentReadingDetailed::parse(value.try_into().unwrap()).ok()?)
+            },
+            (Some(uuids::BATTERY_READ), 1) => Decoded::Battery(value[0]),
+            (Some(uuids::AR4_READ_INTERVAL | uuids::AR4_READ_SECONDS_SINCE_UPDATE), 2) => Decoded::Seconds(u16_le(value, 0)?),
+            (Some(uuids::AR4_READ_TOTAL_READINGS), 2) => Decoded::Count(u16_le(value, 0)?),
+            _ => {
+                let text = String::from_utf8(value.to_vec()).ok()?;
+                if text.is_empty() || text.chars().any(|c| c.is_control()) {
+                    return None;
+                }
+                Decoded::Text(text)
+            },
+        })
+    }
+}
+
+impl fmt::Display for Decoded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Decoded::Reading(r) => write!(f, "{}", r.to_string().trim_end()),
+            Decoded::Battery(b) => write!(f, "Battery: {}%", b),
+            Decoded::Seconds(s) => write!(f, "{}s", s),
+            Decoded::Count(c) => write!(f, "{}", c),
+            Decoded::Text(t) => write!(f, "{:?}", t),
+        }
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde_json", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde_json", serde(tag = "type", rename_all = "snake_case"))]
+enum Event {
+    Advertisement {
+        /// Seconds since the Unix epoch
+        timestamp: f64,
+        address: String,
+        rssi: i8,
+        /// Hex dump of the data sent under Aranet's manufacturer ID
+        manufacturer_data: String,
+        parsed: Option<ManufacturerData>,
+        current_reading: Option<CurrentReadingDetailed>,
+    },
+    GattRead {
+        /// Seconds since the Unix epoch
+        timestamp: f64,
+        address: Option<String>,
+        handle: u16,
+        uuid: Option<String>,
+        name: Option<&'static str>,
+        /// Hex dump of the value
+        value: String,
+        decoded: Option<Decoded>,
+    },
+}
+
+#[derive(Default)]
+struct Connection {
+    address: Option<BDAddr>,
+    /// Characteristic UUIDs by value handle, from the characteristic discovery within the capture
+    characteristics: HashMap<u16, Uuid>,
+    discovering_characteristics: bool,
+    /// The handle of the outstanding read request
+    pending_read: Option<u16>,
+}
+
+#[derive(Default)]
+struct Decoder {
+    connections: HashMap<u16, Connection>,
+    events: Vec<Event>,
+}
+
+impl Decoder {
+    fn packet(&mut self, packet: &Packet) {
+        let timestamp = packet.timestamp as f64 / 1_000_000.0;
+        // malformed packets are ignored
+        let _ = match packet.h4.first() {
+            Some(&H4_EVENT) => self.event(timestamp, &packet.h4[1..]),
+            Some(&H4_ACL) => self.acl(timestamp, &packet.h4[1..]),
+            _ => None,
+        };
+    }
+
+    fn event(&mut self, timestamp: f64, data: &[u8]) -> Option<()> {
+        let params = data.get(2..)?;
+        match data[0] {
+            EVENT_DISCONNECTION_COMPLETE => {
+                self.connections.remove(&(u16_le(params, 1)? & 0x0fff));
+            },
+            EVENT_LE_META => match *params.first()? {
+                LE_CONNECTION_COMPLETE | LE_ENHANCED_CONNECTION_COMPLETE if params.get(1) == Some(&0) => {
+                    let handle = u16_le(params, 2)? & 0x0fff;
+                    // replaces any state left from a missed disconnection
+                    self.connections.insert(handle, Connection { address: address(params, 6), ..Default::default() });
+                },
+                LE_ADVERTISING_REPORT => {
+                    let mut report = params.get(2..)?;
+                    for _ in 0..*params.get(1)? {
+                        let len = *report.get(8)? as usize;
+                        let addr = address(report, 2)?;
+                        let ad = report.get(9..9 + len)?;
+                        let rssi = *report.get(9 + len)? as i8;
+                        self.advertisement(timestamp, addr, rssi, ad);
+                        report = &report[10 + len..];
+                    }
+                },
+                LE_EXTENDED_ADVERTISING_REPORT => {
+                    let mut report = params.get(2..)?;
+                    for _ in 0..*params.get(1)? {
+                        let len = *report.get(23)? as usize;
+                        let addr = address(report, 3)?;
+                        let rssi = *report.get(13)? as i8;
+                        let ad = report.get(24..24 + len)?;
+                        self.advertisement(timestamp, addr, rssi, ad);
+                        report = &report[24 + len..];
+                    }
+                },
+                _ => {},
+            },
+            _ => {},
+        }
+        Some(())
+    }
+
+    fn advertisement(&mut self, timestamp: f64, addr: BDAddr, rssi: i8, mut ad: &[u8]) {
+        // advertising data is a sequence of length-prefixed structures, each starting with their type
+        while let Some((&len, rest)) = ad.split_first() {
+            let Some(structure) = rest.get(..len as usize) else { break };
+            ad = &rest[len as usize..];
+            if structure.first() != Some(&AD_MANUFACTURER_SPECIFIC) || u16_le(structure, 1) != Some(uuids::MANUFACTURER_ID) {
+                continue;
+            }
+
+            let data = &structure[3..];
+            let parsed = data.get(..7).map(|d| ManufacturerData::parse(d.try_into().unwrap()));
+            let current_reading = data.get(8..21)
+                .and_then(|r| CurrentReadingDetailed::parse(r.try_into().unwrap()).ok());
+            self.events.push(Event::Advertisement {
+                timestamp,
+                address: addr.to_string(),
+                rssi,
+                manufacturer_data: crate::hex(data),
+                parsed,
+                current_reading,
+            });
+        }
+    }
+
+    fn acl(&mut self, timestamp: f64, data: &[u8]) -> Option<()> {
+        let header = u16_le(data, 0)?;
+        // continuation fragments are skipped, as ATT PDUs of interest fit within the first
+        if (header >> 12) & 0x03 == 0x01 || u16_le(data, 6)? != L2CAP_CID_ATT {
+            return None;
+        }
+        let conn = self.connections.entry(header & 0x0fff).or_default();
+        let att = data.get(8..)?;
+
+        match *att.first()? {
+            ATT_ERROR_RESPONSE if att.get(1) == Some(&ATT_READ_REQUEST) => conn.pending_read = None,
+            ATT_READ_BY_TYPE_REQUEST => conn.discovering_characteristics = u16_le(att, 5) == Some(GATT_CHARACTERISTIC) && att.len() == 7,
+            ATT_READ_BY_TYPE_RESPONSE if conn.discovering_characteristics => {
+                let len = *att.get(1)? as usize;
+                if len < 5 {
+                    return None;
+                }
+                for declaration in att[2..].chunks_exact(len) {
+                    if let Some(uuid) = uuid(&declaration[5..]) {
+                        conn.characteristics.insert(u16_le(declaration, 3)?, uuid);
+                    }
+                }
+            },
+            ATT_READ_REQUEST => conn.pending_read = Some(u16_le(att, 1)?),
+            ATT_READ_RESPONSE => {
+                let handle = conn.pending_read.take()?;
+                let value = &att[1..];
+                let uuid = conn.characteristics.get(&handle).copied();
+                let address = conn.address.map(|a| a.to_string());
+                self.events.push(Event::GattRead {
+                    timestamp,
+                    address,
+                    handle,
+                    uuid: uuid.map(|u| u.to_string()),
+                    name: uuid.and_then(uuids::name),
+                    value: crate::hex(value),
+                    decoded: Decoded::new(uuid, value),
+                });
+            },
+            _ => {},
+        }
+        Some(())
+    }
+}
+
+pub fn run(args: ParseBtsnoopArgs, json: bool) -> Result<(), Box<dyn Error>> {
+    let data = std::fs::read(&args.file).map_err(|e| format!("unable to read {}: {}", args.file.display(), e))?;
+    let packets = match data.starts_with(BTSNOOP_MAGIC) {
+        true => read_btsnoop(&data)?,
+        false => read_pcap(&data)?,
+    };
+    log::info!("read {} packets from {}", packets.len(), args.file.display());
+
+    let mut decoder = Decoder::default();
+    for packet in &packets {
+        decoder.packet(packet);
+    }
+    if decoder.events.is_empty() {
+        log::warn!("no Aranet advertisements or GATT reads found in {}", args.file.display());
+    }
+
+    let start = packets.first().map(|p| p.timestamp as f64 / 1_000_000.0).unwrap_or_default();
+    for event in &decoder.events {
+        if json {
+            #[cfg(feature = "serde_json")]
+            println!("{}", serde_json::to_string(event).expect("unable to serialize capture event as JSON"));
+        } else {
+            print_event(event, start);
+        }
+    }
+    Ok(())
+}
+
+fn print_event(event: &Event, start: f64) {
+    match event {
+        Event::Advertisement { timestamp, address, rssi, manufacturer_data, parsed, current_reading } => {
+            println!("[{:>12.6}] Advertisement from {} ({} dBm): {}", timestamp - start, address, rssi, manufacturer_data);
+            if let Some(parsed) = parsed {
+                println!("    Firmware: {}, Calibration: {:?}", parsed.version, parsed.calibration_state);
+            }
+            if let Some(r) = current_reading {
+                for line in r.to_string().lines() {
+                    println!("    {}", line);
+                }
+            }
+        },
+        Event::GattRead { timestamp, address, handle, uuid, name, value, decoded } => {
+            let characteristic = match (uuid, name) {
+                (Some(uuid), Some(name)) => format!("{} ({})", uuid, name),
+                (Some(uuid), None) => uuid.clone(),
+                (None, _) => format!("handle 0x{:04x}", handle),
+            };
+            println!("[{:>12.6}] Read from {}, {}: {}", timestamp - start,
+                address.as_deref().unwrap_or("unknown device"), characteristic,
+                if value.is_empty() { "<empty>" } else { value });
+            if let Some(decoded) = decoded {
+                for line in decoded.to_string().lines() {
+                    println!("    {}", line);
+                }
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: [u8; 6] = [0x66, 0x55, 0x44, 0x33, 0x22, 0x11];
+    const MANUFACTURER_DATA: [u8; 21] = [
+        0x20, 0x13, 0x04, 0x01, 0x00, 0x0c, 0x0f, 0x01,
+        0x64, 0x02, 0xc9, 0x01, 0x8b, 0x27, 0x29, 0x57, 0x01, 0x2c, 0x01, 0x2a, 0x00,
+    ];
+    /// The detailed current readings characteristic's value handle
+    const HANDLE: u16 = 0x0021;
+
+    /// An LE advertising report, with the manufacturer data sent under Aranet's ID
+    fn advertisement(manufacturer_data: &[u8]) -> Vec<u8> {
+        let mut ad = vec![manufacturer_data.len() as u8 + 3, AD_MANUFACTURER_SPECIFIC];
+        ad.extend(uuids::MANUFACTURER_ID.to_le_bytes());
+        ad.extend(manufacturer_data);
+        let mut params = vec![LE_ADVERTISING_REPORT, 1, 0x00, 0x00];
+        params.extend(ADDRESS);
+        params.push(ad.len() as u8);
+        params.extend(ad);
+        params.push(-60i8 as u8);
+        [&[H4_EVENT, EVENT_LE_META, params.len() as u8][..], &params].concat()
+    }
+
+    fn connection_complete() -> Vec<u8> {
+        let params = [&[LE_CONNECTION_COMPLETE, 0x00, 0x40, 0x00, 0x00, 0x00][..], &ADDRESS, &[0; 7]].concat();
+        [&[H4_EVENT, EVENT_LE_META, params.len() as u8][..], &params].concat()
+    }
+
+    /// An ATT PDU over the connection
+    fn att(pdu: &[u8]) -> Vec<u8> {
+        let mut acl = vec![H4_ACL, 0x40, 0x20];
+        acl.extend((pdu.len() as u16 + 4).to_le_bytes());
+        acl.extend((pdu.len() as u16).to_le_bytes());
+        acl.extend(L2CAP_CID_ATT.to_le_bytes());
+        acl.extend(pdu);
+        acl
+    }
+
+    /// A connection discovering the characteristic, then reading it
+    fn read(value: &[u8]) -> Vec<Vec<u8>> {
+        // the declaration's handle, its properties (readable), then the value's handle and UUID
+        let mut discovered = vec![ATT_READ_BY_TYPE_RESPONSE, 21];
+        discovered.extend((HANDLE - 1).to_le_bytes());
+        discovered.push(0x02);
+        discovered.extend(HANDLE.to_le_bytes());
+        discovered.extend(uuids::AR4_READ_CURRENT_READINGS_DET.as_bytes().iter().rev());
+        vec![
+            connection_complete(),
+            att(&[&[ATT_READ_BY_TYPE_REQUEST, 0x01, 0x00, 0xff, 0xff][..], &GATT_CHARACTERISTIC.to_le_bytes()].concat()),
+            att(&discovered),
+            att(&[&[ATT_READ_REQUEST][..], &HANDLE.to_le_bytes()].concat()),
+            att(&[&[ATT_READ_RESPONSE][..], value].concat()),
+        ]
+    }
+
+    /// A btsnoop capture of the packets, with the UART datalink, one second apart from the Unix epoch
+    fn btsnoop(packets: &[Vec<u8>]) -> Vec<u8> {
+        let mut capture = BTSNOOP_MAGIC.to_vec();
+        capture.extend(1u32.to_be_bytes());
+        capture.extend(BTSNOOP_HCI_UART.to_be_bytes());
+        for (idx, packet) in packets.iter().enumerate() {
+            let len = (packet.len() as u32).to_be_bytes();
+            capture.extend(len);
+            capture.extend(len);
+            capture.extend([0; 8]); // flags, and drops
+            capture.extend((BTSNOOP_EPOCH_OFFSET + idx as i64 * 1_000_000).to_be_bytes());
+            capture.extend(packet);
+        }
+        capture
+    }
+
+    fn decode(packets: &[Packet]) -> Vec<Event> {
+        let mut decoder = Decoder::default();
+        for packet in packets {
+            decoder.packet(packet);
+        }
+        decoder.events
+    }
+
+    #[test]
+    fn advertisements_and_reads() {
+        let mut packets = vec![advertisement(&MANUFACTURER_DATA)];
+        packets.extend(read(&MANUFACTURER_DATA[8..]));
+        let packets = read_btsnoop(&btsnoop(&packets)).unwrap();
+        assert_eq!(packets.len(), 6);
+        assert_eq!(packets[1].timestamp, 1_000_000);
+
+        let events = decode(&packets);
+        assert_eq!(events.len(), 2, "{:?}", events);
+        let Event::Advertisement { timestamp, address, rssi, parsed, current_reading, .. } = &events[0] else { panic!() };
+        assert_eq!((*timestamp, address.as_str(), *rssi), (0.0, "11:22:33:44:55:66", -60));
+        assert_eq!(parsed.unwrap().version, aranet::Version::new(1, 4, 19));
+        assert_eq!(current_reading.unwrap().co2_ppm, Some(612));
+
+        let Event::GattRead { address, handle, name, decoded, .. } = &events[1] else { panic!() };
+        assert_eq!((address.as_deref(), *handle, *name), (Some("11:22:33:44:55:66"), HANDLE, Some("AR4_READ_CURRENT_READINGS_DET")));
+        assert!(matches!(decoded, Some(Decoded::Reading(r)) if r.age == 42));
+    }
+
+    #[test]
+    fn unknown_status() {
+        let mut data = MANUFACTURER_DATA;
+        data[16] = 4;
+        let mut packets = vec![advertisement(&data)];
+        packets.extend(read(&data[8..]));
+        let events = decode(&read_btsnoop(&btsnoop(&packets)).unwrap());
+
+        // the rest of the advertisement, and the raw value, are still shown
+        assert!(matches!(&events[0], Event::Advertisement { parsed: Some(_), current_reading: None, .. }));
+        assert!(matches!(&events[1], Event::GattRead { decoded: None, .. }));
+    }
+
+    #[test]
+    fn pcap() {
+        let packet = advertisement(&MANUFACTURER_DATA);
+        let mut capture = vec![0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0];
+        capture.extend([0; 12]);
+        capture.extend(PCAP_BLUETOOTH_HCI_H4_WITH_PHDR.to_le_bytes());
+        for seconds in [10u32, 12] {
+            capture.extend(seconds.to_le_bytes());
+            capture.extend(500u32.to_le_bytes());
+            let len = (packet.len() as u32 + 4).to_le_bytes();
+            capture.extend(len);
+            capture.extend(len);
+            capture.extend(1u32.to_be_bytes()); // received
+            capture.extend(&packet);
+        }
+
+        let packets = read_pcap(&capture).unwrap();
+        assert_eq!(packets.iter().map(|p| p.timestamp).collect::<Vec<_>>(), [10_000_500, 12_000_500]);
+        assert_eq!(decode(&packets).len(), 2);
+    }
+
+    #[test]
+    fn malformed_captures() {
+        let mut packets = vec![advertisement(&MANUFACTURER_DATA)];
+        packets.extend(read(&MANUFACTURER_DATA[8..]));
+        let capture = btsnoop(&packets);
+        // records cut short end the capture, and packets cut short are skipped
+        for len in 16..capture.len() {
+            let _ = decode(&read_btsnoop(&capture[..len]).unwrap());
+        }
+        let mut truncated = packets.clone();
+        for packet in &mut truncated {
+            packet.truncate(packet.len() / 2);
+        }
+        assert!(decode(&read_btsnoop(&btsnoop(&truncated)).unwrap()).is_empty());
+
+        assert!(read_btsnoop(&capture[..14]).is_err());
+        assert!(read_pcap(b"not a capture").is_err());
+    }
+}
@@ -0,0 +1,178 @@
+//! An on-disk cache of the latest reading from each device, for --cache.
+//!
+//! Written on every reading, and consulted by one-shot runs (such as CGI requests) that can't get a fresh reading
+//! within their timeout, so they can respond with the last known reading rather than an error.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use aranet::{ActiveReading, CurrentReadingDetailed, DiscoveredAranet};
+
+use crate::filter::DeviceFilter;
+use crate::record::{parse_hex, RecordedAdvertisement};
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct CacheEntry {
+    #[serde(flatten)]
+    advertisement: RecordedAdvertisement,
+    /// Hex dump of the reading, if it was read over GATT rather than advertised
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    current_readings_details: Option<String>,
+}
+
+type Entries = BTreeMap<String, CacheEntry>;
+
+/// Parses a reading cached from GATT, as hex
+fn parse_gatt(hex: &str) -> Result<CurrentReadingDetailed, String> {
+    let raw = parse_hex(hex)
+        .and_then(|raw| <[u8; 13]>::try_from(raw).ok())
+        .ok_or("reading is not 13 bytes of hex")?;
+    CurrentReadingDetailed::parse(raw).map_err(|e| e.to_string())
+}
+
+#[derive(Debug, Clone)]
+pub struct ReadingCache {
+    path: PathBuf,
+    ttl: Duration,
+}
+
+impl ReadingCache {
+    pub fn new(path: PathBuf, ttl: Duration) -> ReadingCache {
+        ReadingCache { path, ttl }
+    }
+
+    fn read(&self) -> io::Result<Entries> {
+        match fs::read(&self.path) {
+            Ok(data) => serde_json::from_slice(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Entries::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Saves the advertisement as the latest from its device. Advertisements without a reading are skipped.
+    pub fn store(&self, adv: &DiscoveredAranet, gatt: Option<&ActiveReading>) -> io::Result<()> {
+        if adv.current_reading.is_none() {
+            return Ok(());
+        }
+
+        // an unreadable cache is replaced, rather than stopping it from being updated
+        let mut entries = self.read().unwrap_or_else(|e| {
+            log::warn!("unable to read --cache {}, replacing it: {}", self.path.display(), e);
+            Entries::new()
+        });
+        entries.insert(adv.peripheral_id.to_string(), CacheEntry {
+            advertisement: RecordedAdvertisement::new(adv),
+            current_readings_details: gatt.map(|g| crate::hex(&g.raw_reading)),
+        });
+
+        // written to a temporary file first, so concurrent runs never see a partial cache
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        fs::write(&tmp, serde_json::to_vec(&entries).expect("unable to serialize reading cache"))?;
+        fs::rename(&tmp, &self.path)
+    }
+
+    /// The most recent cached reading from a device matching the filter, if within the TTL, and how many seconds
+    /// old it is. The reading's measurement age includes the time it spent in the cache.
+    pub fn load(&self, filter: &DeviceFilter) -> Option<(DiscoveredAranet, u64)> {
+        let entries = match self.read() {
+            Ok(entries) => entries,
+            Err(e) => {
+                log::warn!("unable to read --cache {}: {}", self.path.display(), e);
+                return None;
+            },
+        };
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0);
+
+        let mut latest: Option<(DiscoveredAranet, u64)> = None;
+        for entry in entries.into_values() {
+            let cached_for = (now - entry.advertisement.timestamp).max(0.0);
+            if cached_for > self.ttl.as_secs_f64() {
+                continue;
+            }
+            // a corrupt entry is a miss, rather than stopping the rest from being used
+            let gatt = match entry.current_readings_details.as_deref().map(parse_gatt).transpose() {
+                Ok(gatt) => gatt,
+                Err(e) => {
+                    log::warn!("ignoring a corrupt reading in --cache {}: {}", self.path.display(), e);
+                    continue;
+                },
+            };
+            let mut adv = match entry.advertisement.parse() {
+                Ok(adv) => adv,
+                Err(e) => {
+                    log::warn!("ignoring a corrupt advertisement in --cache {}: {}", self.path.display(), e);
+                    continue;
+                },
+            };
+            if ! filter.matches(&adv) {
+                continue;
+            }
+
+            let cached_for = cached_for as u64;
+            if let Some(reading) = gatt {
+                adv.current_reading = Some(reading);
+            }
+            // the age is now relative to being read from the cache
+            if let Some(reading) = &mut adv.current_reading {
+                reading.age = reading.age.saturating_add(cached_for.min(u16::MAX as u64) as u16);
+            }
+            adv.received = SystemTime::now();
+            if latest.as_ref().is_none_or(|(_, age)| cached_for < *age) {
+                latest = Some((adv, cached_for));
+            }
+        }
+        latest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADVERTISEMENT: &str = "20 13 04 01 00 0c 0f 01 64 02 c9 01 8b 27 29 57 01 2c 01 2a 00";
+
+    /// A cache holding one entry, heard just now, with the reading read over GATT if given
+    fn cache(name: &str, manufacturer_data: &str, gatt: Option<&str>) -> ReadingCache {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs_f64();
+        let mut entry = serde_json::json!({
+            "timestamp": now,
+            "peripheral_id": crate::simulate::peripheral_id(0),
+            "manufacturer_data": manufacturer_data,
+        });
+        if let Some(gatt) = gatt {
+            entry["current_readings_details"] = gatt.into();
+        }
+        let path = std::env::temp_dir().join(format!("aranet-cache-test-{}-{}.json", name, std::process::id()));
+        fs::write(&path, serde_json::json!({ "device": entry }).to_string()).unwrap();
+        ReadingCache::new(path, Duration::from_secs(60))
+    }
+
+    #[test]
+    fn loads_readings() {
+        let cache = cache("valid", ADVERTISEMENT, Some("66 08 e0 01 03 27 37 0c 03 3c 00 05 00"));
+        let (adv, _) = cache.load(&DeviceFilter::default()).unwrap();
+        // the GATT reading is used over the advertised one
+        assert_eq!(adv.current_reading.unwrap().co2_ppm, Some(2150));
+        fs::remove_file(&cache.path).unwrap();
+    }
+
+    #[test]
+    fn corrupt_entries_are_misses() {
+        let unknown_status = ADVERTISEMENT.replace("57 01 2c", "57 00 2c");
+        let corrupt = [
+            ("advertisement", unknown_status.as_str(), None),
+            ("short", "20 13 04", None),
+            ("gatt", ADVERTISEMENT, Some("66 08 e0 01 03 27 37 0c 04 3c 00 05 00")),
+            ("gatt-length", ADVERTISEMENT, Some("66 08")),
+        ];
+        for (name, manufacturer_data, gatt) in corrupt {
+            let cache = cache(name, manufacturer_data, gatt);
+            assert!(cache.load(&DeviceFilter::default()).is_none(), "{}", name);
+            fs::remove_file(&cache.path).unwrap();
+        }
+    }
+}
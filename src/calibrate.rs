@@ -0,0 +1,256 @@
+//! Walks through the fresh air CO2 calibration of a device, for `aranet calibrate wizard`.
+//!
+//! The device calibrates its CO2 sensor against outdoor air, which it assumes to be about 420 ppm. The wizard checks
+//! the device is ready, explains where to put it, then follows the calibration through the state the device
+//! advertises, and checks the first reading afterwards looks like fresh air.
+//!
+//! Calibration is started from the Aranet Home app, as the command that starts it isn't documented. The wizard only
+//! listens, so never changes anything on the device itself.
+
+use std::error::Error;
+use std::io::{IsTerminal, Write};
+use std::time::Duration;
+
+use aranet::{CalibrationState, DiscoveredAranet};
+use btleplug::platform::Manager;
+use futures::{Stream, StreamExt};
+use tokio::time::Instant;
+
+use crate::error::{CliError, ErrorKind};
+use crate::filter::DeviceFilter;
+
+/// How long to wait for the device to advertise, if no --timeout is given
+const DEFAULT_TIMEOUT: f64 = 30.0;
+
+/// The CO2 readings, in ppm, expected outdoors once calibrated. Fresh air is about 420 ppm, and the sensor is
+/// accurate to ±30 ppm ±3%.
+const FRESH_AIR: std::ops::RangeInclusive<u16> = 350..=500;
+
+/// How often to show the remaining time, when stdout isn't a terminal
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct CalibrateArgs {
+    #[command(subcommand)]
+    command: CalibrateCommand,
+}
+
+#[derive(clap::Subcommand, Debug, Clone)]
+enum CalibrateCommand {
+    /// Walk through the fresh air calibration of a device (select it with --device): check it's ready, place it
+    /// outdoors, start the calibration in the Aranet Home app, follow its progress, and check the reading afterwards
+    Wizard {
+        /// Don't wait for Enter before each step, such as when already outdoors
+        #[arg(long)]
+        yes: bool,
+        /// Give up if calibration hasn't started and finished within this long: seconds, or a duration such as `30m`
+        #[arg(long, value_name = "DURATION", default_value = "30m", value_parser = crate::parse_duration)]
+        max_wait: Duration,
+    },
+}
+
+/// Prints the message, then waits for Enter unless --yes was given
+async fn confirm(message: &str, yes: bool) -> Result<(), Box<dyn Error>> {
+    println!("{}", message);
+    if yes {
+        return Ok(());
+    }
+    if ! std::io::stdin().is_terminal() {
+        return Err("the calibration wizard needs a terminal to confirm each step, or --yes".into());
+    }
+    print!("Press Enter to continue, or Ctrl-C to stop... ");
+    std::io::stdout().flush()?;
+    tokio::task::spawn_blocking(|| std::io::stdin().read_line(&mut String::new())).await??;
+    Ok(())
+}
+
+/// Shows how long is left before giving up: on one line redrawn in place in a terminal, otherwise once a minute
+struct Progress {
+    terminal: bool,
+    /// When the line was last shown
+    last: Option<Instant>,
+}
+
+impl Progress {
+    fn new() -> Progress {
+        Progress { terminal: std::io::stdout().is_terminal(), last: None }
+    }
+
+    fn show(&mut self, status: &str, remaining: Duration) {
+        let secs = remaining.as_secs();
+        let line = format!("{}, giving up in {}m{:02}s", status, secs / 60, secs % 60);
+        if self.terminal {
+            print!("\r\x1b[K{}", line);
+            let _ = std::io::stdout().flush();
+            self.last = Some(Instant::now());
+        } else if self.last.is_none_or(|last| last.elapsed() >= PROGRESS_INTERVAL) {
+            println!("{}", line);
+            self.last = Some(Instant::now());
+        }
+    }
+}
+
+impl Drop for Progress {
+    /// Ends the redrawn line, so the next message (or error) starts on its own
+    fn drop(&mut self) {
+        if self.terminal && self.last.is_some() {
+            println!();
+        }
+    }
+}
+
+/// Runs the wizard on the first device matching the filter, waiting at most `timeout` seconds for it to advertise
+pub async fn run(args: CalibrateArgs, filter: DeviceFilter, timeout: Option<f64>) -> Result<(), Box<dyn Error>> {
+    let CalibrateCommand::Wizard { yes, max_wait } = args.command;
+
+    let manager = Manager::new().await?;
+    let discovered = crate::lock::discover(&manager).await?;
+    let mut device = discovered.filter(|adv| futures::future::ready(filter.matches(adv)));
+
+    let timeout = timeout.unwrap_or(DEFAULT_TIMEOUT);
+    let adv = match tokio::time::timeout(Duration::from_secs_f64(timeout), device.next()).await {
+        Ok(Some(adv)) => adv,
+        Ok(None) => {
+            let msg = "Unable to discover devices. No Bluetooth adapters present.";
+            return Err(CliError::new(ErrorKind::NoAdapter, msg).into());
+        },
+        Err(_) => {
+            let msg = format!("No advertisement received within {}s.", timeout);
+            return Err(CliError::new(ErrorKind::NoDevice, msg).into());
+        },
+    };
+    let name = crate::alias::display(&adv);
+
+    println!("Step 1 of 4: checking {}", name);
+    check(&adv)?;
+
+    confirm(
+        "\nStep 2 of 4: place the device outdoors, in the shade and out of the rain, away from people, vehicles, \
+        chimneys, and open windows. Let it settle for a few minutes before starting.",
+        yes,
+    ).await?;
+
+    println!(
+        "\nStep 3 of 4: start the CO2 calibration from {}'s settings in the Aranet Home app, keeping the phone a few \
+        meters away from the device. Waiting for it to start...",
+        name,
+    );
+    let deadline = Instant::now() + max_wait;
+    let mut progress = Progress::new();
+    let mut state = adv.manufacturer_data.calibration_state;
+    while state != CalibrationState::InProgress && state != CalibrationState::EndRequest {
+        progress.show("Waiting for calibration to start", deadline.saturating_duration_since(Instant::now()));
+        state = advertisement(&mut device, deadline).await?
+            .map_or(CalibrationState::NotActive, |adv| adv.manufacturer_data.calibration_state);
+    }
+    drop(progress);
+
+    println!("\nStep 4 of 4: calibrating. Leave the device where it is until it's done.");
+    let started = Instant::now();
+    let mut progress = Progress::new();
+    let last = loop {
+        let elapsed = started.elapsed().as_secs();
+        let status = format!("Calibrating for {}m{:02}s", elapsed / 60, elapsed % 60);
+        progress.show(&status, deadline.saturating_duration_since(Instant::now()));
+        let Some(adv) = advertisement(&mut device, deadline).await? else { continue };
+        match adv.manufacturer_data.calibration_state {
+            CalibrationState::NotActive => break adv,
+            CalibrationState::Error => {
+                return Err(CliError::new(ErrorKind::Other, format!(
+                    "{} reported that calibration failed. Check it's in fresh air, and try again", name,
+                )).into());
+            },
+            _ => {},
+        }
+    };
+    drop(progress);
+    println!("Calibration finished after {}s", started.elapsed().as_secs());
+
+    verify(&last, &mut device).await
+}
+
+/// Checks the device is ready to calibrate, printing what was found
+fn check(adv: &DiscoveredAranet) -> Result<(), Box<dyn Error>> {
+    let data = &adv.manufacturer_data;
+    let latest = crate::firmware::latest_known();
+    match data.version < latest {
+        true => println!(
+            "  Firmware {} is older than the latest known release, {}. Consider updating it through the Aranet Home \
+            app first", data.version, latest,
+        ),
+        false => println!("  Firmware {} is up to date", data.version),
+    }
+    if data.dfu_active {
+        let msg = "The device is being updated. Try again once the update has finished";
+        return Err(CliError::new(ErrorKind::Other, msg).into());
+    }
+    match data.calibration_state {
+        CalibrationState::InProgress | CalibrationState::EndRequest => {
+            println!("  Already calibrating, following its progress")
+        },
+        CalibrationState::Error => println!("  The last calibration failed, so it's worth calibrating again"),
+        CalibrationState::NotActive => println!("  Ready to calibrate"),
+    }
+    if adv.current_reading.is_none() {
+        println!(
+            "  Its advertisements don't include readings, so the reading after calibrating will be read by connecting \
+            to it. Enable Smart Home integrations in the Aranet Home app to avoid this",
+        );
+    }
+    Ok(())
+}
+
+/// The next advertisement from the device, or `None` if none arrived for a while (to update the progress). Fails
+/// once the deadline has passed.
+async fn advertisement<S>(device: &mut S, deadline: Instant) -> Result<Option<DiscoveredAranet>, Box<dyn Error>>
+where S: Stream<Item = DiscoveredAranet> + Unpin {
+    if Instant::now() >= deadline {
+        return Err(CliError::new(ErrorKind::Timeout, "Gave up waiting for the device, after --max-wait").into());
+    }
+    let wait = deadline.min(Instant::now() + Duration::from_secs(1));
+    match tokio::time::timeout_at(wait, device.next()).await {
+        Ok(Some(adv)) => Ok(Some(adv)),
+        Ok(None) => {
+            let msg = "Stopped receiving advertisements from the Bluetooth adapter";
+            Err(CliError::new(ErrorKind::NoAdapter, msg).into())
+        },
+        Err(_) => Ok(None),
+    }
+}
+
+/// Checks the first reading measured after calibrating is within [`FRESH_AIR`]
+async fn verify<S>(last: &DiscoveredAranet, device: &mut S) -> Result<(), Box<dyn Error>>
+where S: Stream<Item = DiscoveredAranet> + Unpin {
+    println!("\nChecking the next reading, which may take until the device's next measurement...");
+    let co2 = match last.current_reading {
+        Some(first) => {
+            // the age resets when the device takes a new measurement, within an interval (allowing for a missed one)
+            let deadline = Instant::now() + Duration::from_secs(first.interval as u64 * 2 + 30);
+            let mut age = first.age;
+            loop {
+                let Some(adv) = advertisement(device, deadline).await? else { continue };
+                let Some(r) = adv.current_reading else { continue };
+                if r.age < age {
+                    break r.co2_ppm;
+                }
+                age = r.age;
+            }
+        },
+        None => {
+            let _lock = crate::lock::acquire().await?;
+            last.read_current_with(&crate::retry::policy()).await.map_err(crate::bluetooth_error)?.reading.co2_ppm
+        },
+    };
+
+    match co2 {
+        Some(ppm) if FRESH_AIR.contains(&ppm) => {
+            println!("Calibrated: reading {} ppm in fresh air", ppm);
+            Ok(())
+        },
+        Some(ppm) => Err(CliError::new(ErrorKind::Other, format!(
+            "Read {} ppm after calibrating, where fresh air should read {} to {} ppm. Check the air around the device \
+            is fresh, and calibrate again", ppm, FRESH_AIR.start(), FRESH_AIR.end(),
+        )).into()),
+        None => Err(CliError::new(ErrorKind::Other, "The device didn't report CO2 after calibrating").into()),
+    }
+}
@@ -0,0 +1,116 @@
+//! Response headers for CGI requests, including HTTP caching headers derived from the reading being output.
+//!
+//! Headers are sent just before the response body, once the reading is known. `Cache-Control` lasts until the
+//! device's next measurement, `Last-Modified` is when the measurement was taken, and the weak `ETag` covers the
+//! measured values. Conditional requests (`If-None-Match` or `If-Modified-Since`) that match are answered with
+//! `304 Not Modified`, so dashboards and reverse proxies polling the endpoint don't each wait on the radio.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime};
+
+use aranet::DiscoveredAranet;
+
+struct Response {
+    content_type: &'static str,
+    /// Distinguishes the representations of a reading (format, units, etc), for the ETag
+    variant: String,
+}
+
+static RESPONSE: OnceLock<Response> = OnceLock::new();
+static SENT: AtomicBool = AtomicBool::new(false);
+
+/// Marks this run as answering a CGI request, with the headers sent by [`headers`] before the response body
+pub fn begin(content_type: &'static str, variant: String) {
+    let _ = RESPONSE.set(Response { content_type, variant });
+}
+
+/// A weak ETag over the measured values. The measurement age is excluded, as it changes with every advertisement.
+fn etag(variant: &str, advs: &[&DiscoveredAranet]) -> String {
+    let mut hasher = DefaultHasher::new();
+    variant.hash(&mut hasher);
+    for adv in advs {
+        adv.peripheral_id.to_string().hash(&mut hasher);
+        if let Some(r) = adv.current_reading {
+            r.co2_ppm.hash(&mut hasher);
+            r.temperature_c.map(f32::to_bits).hash(&mut hasher);
+            r.pressure_hpa.map(f32::to_bits).hash(&mut hasher);
+            r.humidity.to_bits().hash(&mut hasher);
+            r.battery.to_bits().hash(&mut hasher);
+            (r.status as u8).hash(&mut hasher);
+            r.interval.hash(&mut hasher);
+        }
+    }
+    format!("W/\"{:016x}\"", hasher.finish())
+}
+
+/// Whether the request's validators match the response, so it can be answered with `304 Not Modified`
+fn not_modified(etag: &str, modified: SystemTime) -> bool {
+    // If-None-Match takes precedence when both are sent
+    if let Ok(tags) = std::env::var("HTTP_IF_NONE_MATCH") {
+        let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_owned();
+        return tags.split(',').any(|tag| tag.trim() == "*" || opaque(tag) == opaque(etag));
+    }
+    match std::env::var("HTTP_IF_MODIFIED_SINCE").map(|since| httpdate::parse_http_date(&since)) {
+        // HTTP dates have second precision, and measurement ages are rounded to seconds
+        Ok(Ok(since)) => modified <= since + Duration::from_secs(1),
+        _ => false,
+    }
+}
+
+/// Sends the response headers, if answering a CGI request and they haven't already been sent. Caching headers are
+/// derived from the readings within `advs`.
+///
+/// If the request is conditional and its validators match, a `304 Not Modified` response is sent and the process
+/// exits.
+pub fn headers(advs: &[&DiscoveredAranet]) {
+    let Some(response) = RESPONSE.get() else { return };
+    if SENT.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    let now = SystemTime::now();
+    let readings: Vec<_> = advs.iter().filter_map(|adv| adv.current_reading).collect();
+    if readings.is_empty() {
+        println!("Cache-Control: no-cache");
+    } else {
+        // fresh until the soonest next measurement
+        let max_age = readings.iter().map(|r| r.interval.saturating_sub(r.age)).min().unwrap_or(0);
+        let modified = readings.iter()
+            .map(|r| now - Duration::from_secs(r.age as u64))
+            .max()
+            .unwrap_or(now);
+        let etag = etag(&response.variant, advs);
+
+        let not_modified = not_modified(&etag, modified);
+        if not_modified {
+            println!("Status: 304 Not Modified");
+        }
+        println!("Cache-Control: max-age={}", max_age);
+        println!("Last-Modified: {}", httpdate::fmt_http_date(modified));
+        println!("ETag: {}", etag);
+        if not_modified {
+            println!();
+            std::process::exit(0);
+        }
+    }
+    println!("Content-Type: {}; charset=utf-8", response.content_type);
+    println!();
+}
+
+/// Sends the headers of an error response, with an HTTP `status` such as `504 Gateway Timeout`, if they haven't
+/// already been sent. Returns whether answering a CGI request, when the error is written to stdout as the body.
+pub fn error_headers(status: &str, content_type: &str) -> bool {
+    if RESPONSE.get().is_none() {
+        return false;
+    }
+    if ! SENT.swap(true, Ordering::SeqCst) {
+        println!("Status: {}", status);
+        println!("Cache-Control: no-cache");
+        println!("Content-Type: {}; charset=utf-8", content_type);
+        println!();
+    }
+    true
+}
@@ -0,0 +1,57 @@
+//! Shell completions and man pages, for `aranet completions` and `aranet man`.
+//!
+//! Both are generated from the same clap definitions as `--help`, so they cover every option and subcommand of the
+//! features this binary was built with.
+
+use std::error::Error;
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::CommandFactory;
+
+use crate::Args;
+
+/// The name completions and man pages are generated for, as installed
+const BIN_NAME: &str = "aranet";
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct CompletionsArgs {
+    /// The shell to generate completions for
+    #[arg(value_enum)]
+    shell: clap_complete::Shell,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct ManArgs {
+    /// Write a page for `aranet` and each subcommand (such as `aranet-daemon.1`) to this directory, rather than
+    /// printing the page for `aranet` alone
+    #[arg(long, value_name = "DIR")]
+    out_dir: Option<PathBuf>,
+}
+
+/// Prints the completion script for the shell
+pub fn completions(args: CompletionsArgs) -> Result<(), Box<dyn Error>> {
+    let mut command = Args::command();
+    // generated in full first, as clap_complete panics if writing fails (such as piped to `head`)
+    let mut script = Vec::new();
+    clap_complete::generate(args.shell, &mut command, BIN_NAME, &mut script);
+    std::io::stdout().lock().write_all(&script)?;
+    Ok(())
+}
+
+/// Prints the man page, or writes every page to --out-dir
+pub fn man(args: ManArgs) -> Result<(), Box<dyn Error>> {
+    let command = Args::command().name(BIN_NAME);
+    match &args.out_dir {
+        Some(dir) => {
+            clap_mangen::generate_to(command, dir).map_err(|e| format!("unable to write to {}: {}", dir.display(), e))?;
+            log::info!("wrote man pages to {}", dir.display());
+        },
+        None => {
+            let mut page = Vec::new();
+            clap_mangen::Man::new(command).render(&mut page)?;
+            std::io::stdout().lock().write_all(&page)?;
+        },
+    }
+    Ok(())
+}
@@ -0,0 +1,170 @@
+//! The TOML configuration file
+//!
+//! Top-level keys are the same as the long command line options (eg: `repeat = true`, `exit_on = ["co2>1200"]`),
+//! and are used as defaults for them. Options passed on the command line take precedence, except for options that
+//! may be repeated, which are combined. Sections such as `[[alerts]]` configure things that don't fit on the
+//! command line.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::{Deserialize, Deserializer};
+
+use crate::alerts::AlertRule;
+use crate::alias::Device;
+
+/// Name of the config file within the platform's config directory
+const FILE_NAME: &str = "config.toml";
+
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct Config {
+    /// Alert rules, evaluated against each reading in --repeat mode
+    #[serde(default)]
+    pub alerts: Vec<AlertRule>,
+    /// Human names for devices, keyed by address or serial number
+    #[serde(default)]
+    aliases: BTreeMap<String, String>,
+    /// Tags for devices, keyed by address or alias
+    #[serde(default)]
+    tags: BTreeMap<String, BTreeMap<String, String>>,
+    /// SMTP settings for emailing alerts
+    #[cfg(feature = "email")]
+    pub email: Option<crate::email::EmailConfig>,
+    /// Defaults for command line options, keyed by their long name
+    #[serde(flatten)]
+    options: BTreeMap<String, toml::Value>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Invalid(PathBuf, String),
+}
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "unable to read config file {}: {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "unable to parse config file {}: {}", path.display(), e),
+            ConfigError::Invalid(path, e) => write!(f, "invalid config file {}: {}", path.display(), e),
+        }
+    }
+}
+impl std::error::Error for ConfigError {}
+
+/// The default config file location: `$XDG_CONFIG_HOME/aranet/config.toml` or `~/.config/aranet/config.toml`,
+/// and `%APPDATA%\aranet\config.toml` on Windows
+pub fn default_path() -> Option<PathBuf> {
+    let dir = if cfg!(windows) {
+        PathBuf::from(std::env::var_os("APPDATA")?)
+    } else {
+        match std::env::var_os("XDG_CONFIG_HOME").filter(|d| ! d.is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+        }
+    };
+    Some(dir.join("aranet").join(FILE_NAME))
+}
+
+impl Config {
+    /// Loads the config file at `path`, or at the [`default_path`] if it exists
+    pub fn find(path: Option<&Path>) -> Result<Config, ConfigError> {
+        match (path, default_path()) {
+            (Some(path), _) => Config::load(path),
+            (None, Some(path)) if path.exists() => Config::load(&path),
+            (None, _) => Ok(Config::default()),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        let raw = std::fs::read_to_string(path)
+            .map_err(|e| ConfigError::Io(path.to_owned(), e))?;
+        let config: Config = toml::from_str(&raw)
+            .map_err(|e| ConfigError::Parse(path.to_owned(), e))?;
+        for rule in &config.alerts {
+            rule.validate().map_err(|e| ConfigError::Invalid(path.to_owned(), e))?;
+        }
+        config.args().map_err(|e| ConfigError::Invalid(path.to_owned(), e))?;
+        config.aliases().map_err(|e| ConfigError::Invalid(path.to_owned(), e))?;
+        config.tags().map_err(|e| ConfigError::Invalid(path.to_owned(), e))?;
+        log::debug!("loaded config file {}: {:?}", path.display(), config);
+        Ok(config)
+    }
+}
+
+impl Config {
+    /// Device aliases, with their addresses or serial numbers parsed
+    pub fn aliases(&self) -> Result<Vec<(Device, String)>, String> {
+        self.aliases.iter()
+            .map(|(device, name)| match Device::parse(device) {
+                Ok(device) => Ok((device, name.clone())),
+                Err(e) => Err(format!("invalid device for alias {:?}: {}", name, e)),
+            })
+            .collect()
+    }
+
+    /// Device tags, with their devices resolved to addresses or serial numbers, and keys validated
+    pub fn tags(&self) -> Result<Vec<(Device, Vec<crate::tags::Tag>)>, String> {
+        let aliases = self.aliases()?;
+        self.tags.iter()
+            .map(|(device, tags)| {
+                let addr = match aliases.iter().find(|(_, name)| name.eq_ignore_ascii_case(device)) {
+                    Some((device, _)) => device.clone(),
+                    None => Device::parse(device).map_err(|e| format!("invalid device for tags: {}", e))?,
+                };
+                for key in tags.keys() {
+                    crate::tags::validate_key(key)?;
+                }
+                Ok((addr, tags.iter().map(|(key, value)| (key.clone(), value.clone())).collect()))
+            })
+            .collect()
+    }
+
+    /// The command line arguments equivalent to the options set in the config file
+    pub fn args(&self) -> Result<Vec<String>, String> {
+        let mut args = Vec::new();
+        for (key, value) in &self.options {
+            let flag = format!("--{}", key.replace('_', "-"));
+            let values = match value {
+                toml::Value::Array(values) => values.iter().collect(),
+                value => vec![value],
+            };
+            for value in values {
+                match value {
+                    toml::Value::Boolean(true) => args.push(flag.clone()),
+                    toml::Value::Boolean(false) => {},
+                    toml::Value::String(s) => args.push(format!("{}={}", flag, s)),
+                    toml::Value::Integer(i) => args.push(format!("{}={}", flag, i)),
+                    toml::Value::Float(f) => args.push(format!("{}={}", flag, f)),
+                    _ => return Err(format!("unsupported value for option {:?}: {}", key, value)),
+                }
+            }
+        }
+        Ok(args)
+    }
+}
+
+/// Deserializes a value through its `FromStr` implementation
+pub fn from_str<'de, D: Deserializer<'de>, T>(d: D) -> Result<T, D::Error>
+where T: FromStr, T::Err: fmt::Display {
+    let s = String::deserialize(d)?;
+    s.parse().map_err(serde::de::Error::custom)
+}
+
+/// Deserializes a duration, from either a number of seconds or a human readable string such as `"5m"` or `"1h 30m"`
+pub fn duration<'de, D: Deserializer<'de>>(d: D) -> Result<Duration, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Seconds(u64),
+        Human(String),
+    }
+    match Raw::deserialize(d)? {
+        Raw::Seconds(s) => Ok(Duration::from_secs(s)),
+        Raw::Human(s) => humantime::parse_duration(&s).map_err(serde::de::Error::custom),
+    }
+}
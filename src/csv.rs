@@ -0,0 +1,46 @@
+//! CSV encoding of flat records, such as for `aranet export --to csv`.
+//!
+//! Follows RFC 4180: fields containing the delimiter, a quote, or a line break are quoted, with quotes doubled. Absent
+//! values (`null`) are empty fields. The delimiter may be other than a comma, such as a tab for TSV.
+
+use serde_json::Value;
+
+/// Quotes a field if needed
+fn field(s: &str, delimiter: char) -> String {
+    match s.contains([delimiter, '"', '\n', '\r']) {
+        true => format!("\"{}\"", s.replace('"', "\"\"")),
+        false => s.to_owned(),
+    }
+}
+
+/// Parses a --csv-delimiter: a single character, or `tab`
+pub fn parse_delimiter(s: &str) -> Result<char, String> {
+    let mut chars = s.chars();
+    match (s, chars.next(), chars.next()) {
+        ("tab" | "\\t", _, _) => Ok('\t'),
+        (_, Some('"' | '\n' | '\r'), None) => Err(format!("{:?} can't be used as a delimiter", s)),
+        (_, Some(c), None) => Ok(c),
+        _ => Err(format!("invalid delimiter {:?}, expected a single character or `tab`", s)),
+    }
+}
+
+/// The header line naming each column
+pub fn header(columns: &[&str], delimiter: char) -> String {
+    let mut line = columns.iter().map(|c| field(c, delimiter)).collect::<Vec<_>>().join(&delimiter.to_string());
+    line.push('\n');
+    line
+}
+
+/// A line of values, in the order of the header's columns
+pub fn row(values: &[Value], delimiter: char) -> String {
+    let mut line = values.iter()
+        .map(|value| match value {
+            Value::Null => String::new(),
+            Value::String(s) => field(s, delimiter),
+            value => field(&value.to_string(), delimiter),
+        })
+        .collect::<Vec<_>>()
+        .join(&delimiter.to_string());
+    line.push('\n');
+    line
+}
@@ -0,0 +1,286 @@
+//! Running long-term as a service with `aranet daemon`: detaching from the terminal, a pidfile, graceful shutdown,
+//! systemd readiness and watchdog notifications, and dumping its state on SIGUSR1.
+//!
+//! Readings are still output as with --repeat, so under systemd they end up in the journal. Detaching discards all
+//! output, so sinks such as --journald or --syslog should be used instead.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use btleplug::api::{Central, Manager as _};
+use btleplug::platform::Manager;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct DaemonArgs {
+    /// Detach from the terminal and run in the background
+    #[arg(long)]
+    pub detach: bool,
+    /// Write the daemon's process ID to this file, removing it on shutdown
+    #[arg(long, value_name = "FILE")]
+    pub pidfile: Option<PathBuf>,
+    /// Write the state dumped on SIGUSR1 (devices heard, their latest readings, sink health, and error counts) to
+    /// this file, replacing it, rather than to stderr
+    #[arg(long, value_name = "FILE")]
+    pub dump_file: Option<PathBuf>,
+    /// Write the collector's own metrics (advertisements, connections, sink errors, and devices visible) to this
+    /// file every 15 seconds, in the Prometheus text format read by node_exporter's textfile collector
+    #[arg(long, value_name = "FILE")]
+    pub metrics_file: Option<PathBuf>,
+}
+
+/// How often --metrics-file is rewritten
+const METRICS_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Set in the environment of the detached process, so it doesn't detach again
+const DETACHED_ENV: &str = "ARANET_DETACHED";
+
+/// Runs this command again in the background, detached from the terminal, then exits. Returns within the detached
+/// process.
+pub fn detach() -> io::Result<()> {
+    if std::env::var_os(DETACHED_ENV).is_some() {
+        return Ok(());
+    }
+
+    // re-spawned rather than forked, as the async runtime's threads are already running
+    let mut command = Command::new(std::env::current_exe()?);
+    command.args(std::env::args_os().skip(1))
+        .env(DETACHED_ENV, "1")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    #[cfg(unix)] {
+        use std::os::unix::process::CommandExt;
+        // so signals from the terminal aren't delivered to it
+        command.process_group(0);
+    }
+    #[cfg(windows)] {
+        use std::os::windows::process::CommandExt;
+        const DETACHED_PROCESS: u32 = 0x00000008;
+        command.creation_flags(DETACHED_PROCESS);
+    }
+
+    let child = command.spawn()?;
+    log::info!("detached as process {}", child.id());
+    std::process::exit(0);
+}
+
+/// A file holding this process's ID, removed when dropped
+pub struct Pidfile(PathBuf);
+
+impl Pidfile {
+    /// Writes the pidfile, failing if it names another running process
+    pub fn create(path: PathBuf) -> Result<Pidfile, String> {
+        if let Ok(existing) = fs::read_to_string(&path) {
+            match existing.trim().parse::<u32>() {
+                Ok(pid) if pid != std::process::id() && is_running(pid) => {
+                    return Err(format!("already running as process {}, according to {}", pid, path.display()));
+                },
+                _ => log::warn!("replacing stale pidfile {}", path.display()),
+            }
+        }
+        fs::write(&path, format!("{}\n", std::process::id()))
+            .map_err(|e| format!("unable to write pidfile {}: {}", path.display(), e))?;
+        Ok(Pidfile(path))
+    }
+}
+
+impl Drop for Pidfile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.0) {
+            log::warn!("unable to remove pidfile {}: {}", self.0.display(), e);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn is_running(pid: u32) -> bool {
+    std::path::Path::new(&format!("/proc/{}", pid)).exists()
+}
+
+/// Without a portable way to check, existing pidfiles are assumed to be stale
+#[cfg(not(target_os = "linux"))]
+fn is_running(_pid: u32) -> bool {
+    false
+}
+
+/// Sends a state notification (such as `READY=1` or `STATUS=...`) to systemd, if running as a `Type=notify` service.
+///
+/// Protocol reference: https://www.freedesktop.org/software/systemd/man/sd_notify.html
+pub fn notify(state: &str) {
+    #[cfg(unix)] {
+        use std::os::unix::net::UnixDatagram;
+
+        let Some(path) = std::env::var_os("NOTIFY_SOCKET") else { return };
+        let send = || {
+            let socket = UnixDatagram::unbound()?;
+            #[cfg(target_os = "linux")] {
+                use std::os::linux::net::SocketAddrExt;
+                use std::os::unix::ffi::OsStrExt;
+                // sockets in the abstract namespace are prefixed with '@'
+                if let Some(name) = path.as_bytes().strip_prefix(b"@") {
+                    let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+                    return socket.send_to_addr(state.as_bytes(), &addr);
+                }
+            }
+            socket.send_to(state.as_bytes(), &path)
+        };
+        if let Err(e) = send() {
+            log::debug!("unable to notify systemd of {:?}: {}", state, e);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = state;
+}
+
+/// The systemd watchdog timeout, if the service has `WatchdogSec=` set
+fn watchdog_timeout() -> Option<Duration> {
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    // only set for another process, such as one this was started by
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok()? != std::process::id() {
+            return None;
+        }
+    }
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
+
+/// Whether every adapter responds within the time limit, which it won't if the Bluetooth stack has wedged
+async fn adapters_respond(manager: &Manager, limit: Duration) -> bool {
+    let check = async {
+        let adapters = manager.adapters().await?;
+        for adapter in &adapters {
+            adapter.adapter_info().await?;
+        }
+        Ok::<_, btleplug::Error>(! adapters.is_empty())
+    };
+    match tokio::time::timeout(limit, check).await {
+        Ok(Ok(responding)) => responding,
+        Ok(Err(e)) => {
+            log::warn!("Bluetooth adapters aren't responding: {}", e);
+            false
+        },
+        Err(_) => {
+            log::warn!("Bluetooth adapters didn't respond within {:?}", limit);
+            false
+        },
+    }
+}
+
+/// Feeds the systemd watchdog (if enabled) while Bluetooth is healthy, so systemd restarts the service if it wedges.
+/// It's healthy while advertisements are arriving, and otherwise (such as when every device is out of range) while
+/// the adapters still respond. Without a manager, such as when replaying, only advertisements count.
+pub fn watchdog(state: crate::state::State, manager: Option<Manager>) {
+    let Some(timeout) = watchdog_timeout() else { return };
+    log::info!("feeding the systemd watchdog, which times out after {:?}", timeout);
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(timeout / 2);
+        loop {
+            ticks.tick().await;
+            let hearing = state.since_advertisement().is_some_and(|since| since < timeout);
+            let healthy = match &manager {
+                _ if hearing => true,
+                Some(manager) => adapters_respond(manager, timeout / 4).await,
+                None => false,
+            };
+            match healthy {
+                true => notify("WATCHDOG=1"),
+                false => log::warn!("not feeding the systemd watchdog, as Bluetooth appears to have stopped working"),
+            }
+        }
+    });
+}
+
+/// Resolves once the process is asked to stop, by SIGTERM or Ctrl-C
+pub async fn shutdown_signal() {
+    #[cfg(unix)] {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => tokio::select! {
+                _ = terminate.recv() => {},
+                _ = tokio::signal::ctrl_c() => {},
+            },
+            Err(e) => {
+                log::warn!("unable to listen for SIGTERM: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+            },
+        }
+    }
+    #[cfg(all(not(unix), not(all(windows, feature = "windows_service"))))]
+    let _ = tokio::signal::ctrl_c().await;
+    // or when the service control manager stops `aranet service run`
+    #[cfg(all(windows, feature = "windows_service"))]
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {},
+        _ = crate::winservice::stop_requested() => {},
+    }
+}
+
+/// Dumps the state each time SIGUSR1 is received, to the file if given or otherwise stderr (the journal, under
+/// systemd)
+pub fn dump_on_signal(state: crate::state::State, file: Option<PathBuf>) {
+    #[cfg(unix)] {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut usr1 = match signal(SignalKind::user_defined1()) {
+            Ok(usr1) => usr1,
+            Err(e) => return log::warn!("unable to listen for SIGUSR1: {}", e),
+        };
+        tokio::spawn(async move {
+            while usr1.recv().await.is_some() {
+                let dump = state.dump();
+                match &file {
+                    Some(path) => match fs::write(path, dump) {
+                        Ok(()) => log::info!("dumped state to {}", path.display()),
+                        Err(e) => log::warn!("unable to dump state to {}: {}", path.display(), e),
+                    },
+                    None => eprint!("{}", dump),
+                }
+            }
+        });
+    }
+    #[cfg(not(unix))]
+    let _ = (state, file);
+}
+
+/// Writes the state's metrics to the file every [`METRICS_INTERVAL`]. The file is replaced by renaming a temporary
+/// file over it, so it's never read half written.
+pub fn write_metrics(state: crate::state::State, path: PathBuf) {
+    let mut temp = path.clone().into_os_string();
+    temp.push(".tmp");
+    let temp = PathBuf::from(temp);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(METRICS_INTERVAL);
+        let mut failing = false;
+        loop {
+            interval.tick().await;
+            let written = fs::write(&temp, state.metrics()).and_then(|()| fs::rename(&temp, &path));
+            match written {
+                Ok(()) => failing = false,
+                // only warn once until it works again, rather than every interval
+                Err(e) if ! failing => {
+                    log::warn!("unable to write --metrics-file {}: {}", path.display(), e);
+                    failing = true;
+                },
+                Err(e) => log::debug!("unable to write --metrics-file {}: {}", path.display(), e),
+            }
+        }
+    });
+}
+
+/// Stops scanning on every adapter, so the radio isn't left scanning after exiting
+pub async fn stop_scans(manager: &Manager) {
+    let adapters = match manager.adapters().await {
+        Ok(adapters) => adapters,
+        Err(e) => {
+            log::debug!("unable to list adapters to stop scanning: {}", e);
+            return;
+        },
+    };
+    for adapter in adapters {
+        if let Err(e) = adapter.stop_scan().await {
+            log::debug!("unable to stop scanning on {:?}: {}", adapter, e);
+        }
+    }
+}
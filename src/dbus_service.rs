@@ -0,0 +1,201 @@
+//! A D-Bus service exposing the latest reading from each Aranet4, so desktop applets and scripts can use readings
+//! without scanning for devices themselves.
+//!
+//! Owns the `io.github.aranet` name, with an object for each device under `/io/github/aranet`. Each implements
+//! `org.freedesktop.DBus.ObjectManager` (on the root object), `org.freedesktop.DBus.Properties`, and:
+//!
+//! | property    | type | unit                                  |
+//! |-------------|------|---------------------------------------|
+//! | Name        | s    | alias, or peripheral id               |
+//! | CO2         | q    | ppm, 0 if not measured                |
+//! | Temperature | d    | °C, NaN if not measured               |
+//! | Humidity    | d    | %                                     |
+//! | Pressure    | d    | hPa, NaN if not measured              |
+//! | Battery     | d    | %                                     |
+//! | Status      | s    | Green, Yellow, or Red                 |
+//! | Interval    | q    | seconds                               |
+//! | Measured    | t    | seconds since the Unix epoch          |
+//!
+//! With each new measurement, `PropertiesChanged` is emitted followed by
+//! `ReadingUpdated(q co2, d temperature, d humidity, d pressure)`.
+
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use aranet::{CurrentReadingDetailed, DiscoveredAranet};
+use btleplug::platform::Manager;
+use dbus::arg::{PropMap, RefArg, Variant};
+use dbus::channel::{MatchingReceiver, Sender};
+use dbus::message::{MatchRule, SignalArgs};
+use dbus::nonblock::stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged;
+use dbus::Path;
+use dbus_crossroads::{Crossroads, IfaceBuilder};
+use futures::StreamExt;
+
+use crate::filter::DeviceFilter;
+use crate::seen::SeenMeasurements;
+
+const BUS_NAME: &str = "io.github.aranet";
+const INTERFACE: &str = "io.github.aranet.Device1";
+const ROOT_PATH: &str = "/io/github/aranet";
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct DbusArgs {
+    /// Register on the system bus rather than the session bus. Requires a D-Bus policy allowing the name to be owned
+    #[arg(long)]
+    system: bool,
+}
+
+/// The latest measurement from a device, as exposed on its object
+#[derive(Debug, Clone)]
+struct Device {
+    name: String,
+    reading: CurrentReadingDetailed,
+    measured: u64,
+}
+
+impl Device {
+    fn new(adv: &DiscoveredAranet, reading: CurrentReadingDetailed) -> Device {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        Device {
+            name: crate::alias::display(adv),
+            reading,
+            measured: now.saturating_sub(reading.age as u64),
+        }
+    }
+
+    fn co2(&self) -> u16 {
+        self.reading.co2_ppm.unwrap_or(0)
+    }
+    fn temperature(&self) -> f64 {
+        self.reading.temperature_c.map_or(f64::NAN, widen)
+    }
+    fn humidity(&self) -> f64 {
+        widen(self.reading.humidity * 100.0)
+    }
+    fn pressure(&self) -> f64 {
+        self.reading.pressure_hpa.map_or(f64::NAN, widen)
+    }
+    fn battery(&self) -> f64 {
+        widen(self.reading.battery * 100.0)
+    }
+    fn status(&self) -> String {
+        format!("{:?}", self.reading.status)
+    }
+
+    /// Every property, for `PropertiesChanged`
+    fn properties(&self) -> PropMap {
+        let mut props = PropMap::new();
+        let mut insert = |name: &str, value: Box<dyn RefArg>| props.insert(name.to_owned(), Variant(value));
+        insert("Name", Box::new(self.name.clone()));
+        insert("CO2", Box::new(self.co2()));
+        insert("Temperature", Box::new(self.temperature()));
+        insert("Humidity", Box::new(self.humidity()));
+        insert("Pressure", Box::new(self.pressure()));
+        insert("Battery", Box::new(self.battery()));
+        insert("Status", Box::new(self.status()));
+        insert("Interval", Box::new(self.reading.interval));
+        insert("Measured", Box::new(self.measured));
+        props
+    }
+}
+
+/// Converts to a double with the same shortest decimal representation, so 1019.7 isn't exposed as 1019.7000122
+fn widen(x: f32) -> f64 {
+    x.to_string().parse().unwrap_or(f64::NAN)
+}
+
+/// The object path of a device, from the last component of its peripheral ID (eg: `dev_AA_BB_CC_DD_EE_FF`)
+fn object_path(adv: &DiscoveredAranet) -> Path<'static> {
+    let id = adv.peripheral_id.to_string();
+    let name: String = id.rsplit('/').next().unwrap_or(&id)
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    Path::new(format!("{}/{}", ROOT_PATH, name)).expect("sanitized object path is invalid")
+}
+
+/// Registers the service, then keeps each device's object updated with its latest measurement
+pub async fn run(args: DbusArgs, filter: DeviceFilter) -> Result<(), Box<dyn Error>> {
+    let (resource, conn) = match args.system {
+        true => dbus_tokio::connection::new_system_sync()?,
+        false => dbus_tokio::connection::new_session_sync()?,
+    };
+    let lost = tokio::spawn(resource);
+    conn.request_name(BUS_NAME, false, true, true).await
+        .map_err(|e| format!("unable to own {}: {}", BUS_NAME, e))?;
+
+    let mut cr = Crossroads::new();
+    cr.set_object_manager_support(Some(conn.clone()));
+    let mut reading_updated = None;
+    let iface = cr.register(INTERFACE, |b: &mut IfaceBuilder<Device>| {
+        b.property("Name").get(|_, d| Ok(d.name.clone()));
+        b.property("CO2").get(|_, d| Ok(d.co2()));
+        b.property("Temperature").get(|_, d| Ok(d.temperature()));
+        b.property("Humidity").get(|_, d| Ok(d.humidity()));
+        b.property("Pressure").get(|_, d| Ok(d.pressure()));
+        b.property("Battery").get(|_, d| Ok(d.battery()));
+        b.property("Status").get(|_, d| Ok(d.status()));
+        b.property("Interval").get(|_, d| Ok(d.reading.interval));
+        b.property("Measured").get(|_, d| Ok(d.measured));
+        reading_updated = Some(b.signal::<(u16, f64, f64, f64), _>("ReadingUpdated", ("co2", "temperature", "humidity", "pressure")).msg_fn());
+    });
+    let reading_updated = reading_updated.expect("ReadingUpdated signal was not registered");
+    let object_manager = cr.object_manager();
+    cr.insert(ROOT_PATH, &[object_manager], ());
+
+    let cr = Arc::new(Mutex::new(cr));
+    conn.start_receive(MatchRule::new_method_call(), Box::new({
+        let cr = cr.clone();
+        move |msg, conn| {
+            let _ = cr.lock().unwrap().handle_message(msg, conn);
+            true
+        }
+    }));
+    log::info!("registered {} on the {} bus", BUS_NAME, if args.system { "system" } else { "session" });
+
+    let manager = Manager::new().await?;
+    let mut discovered = crate::lock::discover(&manager).await?;
+    let mut seen = SeenMeasurements::new();
+
+    let listener = async {
+        while let Some(adv) = discovered.next().await {
+            if ! filter.matches(&adv) || ! seen.is_new(&adv) {
+                continue;
+            }
+            let Some(reading) = adv.current_reading else { continue };
+            let path = object_path(&adv);
+            let device = Device::new(&adv, reading);
+
+            {
+                let mut cr = cr.lock().unwrap();
+                match cr.data_mut::<Device>(&path) {
+                    Some(existing) => *existing = device.clone(),
+                    None => {
+                        log::info!("exposing Aranet4 {} as {}", device.name, path);
+                        cr.insert(path.clone(), &[iface], device.clone());
+                    },
+                }
+            }
+
+            let changed = PropertiesPropertiesChanged {
+                interface_name: INTERFACE.to_owned(),
+                changed_properties: device.properties(),
+                invalidated_properties: Vec::new(),
+            };
+            let updated = reading_updated(&path, &(device.co2(), device.temperature(), device.humidity(), device.pressure()));
+            if conn.send(changed.to_emit_message(&path)).is_err() || conn.send(updated).is_err() {
+                log::warn!("unable to emit updated reading for {}", path);
+            }
+        }
+    };
+
+    tokio::select! {
+        () = listener => Err("Unable to discover devices. No Bluetooth adapters present.".into()),
+        e = lost => Err(match e {
+            Ok(e) => format!("lost connection to D-Bus: {}", e),
+            Err(e) => format!("lost connection to D-Bus: {}", e),
+        }.into()),
+    }
+}
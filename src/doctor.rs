@@ -0,0 +1,277 @@
+//! Diagnoses the Bluetooth environment, for `aranet doctor`.
+//!
+//! Checks each step needed to hear from a device (the Bluetooth service, adapters and their power, permission to
+//! scan, and a short test scan), printing a hint for each that fails. This is the first thing to run when no devices
+//! are found.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::time::Duration;
+
+use aranet::{uuids, DiscoveredAranet};
+use btleplug::api::{Central, CentralEvent, Manager as _, ScanFilter};
+use btleplug::platform::{Manager, PeripheralId};
+use futures::StreamExt;
+
+use crate::filter::DeviceFilter;
+
+/// How long to scan for devices, if no --timeout is given
+const DEFAULT_SCAN: f64 = 10.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde_json", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde_json", serde(rename_all = "lowercase"))]
+enum Status {
+    Ok,
+    Warning,
+    Error,
+}
+
+#[cfg_attr(feature = "serde_json", derive(serde::Serialize))]
+struct Check {
+    name: String,
+    status: Status,
+    detail: String,
+    /// What to do about a warning or error
+    #[cfg_attr(feature = "serde_json", serde(skip_serializing_if = "Option::is_none"))]
+    hint: Option<String>,
+}
+
+impl Check {
+    fn ok(name: impl Into<String>, detail: impl Into<String>) -> Check {
+        Check { name: name.into(), status: Status::Ok, detail: detail.into(), hint: None }
+    }
+
+    fn warning(name: impl Into<String>, detail: impl Into<String>, hint: impl Into<String>) -> Check {
+        Check { name: name.into(), status: Status::Warning, detail: detail.into(), hint: Some(hint.into()) }
+    }
+
+    fn error(name: impl Into<String>, detail: impl Into<String>, hint: impl Into<String>) -> Check {
+        Check { name: name.into(), status: Status::Error, detail: detail.into(), hint: Some(hint.into()) }
+    }
+}
+
+/// What to try when the platform's Bluetooth stack can't be used at all
+fn stack_hint() -> &'static str {
+    if cfg!(target_os = "macos") {
+        "Allow your terminal (or the app running aranet) to use Bluetooth, in System Settings > Privacy & Security > \
+        Bluetooth"
+    } else if cfg!(target_os = "linux") {
+        "Check that BlueZ is installed and running, with `systemctl status bluetooth`"
+    } else {
+        "Check that Bluetooth is turned on in the system settings"
+    }
+}
+
+/// What to try when scanning fails
+fn scan_hint(error: &str) -> String {
+    let denied = ["NotAuthorized", "NotPermitted", "AccessDenied", "not authorized", "permission"].iter()
+        .any(|s| error.contains(s));
+    match denied {
+        true if cfg!(target_os = "linux") => "Scanning was denied. Run as a user allowed to use BlueZ by its D-Bus policy \
+            (such as a member of the `bluetooth` group), or as root".to_owned(),
+        true => stack_hint().to_owned(),
+        false => "Another program may be scanning, or the adapter may be busy. Try again, or restart the Bluetooth \
+            service".to_owned(),
+    }
+}
+
+/// Checks BlueZ is running, and that each of its adapters is powered
+#[cfg(all(target_os = "linux", feature = "dbus"))]
+async fn bluez(checks: &mut Vec<Check>) {
+    use dbus::arg::prop_cast;
+    use dbus::nonblock::stdintf::org_freedesktop_dbus::ObjectManager;
+    use dbus::nonblock::Proxy;
+
+    let conn = match dbus_tokio::connection::new_system_sync() {
+        Ok((resource, conn)) => {
+            tokio::spawn(resource);
+            conn
+        },
+        Err(e) => {
+            checks.push(Check::error(
+                "D-Bus", format!("unable to connect to the system bus: {}", e),
+                "BlueZ is used over the D-Bus system bus. Check it's running, and when in a container, that \
+                /run/dbus/system_bus_socket is mounted",
+            ));
+            return;
+        },
+    };
+    checks.push(Check::ok("D-Bus", "connected to the system bus"));
+
+    let bus = Proxy::new("org.freedesktop.DBus", "/org/freedesktop/DBus", Duration::from_secs(5), conn.clone());
+    match bus.method_call::<(bool,), _, _, _>("org.freedesktop.DBus", "NameHasOwner", ("org.bluez",)).await {
+        Ok((true,)) => checks.push(Check::ok("BlueZ", "running")),
+        Ok((false,)) => {
+            checks.push(Check::error("BlueZ", "not running", "Start it with `sudo systemctl start bluetooth`"));
+            return;
+        },
+        Err(e) => {
+            checks.push(Check::error("BlueZ", format!("unable to check whether it's running: {}", e), stack_hint()));
+            return;
+        },
+    }
+
+    let bluez = Proxy::new("org.bluez", "/", Duration::from_secs(5), conn);
+    let objects = match bluez.get_managed_objects().await {
+        Ok(objects) => objects,
+        Err(e) => {
+            checks.push(Check::error("BlueZ", format!("unable to list adapters: {}", e), scan_hint(&e.to_string())));
+            return;
+        },
+    };
+    let mut adapters: Vec<_> = objects.iter()
+        .filter_map(|(path, interfaces)| Some((path, interfaces.get("org.bluez.Adapter1")?)))
+        .collect();
+    adapters.sort_by_key(|(path, _)| path.to_string());
+    for (path, props) in adapters {
+        let name = format!("Adapter {}", path.trim_start_matches("/org/bluez/"));
+        let address = prop_cast::<String>(props, "Address").cloned().unwrap_or_default();
+        match prop_cast::<bool>(props, "Powered") {
+            Some(true) => checks.push(Check::ok(name, format!("{} is powered on", address))),
+            _ => checks.push(Check::error(
+                name, format!("{} is powered off", address),
+                "Turn it on with `bluetoothctl power on`. If it's blocked, unblock it first with `rfkill unblock bluetooth`",
+            )),
+        }
+    }
+}
+
+/// Runs the checks, scanning for `timeout` seconds. Exits with status 1 if any check failed.
+pub async fn run(filter: DeviceFilter, timeout: Option<f64>, json: bool) -> Result<(), Box<dyn Error>> {
+    let mut checks = Vec::new();
+    #[cfg(all(target_os = "linux", feature = "dbus"))]
+    bluez(&mut checks).await;
+
+    let scan = Duration::from_secs_f64(timeout.unwrap_or(DEFAULT_SCAN));
+    diagnose(&mut checks, &filter, scan).await;
+
+    #[cfg(feature = "serde_json")]
+    if json {
+        println!("{}", serde_json::to_string_pretty(&checks)?);
+    }
+    if ! json {
+        for check in &checks {
+            let tag = match check.status {
+                Status::Ok => " OK ",
+                Status::Warning => "WARN",
+                Status::Error => "FAIL",
+            };
+            println!("[{}] {}: {}", tag, check.name, check.detail);
+            if let Some(hint) = &check.hint {
+                println!("       {}", hint);
+            }
+        }
+    }
+
+    if checks.iter().any(|check| check.status == Status::Error) {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Checks adapters through btleplug, then scans for devices
+async fn diagnose(checks: &mut Vec<Check>, filter: &DeviceFilter, scan: Duration) {
+    let manager = match Manager::new().await {
+        Ok(manager) => manager,
+        Err(e) => return checks.push(Check::error("Bluetooth", format!("unavailable: {}", e), stack_hint())),
+    };
+    let adapters = match manager.adapters().await {
+        Ok(adapters) if adapters.is_empty() => return checks.push(Check::error(
+            "Adapters", "no Bluetooth adapters found",
+            format!("Plug in or enable a Bluetooth 4.0 (LE) adapter. {}", stack_hint()),
+        )),
+        Ok(adapters) => adapters,
+        Err(e) => return checks.push(Check::error(
+            "Adapters", format!("unable to list adapters: {}", crate::bluetooth_error(e)), stack_hint(),
+        )),
+    };
+    for adapter in &adapters {
+        let info = adapter.adapter_info().await.unwrap_or_else(|e| format!("unknown ({})", e));
+        checks.push(Check::ok("Adapters", format!("found {}", info)));
+    }
+
+    // every device heard, and the latest advertisement of each Aranet
+    let mut heard: HashMap<PeripheralId, Option<DiscoveredAranet>> = HashMap::new();
+    let mut streams = Vec::new();
+    {
+        let _lock = match crate::lock::acquire().await {
+            Ok(lock) => lock,
+            Err(e) => return checks.push(Check::error("Scan", e, "Wait for other runs of aranet to finish")),
+        };
+        for adapter in &adapters {
+            let events = match adapter.events().await {
+                Ok(events) => events,
+                Err(e) => return checks.push(Check::error("Scan", format!("unable to listen: {}", e), scan_hint(&e.to_string()))),
+            };
+            if let Err(e) = adapter.start_scan(ScanFilter::default()).await {
+                return checks.push(Check::error("Scan", format!("unable to start scanning: {}", e), scan_hint(&e.to_string())));
+            }
+            streams.push(events);
+        }
+    }
+    checks.push(Check::ok("Scan", format!("started, listening for {}s", scan.as_secs_f64())));
+
+    let mut events = futures::stream::select_all(streams);
+    let listen = async {
+        while let Some(event) = events.next().await {
+            match event {
+                CentralEvent::DeviceDiscovered(id) | CentralEvent::DeviceUpdated(id) => {
+                    heard.entry(id).or_default();
+                },
+                CentralEvent::ManufacturerDataAdvertisement { id, manufacturer_data } => {
+                    let adv = manufacturer_data.get(&uuids::MANUFACTURER_ID)
+                        .and_then(|data| DiscoveredAranet::parse(None, id.clone(), data).ok());
+                    let entry = heard.entry(id).or_default();
+                    if adv.is_some() {
+                        *entry = adv;
+                    }
+                },
+                _ => {},
+            }
+        }
+    };
+    let _ = tokio::time::timeout(scan, listen).await;
+    for adapter in &adapters {
+        if let Err(e) = adapter.stop_scan().await {
+            log::debug!("unable to stop scanning: {}", e);
+        }
+    }
+
+    let aranets: Vec<&DiscoveredAranet> = heard.values().flatten().collect();
+    if heard.is_empty() {
+        return checks.push(Check::error(
+            "Devices", "heard no Bluetooth devices at all",
+            format!("The adapter may be blocked, or unable to receive. {}", stack_hint()),
+        ));
+    }
+    if aranets.is_empty() {
+        return checks.push(Check::warning(
+            "Devices", format!("heard {} Bluetooth devices, but no Aranet4", heard.len()),
+            "Move closer to the Aranet4, and check Bluetooth is enabled on it (in its settings menu). Its battery may \
+            also be empty",
+        ));
+    }
+
+    for adv in &aranets {
+        let name = crate::alias::display(adv);
+        match adv.current_reading {
+            Some(_) => checks.push(Check::ok("Devices", format!("heard {}, advertising readings", name))),
+            None => checks.push(Check::warning(
+                "Devices", format!("heard {}, but its advertisements don't include readings", name),
+                match cfg!(all(target_os = "linux", feature = "dbus")) {
+                    true => "Enable Smart Home integrations in the Aranet Home app's device settings, or pair with \
+                        `aranet pair` and use --prefer-connect",
+                    false => "Enable Smart Home integrations in the Aranet Home app's device settings, or pair with \
+                        the device and use --prefer-connect",
+                },
+            )),
+        }
+    }
+    if ! filter.only.is_empty() && ! aranets.iter().any(|adv| filter.matches(adv)) {
+        checks.push(Check::warning(
+            "Devices", "heard none of the devices given with --device",
+            "Check their addresses, as listed above. On macOS, devices have a per-computer ID rather than their address",
+        ));
+    }
+}
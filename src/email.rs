@@ -0,0 +1,163 @@
+//! Sends alert emails over SMTP, for unattended deployments where nobody is watching the output.
+//!
+//! Emails are sent when an alert from the config file fires or resolves, and when a device hasn't been heard from
+//! for several of its measurement intervals.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use aranet::DiscoveredAranet;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::Deserialize;
+
+use crate::alerts::{AlertEvent, Transition};
+
+/// How often to check for devices that have gone quiet
+const MISSING_CHECK_PERIOD: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    /// Upgrade the connection with STARTTLS, usually on port 587
+    #[default]
+    Starttls,
+    /// Implicit TLS, usually on port 465
+    Tls,
+    /// Unencrypted, such as for a relay on localhost
+    None,
+}
+
+/// The `[email]` section of the config file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EmailConfig {
+    /// Host name of the SMTP server
+    pub server: String,
+    /// Defaults to the usual port for the `security` mode
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub security: SmtpSecurity,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    #[serde(deserialize_with = "crate::config::from_str")]
+    pub from: Mailbox,
+    #[serde(deserialize_with = "mailboxes")]
+    pub to: Vec<Mailbox>,
+    /// Email when a device hasn't been heard from in this many of its measurement intervals. Disabled if unset.
+    pub missing_intervals: Option<u32>,
+}
+
+fn mailboxes<'de, D: serde::Deserializer<'de>>(d: D) -> Result<Vec<Mailbox>, D::Error> {
+    Vec::<String>::deserialize(d)?.iter()
+        .map(|s| s.parse().map_err(serde::de::Error::custom))
+        .collect()
+}
+
+#[derive(Debug)]
+struct LastSeen {
+    at: Instant,
+    interval: Duration,
+    /// Whether the device has already been reported as missing
+    reported: bool,
+}
+
+#[derive(Clone)]
+pub struct EmailNotifier {
+    config: Arc<EmailConfig>,
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    devices: Arc<Mutex<HashMap<String, LastSeen>>>,
+}
+
+impl EmailNotifier {
+    pub fn new(config: EmailConfig) -> Result<EmailNotifier, lettre::transport::smtp::Error> {
+        let mut builder = match config.security {
+            SmtpSecurity::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.server)?,
+            SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.server)?,
+            SmtpSecurity::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.server),
+        };
+        if let Some(port) = config.port {
+            builder = builder.port(port);
+        }
+        if let (Some(username), Some(password)) = (&config.username, &config.password) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+
+        Ok(EmailNotifier {
+            transport: builder.build(),
+            config: Arc::new(config),
+            devices: Arc::default(),
+        })
+    }
+
+    /// Records that a device has been heard from, emailing if it had previously been reported missing
+    pub async fn seen(&self, adv: &DiscoveredAranet) {
+        let Some(r) = &adv.current_reading else { return };
+        let device = crate::alias::display(adv);
+        let previous = self.devices.lock().unwrap().insert(device.clone(), LastSeen {
+            at: Instant::now(),
+            interval: Duration::from_secs(r.interval as u64),
+            reported: false,
+        });
+        if previous.map(|p| p.reported).unwrap_or(false) {
+            self.send(format!("[aranet] {} is reporting again", device), format!("Received a new reading from {}:\n\n{}", device, r)).await;
+        }
+    }
+
+    /// Emails the alert transition, with the reading that caused it
+    pub async fn alert(&self, adv: &DiscoveredAranet, event: &AlertEvent) {
+        let subject = match event.transition {
+            Transition::Firing => format!("[aranet] {} firing for {}", event.name, event.device),
+            Transition::Resolved => format!("[aranet] {} resolved for {}", event.name, event.device),
+        };
+        let mut body = format!("{}\n", event);
+        if let Some(r) = &adv.current_reading {
+            body.push_str(&format!("\nLatest reading from {}:\n\n{}\n", event.device, r));
+        }
+        self.send(subject, body).await;
+    }
+
+    /// Periodically checks for devices that haven't been heard from in `missing_intervals` of their intervals,
+    /// emailing once for each. Does nothing if `missing_intervals` isn't configured.
+    pub fn spawn_missing_check(&self) {
+        let Some(intervals) = self.config.missing_intervals else { return };
+        let notifier = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(MISSING_CHECK_PERIOD);
+            loop {
+                ticker.tick().await;
+                let missing: Vec<(String, Duration)> = notifier.devices.lock().unwrap().iter_mut()
+                    .filter(|(_, seen)| ! seen.reported && seen.at.elapsed() > seen.interval * intervals)
+                    .map(|(device, seen)| {
+                        seen.reported = true;
+                        (device.clone(), seen.at.elapsed())
+                    })
+                    .collect();
+                for (device, elapsed) in missing {
+                    notifier.send(
+                        format!("[aranet] {} has not been heard from", device),
+                        format!("No reading has been received from {} in {}s, over {} of its measurement intervals.", device, elapsed.as_secs(), intervals),
+                    ).await;
+                }
+            }
+        });
+    }
+
+    async fn send(&self, subject: String, body: String) {
+        let mut builder = Message::builder().from(self.config.from.clone()).subject(subject);
+        for to in &self.config.to {
+            builder = builder.to(to.clone());
+        }
+        let message = match builder.body(body) {
+            Ok(message) => message,
+            Err(e) => return log::warn!("unable to build alert email: {}", e),
+        };
+
+        log::debug!("sending alert email via {}", self.config.server);
+        if let Err(e) = self.transport.send(message).await {
+            log::warn!("unable to send alert email: {}", e);
+        }
+    }
+}
@@ -0,0 +1,53 @@
+//! Options from environment variables, so containers and services can be configured without editing their command
+//! line.
+//!
+//! Each option listed by `aranet --help` can be set by `ARANET_OPT_` and its long name in upper case, with hyphens as
+//! underscores: `ARANET_OPT_FORMAT=json` for --format, or `ARANET_OPT_STALE_AFTER=2` for --stale-after. Flags are set
+//! with `true` or `false`. Options given on the command line take precedence over the environment, which takes
+//! precedence over the config file. Subcommands' own options aren't read from the environment.
+//!
+//! The prefix keeps them apart from the variables --exec and --alert-exec commands are given, such as `ARANET_DEVICE`,
+//! so an `aranet` run by one of those commands isn't configured by the reading that triggered it.
+
+use std::ffi::OsString;
+
+use clap::{CommandFactory, FromArgMatches};
+
+use crate::Args;
+
+/// The prefix of the environment variables setting options
+pub const PREFIX: &str = "ARANET_OPT_";
+
+/// The environment variable setting an option, by its long name
+pub fn var(long: &str) -> String {
+    format!("{}{}", PREFIX, long.to_ascii_uppercase().replace('-', "_"))
+}
+
+/// The command line parser, with each option also read from its environment variable
+fn command() -> clap::Command {
+    let mut command = Args::command();
+    let options: Vec<(String, String)> = command.get_arguments()
+        .filter_map(|arg| Some((arg.get_id().to_string(), arg.get_long()?.to_owned())))
+        .collect();
+    for (id, long) in options {
+        // listing each variable would double the length of --help
+        command = command.mut_arg(id, |arg| arg.env(var(&long)).hide_env(true));
+    }
+    command
+}
+
+/// Parses the arguments (including the program name) and environment, exiting with usage on errors
+pub fn parse_from<I, T>(argv: I) -> Args
+where I: IntoIterator<Item = T>, T: Into<OsString> + Clone {
+    let mut command = command();
+    let matches = command.try_get_matches_from_mut(argv).unwrap_or_else(|e| e.exit());
+    Args::from_arg_matches(&matches).unwrap_or_else(|e| e.format(&mut command).exit())
+}
+
+/// Whether the option given by an argument such as `--format=json` is also set in the environment, which overrides
+/// it
+#[cfg(feature = "config")]
+pub fn overrides(arg: &str) -> bool {
+    let long = arg.trim_start_matches("--").split('=').next().unwrap_or_default();
+    std::env::var_os(var(long)).is_some_and(|value| ! value.is_empty())
+}
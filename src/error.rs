@@ -0,0 +1,124 @@
+//! Categories of errors that end a run, each with a stable exit status and name, so scripts and monitoring can tell
+//! a missing adapter from a device that's out of range without parsing messages.
+//!
+//! Exit statuses follow `sysexits.h`. Errors that don't fit a category exit with status 1.
+
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// No Bluetooth adapter is present, or the Bluetooth stack can't be used
+    NoAdapter,
+    /// No matching device was heard
+    NoDevice,
+    /// Nothing was received within --timeout
+    Timeout,
+    /// Connecting to or reading from a device failed
+    ConnectFailed,
+    /// Input such as a recording or configuration file was malformed
+    #[cfg_attr(not(any(feature = "serde_json", feature = "config")), allow(dead_code))]
+    Parse,
+    /// The operating system denied access, such as to Bluetooth
+    PermissionDenied,
+    /// Any other error
+    Other,
+}
+
+impl ErrorKind {
+    /// The exit status of errors of this kind
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::NoAdapter => 69, // EX_UNAVAILABLE
+            ErrorKind::NoDevice => 68, // EX_NOHOST
+            ErrorKind::Timeout => 75, // EX_TEMPFAIL
+            ErrorKind::ConnectFailed => 74, // EX_IOERR
+            ErrorKind::Parse => 65, // EX_DATAERR
+            ErrorKind::PermissionDenied => 77, // EX_NOPERM
+            ErrorKind::Other => 1,
+        }
+    }
+
+    /// The HTTP status answering CGI requests with errors of this kind
+    #[cfg(feature = "cgi_detection")]
+    pub fn http_status(self) -> &'static str {
+        match self {
+            ErrorKind::NoAdapter | ErrorKind::PermissionDenied => "503 Service Unavailable",
+            ErrorKind::NoDevice => "404 Not Found",
+            ErrorKind::Timeout => "504 Gateway Timeout",
+            ErrorKind::ConnectFailed => "502 Bad Gateway",
+            ErrorKind::Parse | ErrorKind::Other => "500 Internal Server Error",
+        }
+    }
+
+    /// The name of this kind, as in the `error` field of JSON errors
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorKind::NoAdapter => "no_adapter",
+            ErrorKind::NoDevice => "no_device",
+            ErrorKind::Timeout => "timeout",
+            ErrorKind::ConnectFailed => "connect_failed",
+            ErrorKind::Parse => "parse",
+            ErrorKind::PermissionDenied => "permission_denied",
+            ErrorKind::Other => "other",
+        }
+    }
+
+    /// The kind of an error, if it's known from its type
+    pub fn of(error: &(dyn Error + 'static)) -> Option<ErrorKind> {
+        if let Some(e) = error.downcast_ref::<CliError>() {
+            return Some(e.kind);
+        }
+        if let Some(e) = error.downcast_ref::<btleplug::Error>() {
+            return match e {
+                btleplug::Error::PermissionDenied => Some(ErrorKind::PermissionDenied),
+                btleplug::Error::DeviceNotFound => Some(ErrorKind::NoDevice),
+                btleplug::Error::TimedOut(_) => Some(ErrorKind::Timeout),
+                btleplug::Error::NotConnected => Some(ErrorKind::ConnectFailed),
+                _ => None,
+            };
+        }
+        if let Some(e) = error.downcast_ref::<std::io::Error>() {
+            return (e.kind() == std::io::ErrorKind::PermissionDenied).then_some(ErrorKind::PermissionDenied);
+        }
+        #[cfg(feature = "serde_json")]
+        if error.is::<serde_json::Error>() {
+            return Some(ErrorKind::Parse);
+        }
+        #[cfg(feature = "config")]
+        if let Some(e) = error.downcast_ref::<crate::config::ConfigError>() {
+            return match e {
+                crate::config::ConfigError::Io(_, e) => ErrorKind::of(e),
+                crate::config::ConfigError::Parse(..) | crate::config::ConfigError::Invalid(..) => Some(ErrorKind::Parse),
+            };
+        }
+        None
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// An error message of a known kind
+#[derive(Debug)]
+pub struct CliError {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl CliError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> CliError {
+        CliError { kind, message: message.into() }
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for CliError {}
@@ -0,0 +1,118 @@
+//! Writes readings and alerts to the Windows Application event log, for Windows-native monitoring of the tool when
+//! run as a service.
+//!
+//! The event source must be registered once (as Administrator) with `aranet register-event-source`. It reuses
+//! EventCreate.exe's message table, which formats event IDs 1 through 1000 as the event's string.
+
+use std::io;
+use std::ptr;
+
+use aranet::DiscoveredAranet;
+use windows_sys::core::PCWSTR;
+use windows_sys::Win32::Foundation::ERROR_SUCCESS;
+use windows_sys::Win32::System::EventLog::{
+    DeregisterEventSource, EventSourceHandle, RegisterEventSourceW, ReportEventW, EVENTLOG_INFORMATION_TYPE,
+    EVENTLOG_WARNING_TYPE, REPORT_EVENT_TYPE,
+};
+use windows_sys::Win32::System::Registry::{
+    RegCloseKey, RegCreateKeyExW, RegSetValueExW, HKEY, HKEY_LOCAL_MACHINE, KEY_SET_VALUE, REG_DWORD, REG_EXPAND_SZ,
+    REG_OPTION_NON_VOLATILE,
+};
+
+use crate::seen::SeenMeasurements;
+
+const SOURCE: &str = "aranet";
+const REGISTRY_KEY: &str = r"SYSTEM\CurrentControlSet\Services\EventLog\Application\aranet";
+const MESSAGE_FILE: &str = r"%SystemRoot%\System32\EventCreate.exe";
+
+const EVENT_READING: u32 = 1;
+const EVENT_ALERT_FIRING: u32 = 2;
+const EVENT_ALERT_RESOLVED: u32 = 3;
+
+/// A nul-terminated UTF-16 string
+fn wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(Some(0)).collect()
+}
+
+/// Registers the `aranet` event source within the Application log. Requires Administrator privileges.
+pub fn register() -> io::Result<()> {
+    let mut key: HKEY = 0;
+    let status = unsafe {
+        RegCreateKeyExW(
+            HKEY_LOCAL_MACHINE, wide(REGISTRY_KEY).as_ptr(), 0, ptr::null(), REG_OPTION_NON_VOLATILE,
+            KEY_SET_VALUE, ptr::null(), &mut key, ptr::null_mut(),
+        )
+    };
+    if status != ERROR_SUCCESS {
+        return Err(io::Error::from_raw_os_error(status as i32));
+    }
+
+    let message_file: Vec<u8> = wide(MESSAGE_FILE).iter().flat_map(|c| c.to_le_bytes()).collect();
+    // error, warning, and information events
+    let types_supported = 7u32.to_le_bytes();
+    let statuses = unsafe {
+        [
+            RegSetValueExW(key, wide("EventMessageFile").as_ptr(), 0, REG_EXPAND_SZ, message_file.as_ptr(), message_file.len() as u32),
+            RegSetValueExW(key, wide("TypesSupported").as_ptr(), 0, REG_DWORD, types_supported.as_ptr(), types_supported.len() as u32),
+        ]
+    };
+    unsafe { RegCloseKey(key) };
+
+    match statuses.into_iter().find(|s| *s != ERROR_SUCCESS) {
+        Some(status) => Err(io::Error::from_raw_os_error(status as i32)),
+        None => Ok(()),
+    }
+}
+
+pub struct EventLog {
+    handle: EventSourceHandle,
+    /// To only log each measurement once
+    seen: SeenMeasurements,
+}
+
+impl EventLog {
+    pub fn new() -> io::Result<EventLog> {
+        let handle = unsafe { RegisterEventSourceW(ptr::null(), wide(SOURCE).as_ptr()) };
+        if handle == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(EventLog { handle, seen: SeenMeasurements::new() })
+    }
+
+    /// Logs the advertisement's reading, if it's a new measurement
+    pub fn reading(&mut self, adv: &DiscoveredAranet) -> io::Result<()> {
+        if ! self.seen.is_new(adv) {
+            return Ok(());
+        }
+        let Some(r) = &adv.current_reading else { return Ok(()) };
+        self.report(EVENTLOG_INFORMATION_TYPE, EVENT_READING, &format!("Reading from {}:\r\n{}", crate::alias::display(adv), r.to_string().replace('\n', "\r\n")))
+    }
+
+    /// Logs an alert from the config file firing or resolving
+    #[cfg(feature = "config")]
+    pub fn alert(&self, event: &crate::alerts::AlertEvent) -> io::Result<()> {
+        let (kind, id) = match event.transition {
+            crate::alerts::Transition::Firing => (EVENTLOG_WARNING_TYPE, EVENT_ALERT_FIRING),
+            crate::alerts::Transition::Resolved => (EVENTLOG_INFORMATION_TYPE, EVENT_ALERT_RESOLVED),
+        };
+        self.report(kind, id, &event.to_string())
+    }
+
+    fn report(&self, kind: REPORT_EVENT_TYPE, id: u32, message: &str) -> io::Result<()> {
+        let message = wide(message);
+        let strings: [PCWSTR; 1] = [message.as_ptr()];
+        let ok = unsafe {
+            ReportEventW(self.handle, kind, 0, id, ptr::null_mut(), strings.len() as u16, 0, strings.as_ptr(), ptr::null())
+        };
+        if ok == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for EventLog {
+    fn drop(&mut self) {
+        unsafe { DeregisterEventSource(self.handle) };
+    }
+}
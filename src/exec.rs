@@ -0,0 +1,225 @@
+//! Runs a user-provided command for each new reading, with the values passed as environment variables.
+
+use std::io;
+use std::time::Duration;
+
+use aranet::DiscoveredAranet;
+use tokio::process::{Child, Command};
+use tokio::task::JoinHandle;
+
+use crate::metric::Metric;
+use crate::seen::SeenMeasurements;
+
+/// How long an --exec or --alert-exec command may run for before it's killed
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+pub struct ExecHook {
+    command: String,
+    /// To only run once per measurement
+    seen: SeenMeasurements,
+    running: Running,
+}
+
+impl ExecHook {
+    pub fn new(command: String) -> ExecHook {
+        ExecHook { command, seen: SeenMeasurements::new(), running: Running::default() }
+    }
+
+    /// The environment variables describing an advertisement's reading
+    ///
+    /// Each metric is exported as `ARANET_<METRIC>` (eg: `ARANET_CO2`, `ARANET_TEMP_C`), and is unset if unavailable.
+    /// Each of the device's tags is exported as `ARANET_TAG_<KEY>` (eg: `ARANET_TAG_SITE`).
+    pub fn environment(adv: &DiscoveredAranet) -> Vec<(String, String)> {
+        let mut env = vec![
+            ("ARANET_DEVICE".to_owned(), adv.peripheral_id.to_string()),
+            ("ARANET_FIRMWARE".to_owned(), adv.manufacturer_data.version.to_string()),
+        ];
+        if let Some(name) = crate::alias::name(adv) {
+            env.push(("ARANET_NAME".to_owned(), name.to_owned()));
+        }
+        for (key, value) in crate::tags::of(adv) {
+            env.push((format!("ARANET_TAG_{}", key.to_ascii_uppercase()), value.to_owned()));
+        }
+        if let Some(r) = &adv.current_reading {
+            env.push(("ARANET_INTERVAL".to_owned(), r.interval.to_string()));
+            env.push(("ARANET_STATUS_NAME".to_owned(), format!("{:?}", r.status).to_ascii_uppercase()));
+            for metric in Metric::ALL {
+                if let Some(value) = metric.format(r) {
+                    env.push((format!("ARANET_{}", metric.name().to_ascii_uppercase()), value));
+                }
+            }
+        }
+        env
+    }
+
+    /// Starts the command for the advertisement's reading, without waiting for it to exit. Failing to start it is an
+    /// error, while it exiting unsuccessfully or being killed after [`TIMEOUT`] is only logged.
+    ///
+    /// Advertisements without a reading, or repeating a measurement the command already ran for, are skipped.
+    pub fn run(&mut self, adv: &DiscoveredAranet) -> io::Result<()> {
+        if ! self.seen.is_new(adv) {
+            log::trace!("skipping --exec for repeated or missing measurement from {}", adv.peripheral_id);
+            return Ok(());
+        }
+
+        log::debug!("running --exec command for reading from {}", adv.peripheral_id);
+        let child = shell(&self.command).envs(Self::environment(adv)).kill_on_drop(true).spawn()?;
+        self.running.push(child, format!("--exec command for {}", adv.peripheral_id));
+        Ok(())
+    }
+
+    /// Waits for any commands still running to exit, or be killed
+    pub async fn finish(self) {
+        self.running.finish().await
+    }
+}
+
+/// Commands that may still be running, each waited on in the background
+#[derive(Default)]
+struct Running(Vec<JoinHandle<()>>);
+
+impl Running {
+    /// Waits for the command in the background, as `what` in logs
+    fn push(&mut self, child: Child, what: String) {
+        self.0.retain(|command| ! command.is_finished());
+        self.0.push(tokio::spawn(wait(child, what)));
+    }
+
+    async fn finish(self) {
+        for command in self.0 {
+            let _ = command.await;
+        }
+    }
+}
+
+/// Waits for a command to exit, killing it after [`TIMEOUT`]
+async fn wait(mut child: Child, what: String) {
+    match tokio::time::timeout(TIMEOUT, child.wait()).await {
+        Ok(Ok(status)) if status.success() => {},
+        Ok(Ok(status)) => log::warn!("{} exited with {}", what, status),
+        Ok(Err(e)) => log::warn!("unable to wait for {}: {}", what, e),
+        Err(_) => {
+            log::warn!("{} didn't exit within {:?}, killing it", what, TIMEOUT);
+            if let Err(e) = child.kill().await {
+                log::warn!("unable to kill {}: {}", what, e);
+            }
+        },
+    }
+}
+
+/// The environment variables describing an alert changing state
+///
+/// The alert is described by `ARANET_ALERT` (its name), `ARANET_ALERT_STATE` (`firing` or `resolved`),
+/// `ARANET_ALERT_METRIC`, `ARANET_ALERT_VALUE`, and `ARANET_ALERT_THRESHOLD`.
+#[cfg(feature = "config")]
+pub fn alert_environment(event: &crate::alerts::AlertEvent) -> Vec<(String, String)> {
+    vec![
+        ("ARANET_ALERT".to_owned(), event.name.clone()),
+        ("ARANET_ALERT_STATE".to_owned(), event.transition.to_string()),
+        ("ARANET_ALERT_METRIC".to_owned(), event.metric.to_string()),
+        ("ARANET_ALERT_VALUE".to_owned(), format!("{:.*}", event.metric.precision(), event.value)),
+        ("ARANET_ALERT_THRESHOLD".to_owned(), event.threshold.to_string()),
+    ]
+}
+
+/// Runs the --alert-exec command whenever an alert changes state
+#[cfg(feature = "config")]
+pub struct AlertHook {
+    command: String,
+    running: Running,
+}
+
+#[cfg(feature = "config")]
+impl AlertHook {
+    pub fn new(command: String) -> AlertHook {
+        AlertHook { command, running: Running::default() }
+    }
+
+    /// Starts the command for an alert changing state, without waiting for it to exit, as with [`ExecHook::run`].
+    ///
+    /// The command receives both the alert's and the reading's variables.
+    pub fn run(&mut self, adv: &DiscoveredAranet, event: &crate::alerts::AlertEvent) -> io::Result<()> {
+        log::debug!("running --alert-exec command for alert {} {}", event.name, event.transition);
+        let child = shell(&self.command)
+            .envs(ExecHook::environment(adv))
+            .envs(alert_environment(event))
+            .kill_on_drop(true)
+            .spawn()?;
+        self.running.push(child, format!("--alert-exec command for alert {} {}", event.name, event.transition));
+        Ok(())
+    }
+
+    /// Waits for any commands still running to exit, or be killed
+    pub async fn finish(self) {
+        self.running.finish().await
+    }
+}
+
+/// A command running `command` through the platform's shell
+fn shell(command: &str) -> Command {
+    #[cfg(windows)]
+    {
+        let mut c = Command::new("cmd");
+        c.arg("/C").arg(command);
+        c
+    }
+    #[cfg(not(windows))]
+    {
+        let mut c = Command::new("sh");
+        c.arg("-c").arg(command);
+        c
+    }
+}
+
+#[cfg(all(test, unix, feature = "serde_json"))]
+mod tests {
+    use super::*;
+
+    fn reading() -> DiscoveredAranet {
+        let data = [
+            0x20, 0x13, 0x04, 0x01, 0x00, 0x0c, 0x0f, 0x01,
+            0x64, 0x02, 0xc9, 0x01, 0x8b, 0x27, 0x29, 0x57, 0x01, 0x2c, 0x01, 0x2a, 0x00,
+        ];
+        DiscoveredAranet::parse(None, crate::simulate::peripheral_id(0), &data).unwrap()
+    }
+
+    #[tokio::test]
+    async fn runs_without_waiting() {
+        let path = std::env::temp_dir().join(format!("aranet-exec-test-{}", std::process::id()));
+        let mut hook = ExecHook::new(format!("sleep 1; echo $ARANET_CO2 > {}", path.display()));
+
+        let start = std::time::Instant::now();
+        hook.run(&reading()).unwrap();
+        // the same measurement again is skipped
+        hook.run(&reading()).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1), "waited {:?}", start.elapsed());
+        assert!(! path.exists());
+
+        hook.finish().await;
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "612\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "config")]
+    #[tokio::test]
+    async fn runs_alerts_without_waiting() {
+        let path = std::env::temp_dir().join(format!("aranet-alert-exec-test-{}", std::process::id()));
+        let mut hook = AlertHook::new(format!("sleep 1; echo $ARANET_ALERT_STATE $ARANET_CO2 > {}", path.display()));
+        let event = crate::alerts::AlertEvent {
+            name: "co2>600".to_owned(),
+            device: "office".to_owned(),
+            transition: crate::alerts::Transition::Firing,
+            metric: Metric::Co2,
+            value: 612.0,
+            threshold: 600.0,
+        };
+
+        let start = std::time::Instant::now();
+        hook.run(&reading(), &event).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1), "waited {:?}", start.elapsed());
+
+        hook.finish().await;
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "firing 612\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
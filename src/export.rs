@@ -0,0 +1,183 @@
+//! Converting readings logged with --output into flat records, for `aranet export`.
+//!
+//! Each reading becomes one record with the same columns in every format, so the log can be loaded into a
+//! spreadsheet, pandas, or a database without knowing the nested structure of the JSON output. --fields picks and
+//! orders the columns, for tools that expect an exact layout.
+
+use std::error::Error;
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::filter::DeviceFilter;
+use crate::metric::Metric;
+use crate::output::{self, Logged};
+
+#[derive(Debug, Clone, Copy)]
+enum Column {
+    MeasuredAt,
+    Device,
+    Name,
+    Metric(Metric),
+    Status,
+    Interval,
+}
+
+/// The columns of each record, in order, unless --fields is given
+const COLUMNS: [Column; 10] = [
+    Column::MeasuredAt, Column::Device, Column::Name,
+    Column::Metric(Metric::Co2), Column::Metric(Metric::TemperatureC), Column::Metric(Metric::Humidity),
+    Column::Metric(Metric::Pressure), Column::Metric(Metric::Battery), Column::Status, Column::Interval,
+];
+
+impl Column {
+    fn name(&self) -> &'static str {
+        match self {
+            Column::MeasuredAt => "measured_at",
+            Column::Device => "device",
+            Column::Name => "name",
+            Column::Metric(metric) => metric.name(),
+            Column::Status => "status",
+            Column::Interval => "interval",
+        }
+    }
+
+    /// The value of this column within a reading's record. Metrics are rounded to their --precision.
+    fn value(&self, logged: &Logged) -> Value {
+        match self {
+            Column::MeasuredAt => match logged.measured {
+                #[cfg(feature = "timestamps")]
+                Some(secs) => crate::timestamp::at(secs).map(|stamp| json!(stamp)).unwrap_or(json!(secs)),
+                #[cfg(not(feature = "timestamps"))]
+                Some(secs) => json!(secs),
+                None => Value::Null,
+            },
+            Column::Device => json!(logged.device),
+            Column::Name => json!(logged.name),
+            Column::Metric(metric) => match logged.values.value(*metric) {
+                Some(v) if metric.precision() == 0 => json!(v.round() as i64),
+                Some(v) => {
+                    let scale = 10f64.powi(metric.precision() as i32);
+                    json!((v * scale).round() / scale)
+                },
+                None => Value::Null,
+            },
+            Column::Status => json!(logged.values.status.to_ascii_lowercase()),
+            Column::Interval => json!(logged.values.interval),
+        }
+    }
+}
+
+/// The header line of every column, for --output-format=csv
+pub fn csv_header() -> String {
+    let names: Vec<&str> = COLUMNS.iter().map(|column| column.name()).collect();
+    crate::csv::header(&names, ',')
+}
+
+/// A reading's record of every column, as a CSV line, for --output-format=csv
+pub fn csv_row(logged: &Logged) -> String {
+    let values: Vec<Value> = COLUMNS.iter().map(|column| column.value(logged)).collect();
+    crate::csv::row(&values, ',')
+}
+
+impl FromStr for Column {
+    type Err = String;
+
+    /// Parses a column by its name, or `timestamp` for `measured_at`. Any metric may be a column, such as `temp_f`.
+    fn from_str(s: &str) -> Result<Column, String> {
+        match s.trim() {
+            "measured_at" | "timestamp" => Ok(Column::MeasuredAt),
+            "device" => Ok(Column::Device),
+            "name" => Ok(Column::Name),
+            "status" => Ok(Column::Status),
+            "interval" => Ok(Column::Interval),
+            name => name.parse().map(Column::Metric).map_err(|_| format!(
+                "unknown field {:?}, expected measured_at (or timestamp), device, name, a metric such as co2 or \
+                temp_c, status, or interval", name,
+            )),
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Comma separated values, with a header line
+    Csv,
+    /// Tab separated values, with a header line. The same as csv with a --csv-delimiter of tab
+    Tsv,
+    /// One JSON object per line
+    Jsonl,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct ExportArgs {
+    /// Files of readings logged with --output, as for `aranet stats`. Use `-` for stdin
+    #[arg(value_name = "FILE", required = true)]
+    files: Vec<PathBuf>,
+    /// The format to convert the readings to
+    #[arg(long, value_name = "FORMAT")]
+    to: ExportFormat,
+    /// The columns to export, in order, such as `timestamp,co2,temp_c`. Defaults to every column
+    #[arg(long, value_name = "FIELDS", value_delimiter = ',')]
+    fields: Vec<Column>,
+    /// The character separating CSV fields, such as `;` or `tab`
+    #[arg(long, value_name = "CHAR", default_value = ",", value_parser = crate::csv::parse_delimiter)]
+    csv_delimiter: char,
+    /// Leave out the CSV header line
+    #[arg(long)]
+    no_header: bool,
+    /// Only export readings measured within this long before now: seconds, or a duration such as `7d`
+    #[arg(long, value_name = "DURATION", value_parser = crate::parse_duration)]
+    since: Option<Duration>,
+    /// Write the records to this file, rather than stdout
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+}
+
+pub fn run(args: ExportArgs, filter: DeviceFilter) -> Result<(), Box<dyn Error>> {
+    let mut readings = output::read(&args.files, &filter, args.since)?;
+    // rotated files may be given in any order
+    readings.sort_by_key(|logged| logged.measured);
+
+    let columns = match args.fields.is_empty() {
+        true => &COLUMNS[..],
+        false => &args.fields[..],
+    };
+    let delimiter = match args.to {
+        ExportFormat::Tsv => '\t',
+        _ => args.csv_delimiter,
+    };
+
+    let mut out = String::new();
+    if args.to != ExportFormat::Jsonl && ! args.no_header {
+        let names: Vec<&str> = columns.iter().map(|column| column.name()).collect();
+        out.push_str(&crate::csv::header(&names, delimiter));
+    }
+    for logged in &readings {
+        let values: Vec<Value> = columns.iter().map(|column| column.value(logged)).collect();
+        match args.to {
+            ExportFormat::Csv | ExportFormat::Tsv => out.push_str(&crate::csv::row(&values, delimiter)),
+            ExportFormat::Jsonl => {
+                let object: serde_json::Map<String, Value> = columns.iter()
+                    .map(|column| column.name().to_owned())
+                    .zip(values)
+                    .collect();
+                out.push_str(&serde_json::to_string(&object)?);
+                out.push('\n');
+            },
+        }
+    }
+
+    match &args.output {
+        Some(path) => std::fs::write(path, out).map_err(|e| format!("unable to write {}: {}", path.display(), e))?,
+        None => if let Err(e) = std::io::stdout().lock().write_all(out.as_bytes()) {
+            log::debug!("unable to write to stdout, exiting: {}", e);
+            std::process::exit(1);
+        },
+    }
+    log::info!("exported {} readings", readings.len());
+    Ok(())
+}
@@ -0,0 +1,44 @@
+//! Which devices to listen to, from --device and --ignore
+
+use aranet::DiscoveredAranet;
+#[cfg(feature = "serde_json")]
+use btleplug::platform::PeripheralId;
+
+use crate::alias::Device;
+
+#[derive(Debug, Clone, Default)]
+pub struct DeviceFilter {
+    /// Only listen to these devices. Empty to listen to any.
+    pub only: Vec<Device>,
+    /// Never listen to these devices
+    pub ignore: Vec<Device>,
+}
+
+impl DeviceFilter {
+    /// Resolves the --device and --ignore arguments, which may be addresses or aliases
+    pub fn new(only: &[String], ignore: &[String]) -> Result<DeviceFilter, String> {
+        let resolve = |devices: &[String]| devices.iter()
+            .map(|d| crate::alias::resolve(d))
+            .collect::<Result<Vec<Device>, String>>();
+        Ok(DeviceFilter { only: resolve(only)?, ignore: resolve(ignore)? })
+    }
+
+    /// Whether advertisements from this device should be used. Devices given by serial number only match once
+    /// --registry has read the device's serial number.
+    pub fn matches(&self, adv: &DiscoveredAranet) -> bool {
+        (self.only.is_empty() || self.only.iter().any(|dev| dev.matches(adv)))
+            && ! self.ignore.iter().any(|dev| dev.matches(adv))
+    }
+
+    /// Whether any device is given by serial number
+    pub fn by_serial(&self) -> bool {
+        self.only.iter().chain(&self.ignore).any(|dev| matches!(dev, Device::Serial(_)))
+    }
+
+    /// Whether readings from this peripheral should be used, such as those logged to an --output file
+    #[cfg(feature = "serde_json")]
+    pub fn matches_id(&self, id: &PeripheralId) -> bool {
+        (self.only.is_empty() || self.only.iter().any(|dev| dev.matches_id(id)))
+            && ! self.ignore.iter().any(|dev| dev.matches_id(id))
+    }
+}
@@ -0,0 +1,147 @@
+//! Checks whether a device's firmware is older than the latest known release, for `aranet firmware check`.
+//!
+//! The latest releases are bundled in [`LATEST`], rather than fetched, as SAF doesn't publish them anywhere machine
+//! readable. Updates themselves are done through the Aranet Home app.
+
+use std::error::Error;
+use std::time::Duration;
+
+use aranet::{DiscoveredAranet, Version};
+use btleplug::api::Peripheral;
+use btleplug::platform::Manager;
+use futures::StreamExt;
+
+use crate::filter::DeviceFilter;
+
+/// How long to wait for the device to advertise, if no --timeout is given
+const DEFAULT_TIMEOUT: f64 = 30.0;
+
+/// The latest known Aranet4 firmware for each hardware revision. `None` matches revisions not listed, including when
+/// it couldn't be read.
+const LATEST: &[(Option<&str>, Version)] = &[
+    (None, Version::new(1, 4, 19)),
+];
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct FirmwareArgs {
+    #[command(subcommand)]
+    command: FirmwareCommand,
+}
+
+#[derive(clap::Subcommand, Debug, Clone)]
+enum FirmwareCommand {
+    /// Compare a device's firmware (select it with --device) against the latest known release for its hardware
+    /// revision, and whether an update (DFU) is advisable
+    Check {
+        /// Compare against this version rather than the bundled latest release, such as one announced since
+        #[arg(long, value_name = "VERSION")]
+        latest: Option<Version>,
+    },
+}
+
+#[cfg_attr(feature = "serde_json", derive(serde::Serialize))]
+struct Report {
+    device: String,
+    /// Read from the device, if it could be connected to
+    hardware_revision: Option<String>,
+    /// Read from the device, or as advertised if it couldn't be connected to
+    firmware: Version,
+    latest: Version,
+    update_available: bool,
+    /// Whether the device is currently being updated
+    dfu_active: bool,
+}
+
+/// The latest known firmware for hardware revisions that aren't listed
+pub fn latest_known() -> Version {
+    LATEST.iter()
+        .find(|(revision, _)| revision.is_none())
+        .map(|(_, version)| *version)
+        .expect("no latest firmware for unlisted hardware revisions")
+}
+
+/// Reads the hardware revision and firmware version, or `None` if the device can't be connected to, such as when
+/// it isn't paired
+async fn read_revisions(adv: &DiscoveredAranet) -> Option<(String, Version)> {
+    let _lock = crate::lock::acquire().await.map_err(|e| log::warn!("{}", e)).ok()?;
+    let aranet = crate::retry::policy().run("connect", || adv.upgrade()).await
+        .map_err(|e| log::warn!("unable to connect to {}: {}", adv.peripheral_id, e)).ok()?;
+    let revisions = async {
+        let hardware = aranet.hardware_revision().await?;
+        let firmware = aranet.version().await?;
+        Ok::<_, btleplug::Error>((hardware, firmware))
+    }.await;
+    if let Err(e) = aranet.as_ref().disconnect().await {
+        log::debug!("unable to disconnect from {}: {}", adv.peripheral_id, e);
+    }
+    let (hardware, firmware) = revisions.map_err(|e| log::warn!("unable to read revisions of {}: {}", adv.peripheral_id, e)).ok()?;
+    let firmware = firmware.parse().map_err(|e| log::warn!("{}", e)).ok()?;
+    Some((hardware.trim_matches(|c: char| c.is_whitespace() || c == '\0').to_owned(), firmware))
+}
+
+/// Checks the first device matching the filter, waiting at most `timeout` seconds for it to advertise
+pub async fn run(args: FirmwareArgs, filter: DeviceFilter, timeout: Option<f64>, json: bool) -> Result<(), Box<dyn Error>> {
+    let FirmwareCommand::Check { latest } = args.command;
+
+    let manager = Manager::new().await?;
+    let mut discovered = crate::lock::discover(&manager).await?;
+
+    let timeout = timeout.unwrap_or(DEFAULT_TIMEOUT);
+    let find = async {
+        while let Some(adv) = discovered.next().await {
+            if filter.matches(&adv) {
+                return Some(adv);
+            }
+        }
+        None
+    };
+    let adv = match tokio::time::timeout(Duration::from_secs_f64(timeout), find).await {
+        Ok(Some(adv)) => adv,
+        Ok(None) => return Err("Unable to discover devices. No Bluetooth adapters present.".into()),
+        Err(_) => return Err(format!("No advertisement received within {}s.", timeout).into()),
+    };
+
+    let (hardware_revision, firmware) = match read_revisions(&adv).await {
+        Some((hardware, firmware)) => (Some(hardware), firmware),
+        None => {
+            log::warn!("using the advertised firmware version, and the latest release for any hardware revision");
+            (None, adv.manufacturer_data.version)
+        },
+    };
+    let latest = latest.unwrap_or_else(|| {
+        LATEST.iter()
+            .find(|(revision, _)| revision.is_some() && *revision == hardware_revision.as_deref())
+            .map(|(_, version)| *version)
+            .unwrap_or_else(latest_known)
+    });
+
+    let report = Report {
+        device: crate::alias::display(&adv),
+        hardware_revision,
+        firmware,
+        latest,
+        update_available: firmware < latest,
+        dfu_active: adv.manufacturer_data.dfu_active,
+    };
+    if json {
+        #[cfg(feature = "serde_json")]
+        println!("{}", serde_json::to_string_pretty(&report).expect("unable to serialize firmware check as JSON"));
+    } else {
+        print_report(&report);
+    }
+    Ok(())
+}
+
+fn print_report(report: &Report) {
+    println!("Device: {}", report.device);
+    println!("Hardware Revision: {}", report.hardware_revision.as_deref().unwrap_or("unknown"));
+    println!("Firmware: {}", report.firmware);
+    println!("Latest Known Firmware: {}", report.latest);
+    if report.dfu_active {
+        println!("The device is currently being updated");
+    } else if report.update_available {
+        println!("An update is advisable, through the Aranet Home app");
+    } else {
+        println!("Up to date");
+    }
+}
@@ -0,0 +1,275 @@
+//! Known payloads, with what each should be parsed as, for tests of this crate and of code built on it.
+//!
+//! Enable the `test-fixtures` feature to use them from another crate's tests:
+//!
+//! ```toml
+//! [dev-dependencies]
+//! aranet = { version = "0.2", features = ["test-fixtures"] }
+//! ```
+//!
+//! The payloads are encoded following the layouts parsed here, as documented by the
+//! [Aranet4-Python](https://github.com/Anrijs/Aranet4-Python) project, covering the advertisement flags, firmware
+//! versions with and without Smart Home integrations, and unavailable sensor values. They weren't captured from
+//! devices, so each description starts with `synthetic`. Captures (such as from `aranet record` or `aranet gatt-dump`)
+//! are welcome alongside them.
+
+use std::time::Duration;
+
+use crate::{
+    AdvertisementData, Appearance, CalibrationState, CurrentReading, CurrentReadingDetailed, DisplayStatus,
+    ManufacturerData, ParseError, PreferredConnectionParameters, SystemId, Version,
+};
+
+/// A payload, and what it should be parsed as
+#[derive(Debug, Clone, Copy)]
+pub struct Fixture<T: 'static> {
+    /// What the payload is of, such as `synthetic v1.4.19, with Smart Home integrations enabled`
+    pub description: &'static str,
+    pub data: &'static [u8],
+    pub parsed: T,
+}
+
+const fn flags(
+    disconnected: bool, calibration_state: CalibrationState, dfu_active: bool, integrations: bool, version: Version,
+) -> ManufacturerData {
+    ManufacturerData { disconnected, calibration_state, dfu_active, integrations, version }
+}
+
+/// The manufacturer data of advertisements, sent under [`crate::uuids::MANUFACTURER_ID`]
+pub const ADVERTISEMENTS: &[Fixture<AdvertisementData>] = &[
+    Fixture {
+        description: "synthetic v1.4.19, with Smart Home integrations enabled",
+        data: &[
+            0x20, 0x13, 0x04, 0x01, 0x00, 0x0c, 0x0f, 0x01,
+            0x64, 0x02, 0xc9, 0x01, 0x8b, 0x27, 0x29, 0x57, 0x01, 0x2c, 0x01, 0x2a, 0x00,
+        ],
+        parsed: AdvertisementData {
+            manufacturer_data: flags(false, CalibrationState::NotActive, false, true, Version::new(1, 4, 19)),
+            current_reading: Some(CurrentReadingDetailed {
+                co2_ppm: Some(612),
+                temperature_c: Some(22.85),
+                pressure_hpa: Some(1012.3),
+                humidity: 0.41,
+                battery: 0.87,
+                status: DisplayStatus::Green,
+                interval: 300,
+                age: 42,
+            }),
+        },
+    },
+    Fixture {
+        description: "synthetic v1.2.0, with Smart Home integrations disabled, so without a reading",
+        data: &[0x00, 0x00, 0x02, 0x01, 0x00, 0x0c, 0x0f],
+        parsed: AdvertisementData {
+            manufacturer_data: flags(false, CalibrationState::NotActive, false, false, Version::new(1, 2, 0)),
+            current_reading: None,
+        },
+    },
+    Fixture {
+        description: "synthetic v1.3.5, red CO2 status and low battery, with a byte after the reading, which is ignored",
+        data: &[
+            0x20, 0x05, 0x03, 0x01, 0x00, 0x0c, 0x0f, 0x01,
+            0x66, 0x08, 0xe0, 0x01, 0x03, 0x27, 0x37, 0x0c, 0x03, 0x3c, 0x00, 0x05, 0x00,
+            0x07,
+        ],
+        parsed: AdvertisementData {
+            manufacturer_data: flags(false, CalibrationState::NotActive, false, true, Version::new(1, 3, 5)),
+            current_reading: Some(CurrentReadingDetailed {
+                co2_ppm: Some(2150),
+                temperature_c: Some(24.0),
+                pressure_hpa: Some(998.7),
+                humidity: 0.55,
+                battery: 0.12,
+                status: DisplayStatus::Red,
+                interval: 60,
+                age: 5,
+            }),
+        },
+    },
+    Fixture {
+        description: "synthetic v1.4.19, with calibration in progress, and CO2, temperature, and pressure marked unavailable",
+        data: &[
+            0x28, 0x13, 0x04, 0x01, 0x00, 0x0c, 0x0f, 0x01,
+            0x00, 0x80, 0x00, 0x40, 0x00, 0x80, 0x28, 0x64, 0x02, 0x78, 0x00, 0x00, 0x00,
+        ],
+        parsed: AdvertisementData {
+            manufacturer_data: flags(false, CalibrationState::InProgress, false, true, Version::new(1, 4, 19)),
+            current_reading: Some(CurrentReadingDetailed {
+                co2_ppm: None,
+                temperature_c: None,
+                pressure_hpa: None,
+                humidity: 0.40,
+                battery: 1.0,
+                status: DisplayStatus::Yellow,
+                interval: 120,
+                age: 0,
+            }),
+        },
+    },
+    Fixture {
+        description: "synthetic v1.4.4, disconnected and with a firmware update active, measuring every 10 minutes",
+        data: &[
+            0x31, 0x04, 0x04, 0x01, 0x00, 0x0c, 0x0f, 0x01,
+            0xd2, 0x04, 0x90, 0x01, 0x10, 0x27, 0x32, 0x42, 0x02, 0x58, 0x02, 0x57, 0x02,
+        ],
+        parsed: AdvertisementData {
+            manufacturer_data: flags(true, CalibrationState::NotActive, true, true, Version::new(1, 4, 4)),
+            current_reading: Some(CurrentReadingDetailed {
+                co2_ppm: Some(1234),
+                temperature_c: Some(20.0),
+                pressure_hpa: Some(1000.0),
+                humidity: 0.50,
+                battery: 0.66,
+                status: DisplayStatus::Yellow,
+                interval: 600,
+                age: 599,
+            }),
+        },
+    },
+];
+
+/// Manufacturer data that isn't a valid advertisement, and the error parsing it fails with
+pub const INVALID_ADVERTISEMENTS: &[Fixture<ParseError>] = &[
+    Fixture {
+        description: "synthetic v1.4.19, with an unknown CO2 status of 0",
+        data: &[
+            0x20, 0x13, 0x04, 0x01, 0x00, 0x0c, 0x0f, 0x01,
+            0x64, 0x02, 0xc9, 0x01, 0x8b, 0x27, 0x29, 0x57, 0x00, 0x2c, 0x01, 0x2a, 0x00,
+        ],
+        parsed: ParseError::UnknownStatus(0),
+    },
+    Fixture {
+        description: "synthetic v1.4.19, with an unknown CO2 status of 4",
+        data: &[
+            0x20, 0x13, 0x04, 0x01, 0x00, 0x0c, 0x0f, 0x01,
+            0x64, 0x02, 0xc9, 0x01, 0x8b, 0x27, 0x29, 0x57, 0x04, 0x2c, 0x01, 0x2a, 0x00,
+        ],
+        parsed: ParseError::UnknownStatus(4),
+    },
+    Fixture {
+        description: "synthetic advertisement cut short within the firmware version",
+        data: &[0x20, 0x13, 0x04],
+        parsed: ParseError::TooShort { expected: 7, received: 3 },
+    },
+];
+
+/// Values of [`crate::uuids::AR4_READ_CURRENT_READINGS`]
+pub const CURRENT_READINGS: &[Fixture<CurrentReading>] = &[
+    Fixture {
+        description: "synthetic green CO2 status",
+        data: &[0x64, 0x02, 0xc9, 0x01, 0x8b, 0x27, 0x29, 0x57, 0x01],
+        parsed: CurrentReading {
+            co2_ppm: Some(612),
+            temperature_c: Some(22.85),
+            pressure_hpa: Some(1012.3),
+            humidity: 0.41,
+            battery: 0.87,
+            status: DisplayStatus::Green,
+        },
+    },
+];
+
+/// Values of [`crate::uuids::AR4_READ_CURRENT_READINGS_DET`]
+pub const CURRENT_READINGS_DETAILED: &[Fixture<CurrentReadingDetailed>] = &[
+    Fixture {
+        description: "synthetic red CO2 status, measuring every minute",
+        data: &[0x66, 0x08, 0xe0, 0x01, 0x03, 0x27, 0x37, 0x0c, 0x03, 0x3c, 0x00, 0x05, 0x00],
+        parsed: CurrentReadingDetailed {
+            co2_ppm: Some(2150),
+            temperature_c: Some(24.0),
+            pressure_hpa: Some(998.7),
+            humidity: 0.55,
+            battery: 0.12,
+            status: DisplayStatus::Red,
+            interval: 60,
+            age: 5,
+        },
+    },
+];
+
+/// Values of [`crate::uuids::COMMON_READ_SW_REV`], the firmware version
+pub const SOFTWARE_REVISIONS: &[Fixture<Version>] = &[
+    Fixture { description: "synthetic v1.4.19", data: b"v1.4.19", parsed: Version::new(1, 4, 19) },
+    Fixture { description: "synthetic v1.2.0, NUL terminated", data: b"v1.2.0\0", parsed: Version::new(1, 2, 0) },
+];
+
+/// Values of [`crate::uuids::COMMON_SYSTEM_ID`]
+pub const SYSTEM_IDS: &[Fixture<SystemId>] = &[
+    Fixture {
+        description: "synthetic 001122-3344556677",
+        data: &[0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11, 0x00],
+        parsed: SystemId { manufacturer_identifier: 0x33_4455_6677, organizationally_unique_identifier: 0x00_1122 },
+    },
+];
+
+/// Values of [`crate::uuids::COMMON_APPEARANCE`]
+pub const APPEARANCES: &[Fixture<Appearance>] = &[
+    Fixture { description: "synthetic unknown", data: &[0x00, 0x00], parsed: Appearance { category: 0, subcategory: 0 } },
+    Fixture { description: "synthetic generic sensor", data: &[0x40, 0x05], parsed: Appearance { category: 21, subcategory: 0 } },
+];
+
+/// Values of [`crate::uuids::COMMON_PREFERRED_CONNECT_PARAMS`]
+pub const PREFERRED_CONNECTION_PARAMETERS: &[Fixture<PreferredConnectionParameters>] = &[
+    Fixture {
+        description: "synthetic 30ms to 50ms intervals, with a 4s supervision timeout",
+        data: &[0x18, 0x00, 0x28, 0x00, 0x00, 0x00, 0x90, 0x01],
+        parsed: PreferredConnectionParameters {
+            min_interval: Some(Duration::from_millis(30)),
+            max_interval: Some(Duration::from_millis(50)),
+            peripheral_latency: 0,
+            supervision_timeout: Some(Duration::from_secs(4)),
+        },
+    },
+    Fixture {
+        description: "synthetic no preferences",
+        data: &[0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0xff, 0xff],
+        parsed: PreferredConnectionParameters {
+            min_interval: None,
+            max_interval: None,
+            peripheral_latency: 0,
+            supervision_timeout: None,
+        },
+    },
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs `parse` on each fixture's payload, with the fixture's description in any failure
+    fn check<T: PartialEq + std::fmt::Debug, const N: usize>(fixtures: &[Fixture<T>], parse: impl Fn([u8; N]) -> T) {
+        for fixture in fixtures {
+            let data = fixture.data.try_into()
+                .unwrap_or_else(|_| panic!("{}: expected {} bytes, not {}", fixture.description, N, fixture.data.len()));
+            assert_eq!(parse(data), fixture.parsed, "{}", fixture.description);
+        }
+    }
+
+    #[test]
+    fn advertisements() {
+        for fixture in ADVERTISEMENTS {
+            assert_eq!(AdvertisementData::parse(fixture.data), Ok(fixture.parsed), "{}", fixture.description);
+        }
+        for fixture in INVALID_ADVERTISEMENTS {
+            assert_eq!(AdvertisementData::parse(fixture.data), Err(fixture.parsed.clone()), "{}", fixture.description);
+        }
+    }
+
+    #[test]
+    fn characteristics() {
+        check(CURRENT_READINGS, |d| CurrentReading::parse(d).unwrap());
+        check(CURRENT_READINGS_DETAILED, |d| CurrentReadingDetailed::parse(d).unwrap());
+        check(SYSTEM_IDS, SystemId::parse);
+        check(APPEARANCES, Appearance::parse);
+        check(PREFERRED_CONNECTION_PARAMETERS, PreferredConnectionParameters::parse);
+        for fixture in SOFTWARE_REVISIONS {
+            let version = std::str::from_utf8(fixture.data).unwrap().parse::<Version>();
+            assert_eq!(version, Ok(fixture.parsed), "{}", fixture.description);
+        }
+    }
+
+    #[test]
+    fn system_id_display() {
+        assert_eq!(Some(SYSTEM_IDS[0].parsed.to_string().as_str()), SYSTEM_IDS[0].description.strip_prefix("synthetic "));
+    }
+}
@@ -0,0 +1,176 @@
+//! Connects to a device and dumps every GATT service and characteristic, reading those that are readable.
+//!
+//! This is the data needed to add support for new firmware versions or products. btleplug does not expose
+//! descriptors, so they are not included.
+
+use std::error::Error;
+use std::time::Duration;
+
+use aranet::{uuids, Appearance, DiscoveredAranet, PreferredConnectionParameters, SystemId};
+use btleplug::api::{CharPropFlags, Peripheral};
+use btleplug::platform::Manager;
+use futures::StreamExt;
+use uuid::Uuid;
+
+use crate::filter::DeviceFilter;
+
+/// How long to wait for the device to advertise, if no --timeout is given
+const DEFAULT_TIMEOUT: f64 = 30.0;
+
+const PROPERTIES: [(CharPropFlags, &str); 8] = [
+    (CharPropFlags::BROADCAST, "broadcast"),
+    (CharPropFlags::READ, "read"),
+    (CharPropFlags::WRITE_WITHOUT_RESPONSE, "write_without_response"),
+    (CharPropFlags::WRITE, "write"),
+    (CharPropFlags::NOTIFY, "notify"),
+    (CharPropFlags::INDICATE, "indicate"),
+    (CharPropFlags::AUTHENTICATED_SIGNED_WRITES, "authenticated_signed_writes"),
+    (CharPropFlags::EXTENDED_PROPERTIES, "extended_properties"),
+];
+
+#[cfg_attr(feature = "serde_json", derive(serde::Serialize))]
+struct CharacteristicDump {
+    uuid: String,
+    name: Option<&'static str>,
+    properties: Vec<&'static str>,
+    /// Hex dump of the value, if it was read
+    value: Option<String>,
+    /// The value as text, if it is printable UTF-8
+    text: Option<String>,
+    /// The value as parsed by the library, for characteristics with a typed read
+    decoded: Option<String>,
+    error: Option<String>,
+}
+
+#[cfg_attr(feature = "serde_json", derive(serde::Serialize))]
+struct ServiceDump {
+    uuid: String,
+    name: Option<&'static str>,
+    primary: bool,
+    characteristics: Vec<CharacteristicDump>,
+}
+
+#[cfg_attr(feature = "serde_json", derive(serde::Serialize))]
+struct Report<'a> {
+    device: String,
+    advertisement: &'a DiscoveredAranet,
+    services: Vec<ServiceDump>,
+}
+
+fn format_uuid(uuid: &str, name: Option<&str>) -> String {
+    match name {
+        Some(name) => format!("{} ({})", uuid, name),
+        None => uuid.to_string(),
+    }
+}
+
+/// Parses the value of a characteristic with a typed read, such as the preferred connection parameters
+fn decode(uuid: Uuid, value: &[u8]) -> Option<String> {
+    match uuid {
+        uuids::COMMON_SYSTEM_ID => value.try_into().ok().map(|v| SystemId::parse(v).to_string()),
+        uuids::COMMON_APPEARANCE => value.try_into().ok().map(|v| Appearance::parse(v).to_string()),
+        uuids::COMMON_PREFERRED_CONNECT_PARAMS => {
+            value.try_into().ok().map(|v| PreferredConnectionParameters::parse(v).to_string())
+        },
+        _ => None,
+    }
+}
+
+/// Dumps the first device matching the filter, waiting at most `timeout` seconds for it to advertise
+pub async fn run(filter: DeviceFilter, timeout: Option<f64>, json: bool) -> Result<(), Box<dyn Error>> {
+    let manager = Manager::new().await?;
+    let mut discovered = crate::lock::discover(&manager).await?;
+
+    let timeout = timeout.unwrap_or(DEFAULT_TIMEOUT);
+    let find = async {
+        while let Some(adv) = discovered.next().await {
+            if filter.matches(&adv) {
+                return Some(adv);
+            }
+        }
+        None
+    };
+    let adv = match tokio::time::timeout(Duration::from_secs_f64(timeout), find).await {
+        Ok(Some(adv)) => adv,
+        Ok(None) => return Err("Unable to discover devices. No Bluetooth adapters present.".into()),
+        Err(_) => return Err(format!("No advertisement received within {}s.", timeout).into()),
+    };
+
+    log::info!("connecting to {}", adv.peripheral_id);
+    // not using DiscoveredAranet::upgrade, as that rejects devices without the known Aranet4 service
+    let _lock = crate::lock::acquire().await?;
+    let periph = adv.peripheral().await?;
+    if ! periph.is_connected().await? {
+        crate::retry::policy().run("connect", || periph.connect()).await?;
+    }
+    periph.discover_services().await?;
+
+    let mut services = Vec::new();
+    for service in periph.services() {
+        let mut characteristics = Vec::new();
+        for ch in &service.characteristics {
+            let (value, error) = match ch.properties.contains(CharPropFlags::READ) {
+                false => (None, None),
+                true => match periph.read(ch).await {
+                    Ok(value) => (Some(value), None),
+                    Err(e) => (None, Some(e.to_string())),
+                },
+            };
+            characteristics.push(CharacteristicDump {
+                uuid: ch.uuid.to_string(),
+                name: uuids::name(ch.uuid),
+                properties: PROPERTIES.iter().filter(|(f, _)| ch.properties.contains(*f)).map(|(_, n)| *n).collect(),
+                text: value.as_ref()
+                    .and_then(|v| String::from_utf8(v.clone()).ok())
+                    .filter(|s| ! s.is_empty() && s.chars().all(|c| ! c.is_control())),
+                decoded: value.as_deref().and_then(|v| decode(ch.uuid, v)),
+                value: value.as_deref().map(crate::hex),
+                error,
+            });
+        }
+        services.push(ServiceDump {
+            uuid: service.uuid.to_string(),
+            name: uuids::name(service.uuid),
+            primary: service.primary,
+            characteristics,
+        });
+    }
+
+    if let Err(e) = periph.disconnect().await {
+        log::debug!("unable to disconnect from {}: {}", adv.peripheral_id, e);
+    }
+
+    let report = Report { device: crate::alias::display(&adv), advertisement: &adv, services };
+    if json {
+        #[cfg(feature = "serde_json")]
+        println!("{}", serde_json::to_string_pretty(&report).expect("unable to serialize GATT dump as JSON"));
+    } else {
+        print_report(&report);
+    }
+    Ok(())
+}
+
+fn print_report(report: &Report) {
+    println!("Device: {}", report.device);
+    println!("Advertised Firmware: {}", report.advertisement.manufacturer_data.version);
+    println!("Raw Manufacturer Data: {}", crate::hex(&report.advertisement.raw_manufacturer_data));
+    for service in &report.services {
+        println!();
+        println!("Service {}{}", format_uuid(&service.uuid, service.name), if service.primary { "" } else { " (secondary)" });
+        for ch in &service.characteristics {
+            println!("  Characteristic {} [{}]", format_uuid(&ch.uuid, ch.name), ch.properties.join(", "));
+            if let Some(value) = &ch.value {
+                println!("    Value: {}", if value.is_empty() { "<empty>" } else { value });
+            }
+            if let Some(text) = &ch.text {
+                println!("    Text: {:?}", text);
+            }
+            if let Some(decoded) = &ch.decoded {
+                println!("    Decoded: {}", decoded);
+            }
+            if let Some(error) = &ch.error {
+                println!("    Read Error: {}", error);
+            }
+        }
+    }
+}
@@ -0,0 +1,85 @@
+//! Pushes readings to Graphite (Carbon) using the plaintext protocol.
+//!
+//! Each value is sent as `<prefix>.<device>.<metric> <value> <timestamp>`, where the device is its alias (or
+//! peripheral ID) with characters other than letters, digits, `-` and `_` replaced by `_`, and the timestamp is when
+//! the reading was measured. Device tags are appended in the tagged format of Graphite 1.1, as `;key=value`.
+//!
+//! Protocol reference: https://graphite.readthedocs.io/en/latest/feeding-carbon.html#the-plaintext-protocol
+
+use std::io;
+use std::time::{Duration, UNIX_EPOCH};
+
+use aranet::DiscoveredAranet;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+
+use crate::metric::Metric;
+use crate::seen::SeenMeasurements;
+
+/// Default port of the Carbon plaintext receiver
+pub const DEFAULT_PORT: u16 = 2003;
+
+/// The metrics sent for each reading
+const METRICS: [Metric; 7] = [
+    Metric::Co2, Metric::TemperatureC, Metric::Humidity, Metric::Pressure, Metric::Battery, Metric::Status, Metric::Age,
+];
+
+pub struct GraphiteSender {
+    /// `host:port` of the Carbon receiver
+    server: String,
+    prefix: String,
+    /// To only send each measurement once
+    seen: SeenMeasurements,
+}
+
+/// Replaces characters that would split or break a metric path
+fn path_component(s: &str) -> String {
+    s.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' }).collect()
+}
+
+impl GraphiteSender {
+    /// Creates a new sender. If `server` does not contain a port, [`DEFAULT_PORT`] is used.
+    pub fn new(server: &str, prefix: String) -> GraphiteSender {
+        let has_port = match server.rsplit_once(':') {
+            // bare IPv6 addresses contain colons, but are bracketed when a port is included
+            Some((host, port)) => port.parse::<u16>().is_ok() && (!host.contains(':') || host.starts_with('[')),
+            None => false,
+        };
+        let server = if has_port { server.to_owned() } else { format!("{}:{}", server, DEFAULT_PORT) };
+        let prefix = prefix.trim_matches('.').to_owned();
+        GraphiteSender { server, prefix, seen: SeenMeasurements::new() }
+    }
+
+    /// Plaintext protocol lines for a reading. Unavailable values (eg: CO2 during calibration) are skipped.
+    fn lines(&self, adv: &DiscoveredAranet) -> String {
+        let Some(r) = adv.current_reading else { return String::new() };
+        let measured = adv.received.checked_sub(Duration::from_secs(r.age as u64)).unwrap_or(adv.received);
+        let timestamp = measured.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let device = path_component(&crate::alias::display(adv));
+        let tags: String = crate::tags::of(adv).iter()
+            .map(|(key, value)| format!(";{}={}", key, path_component(value)))
+            .collect();
+
+        METRICS.iter()
+            .filter_map(|metric| {
+                let value = metric.format(&r)?;
+                Some(match self.prefix.is_empty() {
+                    true => format!("{}.{}{} {} {}\n", device, metric, tags, value, timestamp),
+                    false => format!("{}.{}.{}{} {} {}\n", self.prefix, device, metric, tags, value, timestamp),
+                })
+            })
+            .collect()
+    }
+
+    /// Sends the advertisement's reading to Carbon, if it's a new measurement
+    pub async fn send(&mut self, adv: &DiscoveredAranet) -> io::Result<()> {
+        if adv.current_reading.is_none() || ! self.seen.is_new(adv) {
+            return Ok(());
+        }
+        let lines = self.lines(adv);
+        log::debug!("sending {} metrics to graphite {}", lines.lines().count(), self.server);
+        let mut stream = TcpStream::connect(&self.server).await?;
+        stream.write_all(lines.as_bytes()).await?;
+        stream.shutdown().await
+    }
+}
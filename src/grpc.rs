@@ -0,0 +1,250 @@
+//! A gRPC server exposing the latest reading from each Aranet4, and streaming new ones, for `aranet grpc`.
+//!
+//! The service is defined in `proto/aranet.proto`, for integrators to generate clients from:
+//!
+//! | RPC             | returns                                          |
+//! |-----------------|--------------------------------------------------|
+//! | `GetCurrent`    | the latest reading of a device                   |
+//! | `ListDevices`   | every device heard, with its latest reading      |
+//! | `WatchReadings` | a stream of new readings, optionally per device  |
+//!
+//! The server side is generated by build.rs without protoc, so the messages below are written by hand, and have to
+//! be kept in sync with the .proto.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
+
+use aranet::{DiscoveredAranet, DisplayStatus};
+use btleplug::platform::Manager;
+use futures::{Stream, StreamExt};
+use tokio::sync::broadcast;
+use tonic::{Request, Response, Status};
+
+use crate::filter::DeviceFilter;
+use crate::seen::SeenMeasurements;
+
+mod generated {
+    include!(concat!(env!("OUT_DIR"), "/aranet.v1.Aranet.rs"));
+}
+use generated::aranet_server::{Aranet, AranetServer};
+
+/// New readings buffered for each `WatchReadings` call, beyond which a slow client misses some
+const BUFFER: usize = 256;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct GrpcArgs {
+    /// The address to listen on, such as `:50051` for every interface, or `127.0.0.1:50051`
+    #[arg(long, value_name = "ADDR", default_value = ":50051", value_parser = crate::parse_addr)]
+    listen: SocketAddr,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Reading {
+    #[prost(string, tag = "1")]
+    pub device: String,
+    #[prost(string, tag = "2")]
+    pub name: String,
+    #[prost(uint32, optional, tag = "3")]
+    pub co2_ppm: Option<u32>,
+    #[prost(double, optional, tag = "4")]
+    pub temperature_c: Option<f64>,
+    #[prost(double, tag = "5")]
+    pub humidity_percent: f64,
+    #[prost(double, optional, tag = "6")]
+    pub pressure_hpa: Option<f64>,
+    #[prost(double, tag = "7")]
+    pub battery_percent: f64,
+    #[prost(enumeration = "ReadingStatus", tag = "8")]
+    pub status: i32,
+    #[prost(uint32, tag = "9")]
+    pub interval_seconds: u32,
+    #[prost(int64, tag = "10")]
+    pub measured: i64,
+}
+
+/// `Status` in the .proto, renamed so it isn't mistaken for [`tonic::Status`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum ReadingStatus {
+    Unspecified = 0,
+    Green = 1,
+    Yellow = 2,
+    Red = 3,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Device {
+    #[prost(string, tag = "1")]
+    pub device: String,
+    #[prost(string, tag = "2")]
+    pub name: String,
+    #[prost(string, tag = "3")]
+    pub firmware: String,
+    #[prost(message, optional, tag = "4")]
+    pub latest: Option<Reading>,
+    #[prost(int64, tag = "5")]
+    pub last_seen: i64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GetCurrentRequest {
+    #[prost(string, tag = "1")]
+    pub device: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListDevicesRequest {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListDevicesResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub devices: Vec<Device>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct WatchReadingsRequest {
+    #[prost(string, repeated, tag = "1")]
+    pub devices: Vec<String>,
+}
+
+/// Converts to a double with the same shortest decimal representation, so 1019.7 isn't sent as 1019.7000122
+fn widen(x: f32) -> f64 {
+    x.to_string().parse().unwrap_or(f64::NAN)
+}
+
+/// Seconds since the Unix epoch
+fn unix(time: std::time::SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+impl Reading {
+    /// The advertisement's reading, if it has one
+    fn new(adv: &DiscoveredAranet) -> Option<Reading> {
+        let reading = adv.current_reading?;
+        let measured = adv.received.checked_sub(Duration::from_secs(reading.age as u64)).unwrap_or(adv.received);
+        let status = match reading.status {
+            DisplayStatus::Green => ReadingStatus::Green,
+            DisplayStatus::Yellow => ReadingStatus::Yellow,
+            DisplayStatus::Red => ReadingStatus::Red,
+        };
+        Some(Reading {
+            device: adv.peripheral_id.to_string(),
+            name: crate::alias::display(adv),
+            co2_ppm: reading.co2_ppm.map(u32::from),
+            temperature_c: reading.temperature_c.map(widen),
+            humidity_percent: widen(reading.humidity * 100.0),
+            pressure_hpa: reading.pressure_hpa.map(widen),
+            battery_percent: widen(reading.battery * 100.0),
+            status: status as i32,
+            interval_seconds: reading.interval.into(),
+            measured: unix(measured),
+        })
+    }
+}
+
+type ReadingStream = Pin<Box<dyn Stream<Item = Result<Reading, Status>> + Send>>;
+
+struct Service {
+    /// The latest advertisement from each device, by peripheral ID
+    devices: Arc<Mutex<BTreeMap<String, DiscoveredAranet>>>,
+    /// Each new measurement
+    readings: broadcast::Sender<DiscoveredAranet>,
+}
+
+#[tonic::async_trait]
+impl Aranet for Service {
+    async fn get_current(&self, request: Request<GetCurrentRequest>) -> Result<Response<Reading>, Status> {
+        let requested = &request.get_ref().device;
+        let device = crate::alias::resolve(requested).map_err(Status::invalid_argument)?;
+        let devices = self.devices.lock().unwrap();
+        let adv = devices.values().find(|adv| device.matches(adv))
+            .ok_or_else(|| Status::not_found(format!("{} hasn't been heard yet", requested)))?;
+        let reading = Reading::new(adv).ok_or_else(|| Status::failed_precondition(format!(
+            "{}'s advertisements don't include readings. Enable Smart Home integrations in the Aranet Home app",
+            requested,
+        )))?;
+        Ok(Response::new(reading))
+    }
+
+    async fn list_devices(&self, _: Request<ListDevicesRequest>) -> Result<Response<ListDevicesResponse>, Status> {
+        let devices = self.devices.lock().unwrap().iter()
+            .map(|(id, adv)| Device {
+                device: id.clone(),
+                name: crate::alias::display(adv),
+                firmware: adv.manufacturer_data.version.to_string(),
+                latest: Reading::new(adv),
+                last_seen: unix(adv.received),
+            })
+            .collect();
+        Ok(Response::new(ListDevicesResponse { devices }))
+    }
+
+    type WatchReadingsStream = ReadingStream;
+
+    async fn watch_readings(&self, request: Request<WatchReadingsRequest>) -> Result<Response<ReadingStream>, Status> {
+        let watched = request.get_ref().devices.iter()
+            .map(|device| crate::alias::resolve(device))
+            .collect::<Result<Vec<crate::alias::Device>, String>>()
+            .map_err(Status::invalid_argument)?;
+        let readings = futures::stream::unfold(self.readings.subscribe(), move |mut readings| {
+            let watched = watched.clone();
+            async move {
+                loop {
+                    match readings.recv().await {
+                        Ok(adv) if watched.is_empty() || watched.iter().any(|device| device.matches(&adv)) => {
+                            let Some(reading) = Reading::new(&adv) else { continue };
+                            return Some((Ok(reading), readings));
+                        },
+                        Ok(_) => {},
+                        Err(broadcast::error::RecvError::Lagged(missed)) => {
+                            log::warn!("client fell behind, skipped {} readings", missed);
+                        },
+                        Err(broadcast::error::RecvError::Closed) => return None,
+                    }
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(readings)))
+    }
+}
+
+/// Serves the latest reading of each device matching the filter
+pub async fn run(args: GrpcArgs, filter: DeviceFilter) -> Result<(), Box<dyn Error>> {
+    let manager = Manager::new().await?;
+    let mut discovered = crate::lock::discover(&manager).await?;
+
+    let devices = Arc::new(Mutex::new(BTreeMap::new()));
+    let (readings, _) = broadcast::channel(BUFFER);
+    let service = Service { devices: devices.clone(), readings: readings.clone() };
+    let server = tonic::transport::Server::builder()
+        .add_service(AranetServer::new(service))
+        .serve(args.listen);
+    log::info!("serving gRPC on {}", args.listen);
+
+    let listener = async {
+        let mut seen = SeenMeasurements::new();
+        while let Some(adv) = discovered.next().await {
+            if ! filter.matches(&adv) {
+                continue;
+            }
+            let new = adv.current_reading.is_some() && seen.is_new(&adv);
+            devices.lock().unwrap().insert(adv.peripheral_id.to_string(), adv.clone());
+            if new {
+                // no clients watching isn't an error
+                let _ = readings.send(adv);
+            }
+        }
+    };
+
+    tokio::select! {
+        () = listener => Err("Unable to discover devices. No Bluetooth adapters present.".into()),
+        served = server => match served {
+            Ok(()) => Ok(()),
+            Err(e) => Err(format!("unable to serve gRPC on {}: {}", args.listen, e).into()),
+        },
+    }
+}
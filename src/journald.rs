@@ -0,0 +1,86 @@
+//! Writes readings and alerts to the systemd journal as native entries, with each value as its own field.
+//!
+//! Fields are named the same as the --exec environment variables (eg: `ARANET_DEVICE`, `ARANET_CO2`), so
+//! `journalctl -t aranet -o json` can be queried like a store of readings.
+//!
+//! Protocol reference: https://systemd.io/JOURNAL_NATIVE_PROTOCOL/
+
+use std::io;
+
+use aranet::DiscoveredAranet;
+
+use crate::exec::ExecHook;
+use crate::seen::SeenMeasurements;
+
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+const PRIORITY_WARNING: u8 = 4;
+const PRIORITY_NOTICE: u8 = 5;
+const PRIORITY_INFO: u8 = 6;
+
+pub struct Journald {
+    /// To only log each measurement once
+    seen: SeenMeasurements,
+}
+
+impl Journald {
+    pub fn new() -> Journald {
+        Journald { seen: SeenMeasurements::new() }
+    }
+
+    /// Logs the advertisement's reading, if it's a new measurement
+    pub async fn reading(&mut self, adv: &DiscoveredAranet) -> io::Result<()> {
+        if ! self.seen.is_new(adv) {
+            return Ok(());
+        }
+        let Some(r) = &adv.current_reading else { return Ok(()) };
+
+        let message = format!("Reading from {}: {}", crate::alias::display(adv), r.to_string().replace('\n', ", "));
+        send(PRIORITY_INFO, &message, ExecHook::environment(adv)).await
+    }
+
+    /// Logs an alert from the config file firing or resolving, alongside the reading that caused it
+    #[cfg(feature = "config")]
+    pub async fn alert(&self, adv: &DiscoveredAranet, event: &crate::alerts::AlertEvent) -> io::Result<()> {
+        let priority = match event.transition {
+            crate::alerts::Transition::Firing => PRIORITY_WARNING,
+            crate::alerts::Transition::Resolved => PRIORITY_NOTICE,
+        };
+        let mut fields = ExecHook::environment(adv);
+        fields.extend(crate::exec::alert_environment(event));
+        send(priority, &event.to_string(), fields).await
+    }
+}
+
+/// Serializes a journal entry. Values containing newlines use the length-prefixed binary form.
+fn entry(priority: u8, message: &str, fields: Vec<(String, String)>) -> Vec<u8> {
+    let mut buf = Vec::new();
+    let standard = [
+        ("PRIORITY".to_owned(), priority.to_string()),
+        ("SYSLOG_IDENTIFIER".to_owned(), "aranet".to_owned()),
+        ("MESSAGE".to_owned(), message.to_owned()),
+    ];
+    for (key, value) in standard.into_iter().chain(fields) {
+        buf.extend_from_slice(key.as_bytes());
+        if value.contains('\n') {
+            buf.push(b'\n');
+            buf.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            buf.push(b'=');
+        }
+        buf.extend_from_slice(value.as_bytes());
+        buf.push(b'\n');
+    }
+    buf
+}
+
+#[cfg(unix)]
+async fn send(priority: u8, message: &str, fields: Vec<(String, String)>) -> io::Result<()> {
+    let socket = tokio::net::UnixDatagram::unbound()?;
+    socket.send_to(&entry(priority, message, fields), JOURNAL_SOCKET).await.map(|_| ())
+}
+
+#[cfg(not(unix))]
+async fn send(_priority: u8, _message: &str, _fields: Vec<(String, String)>) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "the systemd journal is not available on this platform"))
+}
@@ -0,0 +1,123 @@
+//! Produces readings to Kafka, for --kafka.
+//!
+//! Each new reading is produced to --kafka-topic as the JSON output's object, keyed by the device's serial number
+//! when --registry has read it (or else its peripheral ID), so each device's readings stay in order on one partition.
+//!
+//! Records are batched by librdkafka (see its `linger.ms` and `batch.size`, which can be set with --kafka-option), so
+//! readings are queued without waiting for the brokers. Each delivery, or failure to deliver, is counted under
+//! `--kafka` in the collector's state, and so in `aranet daemon --metrics-file`, once the brokers acknowledge it.
+//! TLS and SASL aren't supported, as librdkafka is built without them.
+
+use std::time::Duration;
+
+use aranet::DiscoveredAranet;
+use rdkafka::config::ClientConfig;
+use rdkafka::error::KafkaError;
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use tokio::task::JoinHandle;
+
+use crate::seen::SeenMeasurements;
+use crate::state::State;
+
+/// How long to wait for queued readings to be delivered when stopping
+const FINISH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Parses a --kafka-option, a librdkafka setting such as `linger.ms=500`
+pub fn parse_option(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if ! key.trim().is_empty() => Ok((key.trim().to_owned(), value.trim().to_owned())),
+        _ => Err(format!("invalid option {:?}, expected KEY=VALUE", s)),
+    }
+}
+
+pub struct KafkaProducer {
+    producer: FutureProducer,
+    topic: String,
+    /// Where deliveries are counted
+    state: State,
+    /// Waiting for the brokers to acknowledge each queued reading, returning whether they did
+    deliveries: Vec<JoinHandle<Result<(), String>>>,
+    /// To only produce each measurement once
+    seen: SeenMeasurements,
+}
+
+impl KafkaProducer {
+    /// Creates a producer for these `host:port` brokers, separated by commas. Brokers are connected to in the
+    /// background, so an unreachable one only fails deliveries.
+    pub fn new(brokers: &str, topic: String, options: &[(String, String)], state: State) -> Result<KafkaProducer, KafkaError> {
+        let mut config = ClientConfig::new();
+        config.set("bootstrap.servers", brokers).set("client.id", "aranet");
+        for (key, value) in options {
+            config.set(key, value);
+        }
+        let producer = config.create()?;
+        Ok(KafkaProducer { producer, topic, state, deliveries: Vec::new(), seen: SeenMeasurements::new() })
+    }
+
+    /// Queues the advertisement's reading, if it's a new measurement. Only fails if it can't be queued, such as when
+    /// the queue is full; deliveries are counted once acknowledged.
+    pub fn reading(&mut self, adv: &DiscoveredAranet) -> Result<(), KafkaError> {
+        if ! self.seen.is_new(adv) {
+            return Ok(());
+        }
+        let payload = serde_json::to_string(&crate::NamedAranet::new(adv)).expect("unable to serialize reading as JSON");
+        let key = crate::registry::serial(adv).unwrap_or_else(|| adv.peripheral_id.to_string());
+        let record = FutureRecord::to(&self.topic).key(&key).payload(&payload);
+        let delivery = self.producer.send_result(record).map_err(|(e, _)| e)?;
+
+        self.deliveries.retain(|delivery| ! delivery.is_finished());
+        let state = self.state.clone();
+        self.deliveries.push(tokio::spawn(async move {
+            let delivered = match delivery.await {
+                Ok(Ok(_)) => Ok(()),
+                Ok(Err((e, _))) => Err(e.to_string()),
+                Err(_) => Err("the producer stopped before it was delivered".to_owned()),
+            };
+            if let Err(e) = &delivered {
+                log::warn!("unable to deliver a reading from {} to --kafka: {}", key, e);
+            }
+            state.sink("--kafka", delivered)
+        }));
+        Ok(())
+    }
+
+    /// Waits for the queued readings to be delivered, returning the first failure
+    pub async fn finish(self) -> Result<(), String> {
+        let deliveries = futures::future::join_all(self.deliveries);
+        let delivered = tokio::time::timeout(FINISH_TIMEOUT, deliveries).await
+            .map_err(|_| format!("readings weren't delivered within {}s", FINISH_TIMEOUT.as_secs()))?;
+        delivered.into_iter().try_for_each(|delivered| delivered.unwrap_or_else(|e| Err(e.to_string())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn counts_failed_deliveries() {
+        let data = [
+            0x20, 0x13, 0x04, 0x01, 0x00, 0x0c, 0x0f, 0x01,
+            0x64, 0x02, 0xc9, 0x01, 0x8b, 0x27, 0x29, 0x57, 0x01, 0x2c, 0x01, 0x2a, 0x00,
+        ];
+        let adv = DiscoveredAranet::parse(None, crate::simulate::peripheral_id(0), &data).unwrap();
+        let state = State::new();
+        // nothing listens on the discard port, so the reading is given up on
+        let options = [("message.timeout.ms".to_owned(), "100".to_owned())];
+        let mut kafka = KafkaProducer::new("127.0.0.1:9", "aranet".to_owned(), &options, state.clone()).unwrap();
+        kafka.reading(&adv).unwrap();
+        // the same measurement again is skipped
+        kafka.reading(&adv).unwrap();
+
+        assert!(kafka.finish().await.is_err());
+        assert!(state.dump().contains("--kafka: 0 sent, 1 errors"), "{}", state.dump());
+    }
+
+    #[test]
+    fn parses_options() {
+        assert_eq!(parse_option("linger.ms=500"), Ok(("linger.ms".to_owned(), "500".to_owned())));
+        assert_eq!(parse_option(" acks = all "), Ok(("acks".to_owned(), "all".to_owned())));
+        assert!(parse_option("acks").is_err());
+        assert!(parse_option("=all").is_err());
+    }
+}
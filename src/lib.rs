@@ -2,14 +2,22 @@
 use std::fmt;
 
 use std::pin::Pin;
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock};
+use std::future::Future;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
 use btleplug::api::CentralEvent;
-use btleplug::api::{Central, Manager as _, ScanFilter, Peripheral, Characteristic};
+use btleplug::api::{BDAddr, Central, Manager as _, ScanFilter, Peripheral, Characteristic, WriteType};
 use btleplug::platform::{Adapter, Manager, PeripheralId};
 use futures::{future, Stream, StreamExt};
 
 pub fn temperature_c_to_f(c: f32) -> f32 { c * 1.8 + 32.0 }
 pub fn pressure_hpa_to_atm(hpa: f32) -> f32 { hpa/1013.25 }
 
+#[cfg(any(test, feature = "test-fixtures"))]
+pub mod fixtures;
+
 pub mod uuids {
     use uuid::{uuid, Uuid};
 
@@ -72,6 +80,29 @@ pub mod uuids {
 
     /// device firmware update
     pub const NORDIC_DFU: Uuid = uuid!("8ec90003-f315-4f60-9fb8-838830daea50");
+
+    /// The name of a known service or characteristic UUID, such as `AR4_SERVICE`
+    pub fn name(uuid: Uuid) -> Option<&'static str> {
+        macro_rules! names {
+            ($($name: ident),* $(,)?) => {
+                [$((stringify!($name), $name)),*]
+            };
+        }
+        names![
+            AR4_OLD_SERVICE, AR4_SERVICE, GENERIC_SERVICE, COMMON_SERVICE,
+            AR4_READ_CURRENT_READINGS, AR4_READ_CURRENT_READINGS_DET, AR4_READ_INTERVAL, AR4_READ_SECONDS_SINCE_UPDATE,
+            AR4_READ_TOTAL_READINGS, AR4_READ_HISTORY_READINGS_V1, AR4_READ_HISTORY_READINGS_V2,
+            GENERIC_READ_DEVICE_NAME,
+            COMMON_READ_MANUFACTURER_NAME, COMMON_READ_MODEL_NUMBER, COMMON_READ_SERIAL_NO, COMMON_READ_HW_REV,
+            COMMON_READ_FACTORY_SW_REV, COMMON_READ_SW_REV,
+            AR4_WRITE_CMD,
+            GENERIC_ATTRIBUTE, BATTERY_SERVICE, BATTERY_READ, COMMON_APPEARANCE, COMMON_PREFERRED_CONNECT_PARAMS,
+            GENERIC_SVC_CHANGED, COMMON_SYSTEM_ID, COMMON_CENTRAL_ADDR_RESOLUTION,
+            MANUF_NORDIC_SEMICONDUCTOR_ASA,
+            AR4_READ_SENSOR_CALIBRATION, AR4_READ_SENSOR_SETTINGS,
+            NORDIC_DFU,
+        ].into_iter().find(|(_, u)| *u == uuid).map(|(name, _)| name)
+    }
 }
 
 pub mod characteristics {
@@ -121,18 +152,40 @@ pub mod characteristics {
 
 }
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+/// Opcodes of the commands written to [`uuids::AR4_WRITE_CMD`], each followed by its value
+mod commands {
+    pub const SET_BLUETOOTH_RANGE: u8 = 0x92;
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Version {
     major: u8,
     minor: u8,
     patch: u8,
 }
 impl Version {
-    pub fn new(major: u8, minor: u8, patch: u8) -> Version {
+    pub const fn new(major: u8, minor: u8, patch: u8) -> Version {
         Version { major, minor, patch }
     }
 }
+impl FromStr for Version {
+    type Err = String;
+    /// Parses a version such as `v1.4.19` or `1.4.19`, as read from the software revision characteristic
+    fn from_str(s: &str) -> Result<Version, String> {
+        let trimmed = s.trim_matches(|c: char| c.is_whitespace() || c == '\0');
+        let parts: Vec<u8> = trimmed.strip_prefix(['v', 'V']).unwrap_or(trimmed)
+            .split('.')
+            .map(|part| part.parse::<u8>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| format!("invalid version {:?}, expected one such as v1.4.19", s))?;
+        match parts[..] {
+            [major, minor, patch] => Ok(Version { major, minor, patch }),
+            _ => Err(format!("invalid version {:?}, expected one such as v1.4.19", s)),
+        }
+    }
+}
 impl fmt::Display for Version {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "v{}.{}.{}", self.major, self.minor, self.patch)
@@ -146,6 +199,7 @@ impl fmt::Debug for Version {
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[repr(u8)]
 pub enum CalibrationState {
     NotActive = 0,
@@ -167,12 +221,43 @@ impl CalibrationState {
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[repr(u8)]
 pub enum DisplayStatus {
     Green = 1,
     Yellow = 2,
     Red = 3,
 }
+impl DisplayStatus {
+    pub fn from_raw(b: u8) -> Option<DisplayStatus> {
+        Some(match b {
+            1 => DisplayStatus::Green,
+            2 => DisplayStatus::Yellow,
+            3 => DisplayStatus::Red,
+            _ => return None,
+        })
+    }
+}
+
+/// Data from a device that couldn't be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// Fewer bytes than the shortest valid value
+    TooShort { expected: usize, received: usize },
+    /// A CO2 status other than green, yellow, or red
+    UnknownStatus(u8),
+}
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooShort { expected, received } => {
+                write!(f, "received {} bytes, but expected at least {}", received, expected)
+            },
+            Self::UnknownStatus(status) => write!(f, "unknown CO2 status {}", status),
+        }
+    }
+}
+impl std::error::Error for ParseError {}
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct CurrentReading {
@@ -190,7 +275,7 @@ pub struct CurrentReading {
 }
 
 impl CurrentReading {
-    pub fn parse(data: [u8; 9]) -> CurrentReading {
+    pub fn parse(data: [u8; 9]) -> Result<CurrentReading, ParseError> {
         // reference for the filtering/mapped options:
         // https://github.com/Anrijs/Aranet4-Python/blob/b712654891c6f434c04774cb62f8aea0d97016a5/aranet4/client.py#L108
 
@@ -204,14 +289,9 @@ impl CurrentReading {
             .map(|r| r as f32 * 0.1);
         let humidity = data[6] as f32 / 100.0;
         let battery = data[7] as f32 / 100.0;
-        let status = match data[8] {
-            1 => DisplayStatus::Green,
-            2 => DisplayStatus::Yellow,
-            3 => DisplayStatus::Red,
-            o => panic!("unexpected display status value: {}", o),
-        };
+        let status = DisplayStatus::from_raw(data[8]).ok_or(ParseError::UnknownStatus(data[8]))?;
 
-        CurrentReading { co2_ppm: co2, temperature_c: temperature, pressure_hpa: pressure, humidity, battery, status }
+        Ok(CurrentReading { co2_ppm: co2, temperature_c: temperature, pressure_hpa: pressure, humidity, battery, status })
     }
 
     pub fn temperature_f(&self) -> Option<f32> {
@@ -222,6 +302,7 @@ impl CurrentReading {
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct CurrentReadingDetailed {
     /// in ppm
     pub co2_ppm: Option<u16>,
@@ -241,14 +322,14 @@ pub struct CurrentReadingDetailed {
 }
 
 impl CurrentReadingDetailed {
-    pub fn parse(data: [u8; 13]) -> CurrentReadingDetailed {
-        let cr = CurrentReading::parse(data[..9].try_into().unwrap());
+    pub fn parse(data: [u8; 13]) -> Result<CurrentReadingDetailed, ParseError> {
+        let cr = CurrentReading::parse(data[..9].try_into().unwrap())?;
         let CurrentReading { co2_ppm: co2, temperature_c: temperature, pressure_hpa: pressure, humidity, battery, status } = cr;
 
         let interval = u16::from_le_bytes([data[9], data[10]]);
         let age = u16::from_le_bytes([data[11], data[12]]);
 
-        CurrentReadingDetailed { co2_ppm: co2, temperature_c: temperature, pressure_hpa: pressure, humidity, battery, status, interval, age }
+        Ok(CurrentReadingDetailed { co2_ppm: co2, temperature_c: temperature, pressure_hpa: pressure, humidity, battery, status, interval, age })
     }
     
     pub fn temperature_f(&self) -> Option<f32> {
@@ -258,24 +339,149 @@ impl CurrentReadingDetailed {
     pub fn pressure_atm(&self) -> Option<f32> {
         self.pressure_hpa.map(pressure_hpa_to_atm)
     }
+
+    /// Whether the measurement is older than this many of the device's measurement intervals, such as when the device
+    /// has stopped measuring, or the reading was held onto since
+    pub fn is_stale(&self, intervals: f64) -> bool {
+        self.age as f64 > self.interval as f64 * intervals
+    }
+
+    /// Displays the reading with temperature and pressure in the given units
+    pub fn display(&self, units: Units) -> DisplayUnits<'_> {
+        DisplayUnits(self, units, Precision::default())
+    }
 }
 
 impl fmt::Display for CurrentReadingDetailed {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(f, "Measurement Age: {}/{}s", self.age, self.interval)?;
-        writeln!(f, "Battery: {:.0}%", self.battery * 100.0)?;
-        if let Some(ppm) = self.co2_ppm {
+        self.display(Units::Both).fmt(f)
+    }
+}
+
+/// The range of the device's Bluetooth radio, as set in the Aranet Home app
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum BluetoothRange {
+    Normal = 0,
+    Extended = 1,
+}
+impl FromStr for BluetoothRange {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "normal" | "standard" => BluetoothRange::Normal,
+            "extended" => BluetoothRange::Extended,
+            _ => return Err(format!("unknown Bluetooth range {:?}, expected normal or extended", s)),
+        })
+    }
+}
+impl fmt::Display for BluetoothRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BluetoothRange::Normal => "normal",
+            BluetoothRange::Extended => "extended",
+        })
+    }
+}
+
+/// The units to display temperature and pressure in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Units {
+    /// Imperial, followed by metric. eg: `72.5°F (22.5°C)`
+    #[default]
+    Both,
+    /// °C and hPa
+    Metric,
+    /// °F and atm
+    Imperial,
+}
+impl FromStr for Units {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "both" => Units::Both,
+            "metric" | "si" => Units::Metric,
+            "imperial" | "us" => Units::Imperial,
+            _ => return Err(format!("unknown units {:?}, expected one of: both, metric, imperial", s)),
+        })
+    }
+}
+
+/// Decimal places to show values with. `None` keeps each value's default.
+///
+/// Humidity and battery are in percent, and pressure applies to both hPa and atm.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Precision {
+    pub temperature: Option<usize>,
+    pub humidity: Option<usize>,
+    pub pressure: Option<usize>,
+    pub battery: Option<usize>,
+}
+
+impl Precision {
+    /// Rounds the reading's values to these decimal places, such as for serializing without float artifacts like
+    /// `21.400002`. Values without a precision set are left as they are.
+    pub fn round(&self, r: CurrentReadingDetailed) -> CurrentReadingDetailed {
+        fn round(value: f32, places: Option<usize>) -> f32 {
+            match places {
+                Some(places) => {
+                    let scale = 10f64.powi(places as i32);
+                    ((value as f64 * scale).round() / scale) as f32
+                },
+                None => value,
+            }
+        }
+        // humidity and battery are fractions, rather than percent
+        CurrentReadingDetailed {
+            temperature_c: r.temperature_c.map(|c| round(c, self.temperature)),
+            pressure_hpa: r.pressure_hpa.map(|hpa| round(hpa, self.pressure)),
+            humidity: round(r.humidity, self.humidity.map(|p| p + 2)),
+            battery: round(r.battery, self.battery.map(|p| p + 2)),
+            ..r
+        }
+    }
+}
+
+/// Displays a reading in specific units, from [`CurrentReadingDetailed::display`]
+pub struct DisplayUnits<'a>(&'a CurrentReadingDetailed, Units, Precision);
+
+impl DisplayUnits<'_> {
+    /// Shows values with these decimal places, rather than the defaults
+    pub fn precision(self, precision: Precision) -> Self {
+        DisplayUnits(self.0, self.1, precision)
+    }
+}
+
+impl fmt::Display for DisplayUnits<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let DisplayUnits(r, units, precision) = self;
+        let temperature = precision.temperature.unwrap_or(1);
+        let humidity = precision.humidity.unwrap_or(0);
+        let battery = precision.battery.unwrap_or(0);
+        writeln!(f, "Measurement Age: {}/{}s", r.age, r.interval)?;
+        writeln!(f, "Battery: {:.*}%", battery, r.battery * 100.0)?;
+        if let Some(ppm) = r.co2_ppm {
             writeln!(f, "CO2: {} PPM", ppm)?;
         }
-        writeln!(f, "CO2 Status: {:?}", self.status)?;
-        if let Some(c) = self.temperature_c {
-            writeln!(f, "Temperature: {:.1}°F ({:.1}°C)", temperature_c_to_f(c), c)?;
+        writeln!(f, "CO2 Status: {:?}", r.status)?;
+        if let Some(c) = r.temperature_c {
+            match units {
+                Units::Both => writeln!(f, "Temperature: {:.*}°F ({:.*}°C)", temperature, temperature_c_to_f(c), temperature, c)?,
+                Units::Metric => writeln!(f, "Temperature: {:.*}°C", temperature, c)?,
+                Units::Imperial => writeln!(f, "Temperature: {:.*}°F", temperature, temperature_c_to_f(c))?,
+            }
         }
-        writeln!(f, "Rel. Humidity: {:.0}%", self.humidity * 100.0)?;
-        if let Some(hpa) = self.pressure_hpa {
-            writeln!(f, "Pressure: {:.3} atm ({:.0} hPa)", pressure_hpa_to_atm(hpa), hpa)?;
+        writeln!(f, "Rel. Humidity: {:.*}%", humidity, r.humidity * 100.0)?;
+        if let Some(hpa) = r.pressure_hpa {
+            let atm = precision.pressure.unwrap_or(3);
+            let hpa_places = precision.pressure.unwrap_or(0);
+            match units {
+                Units::Both => writeln!(f, "Pressure: {:.*} atm ({:.*} hPa)", atm, pressure_hpa_to_atm(hpa), hpa_places, hpa)?,
+                Units::Metric => writeln!(f, "Pressure: {:.*} hPa", hpa_places, hpa)?,
+                Units::Imperial => writeln!(f, "Pressure: {:.*} atm", atm, pressure_hpa_to_atm(hpa))?,
+            }
         }
-        
+
         Ok(())
     }
 }
@@ -286,6 +492,7 @@ impl CurrentReading {
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct ManufacturerData {
     pub disconnected: bool,
     pub calibration_state: CalibrationState,
@@ -304,8 +511,139 @@ impl ManufacturerData {
     }
 }
 
+/// The contents of an advertisement's manufacturer data, sent under [`uuids::MANUFACTURER_ID`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdvertisementData {
+    pub manufacturer_data: ManufacturerData,
+    /// Only advertised with Smart Home integrations enabled
+    pub current_reading: Option<CurrentReadingDetailed>,
+}
+impl AdvertisementData {
+    pub fn parse(data: &[u8]) -> Result<AdvertisementData, ParseError> {
+        let raw_manuf = data.get(..7)
+            .ok_or(ParseError::TooShort { expected: 7, received: data.len() })?;
+        let manufacturer_data = ManufacturerData::parse(raw_manuf.try_into().unwrap());
+        let current_reading = data.get(8..21)
+            .map(|d| CurrentReadingDetailed::parse(d.try_into().unwrap()))
+            .transpose()?;
+        Ok(AdvertisementData { manufacturer_data, current_reading })
+    }
+}
+
+/// The device's system ID, read from [`uuids::COMMON_SYSTEM_ID`]. Unique to each device, and unlike its address,
+/// the same on every platform and adapter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SystemId {
+    /// Assigned by the manufacturer, 40 bits
+    pub manufacturer_identifier: u64,
+    /// The manufacturer's IEEE organizationally unique identifier, 24 bits
+    pub organizationally_unique_identifier: u32,
+}
+impl SystemId {
+    pub fn parse(data: [u8; 8]) -> SystemId {
+        let mut manufacturer_identifier = [0; 8];
+        manufacturer_identifier[..5].copy_from_slice(&data[..5]);
+        let mut organizationally_unique_identifier = [0; 4];
+        organizationally_unique_identifier[..3].copy_from_slice(&data[5..]);
+        SystemId {
+            manufacturer_identifier: u64::from_le_bytes(manufacturer_identifier),
+            organizationally_unique_identifier: u32::from_le_bytes(organizationally_unique_identifier),
+        }
+    }
+}
+impl fmt::Display for SystemId {
+    /// Formats as the OUI and manufacturer identifier in hex, such as `001122-3344556677`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:06X}-{:010X}", self.organizationally_unique_identifier, self.manufacturer_identifier)
+    }
+}
+
+/// The device's external appearance, read from [`uuids::COMMON_APPEARANCE`], as numbered in the Bluetooth Assigned
+/// Numbers document
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Appearance {
+    /// Such as 0 for unknown, or 21 for a sensor
+    pub category: u16,
+    /// Within the category, with 0 being generic
+    pub subcategory: u8,
+}
+impl Appearance {
+    pub fn parse(data: [u8; 2]) -> Appearance {
+        let raw = u16::from_le_bytes(data);
+        Appearance { category: raw >> 6, subcategory: (raw & 0x3f) as u8 }
+    }
+}
+impl fmt::Display for Appearance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "category {}, subcategory {}", self.category, self.subcategory)
+    }
+}
+
+/// The connection parameters the device prefers, read from [`uuids::COMMON_PREFERRED_CONNECT_PARAMS`]. Connections
+/// made with other parameters may be slow or unreliable, or drain the device's battery. Values the device has no
+/// preference for are `None`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct PreferredConnectionParameters {
+    pub min_interval: Option<Duration>,
+    pub max_interval: Option<Duration>,
+    /// How many connection events the device may skip when it has nothing to send
+    pub peripheral_latency: u16,
+    /// How long without a response before the connection is considered lost
+    pub supervision_timeout: Option<Duration>,
+}
+impl PreferredConnectionParameters {
+    pub fn parse(data: [u8; 8]) -> PreferredConnectionParameters {
+        let field = |i: usize| u16::from_le_bytes([data[i * 2], data[i * 2 + 1]]);
+        // in units of 1.25ms and 10ms, where 0xFFFF is no preference
+        let interval = |raw: u16| (raw != 0xFFFF).then(|| Duration::from_micros(raw as u64 * 1250));
+        let timeout = field(3);
+        PreferredConnectionParameters {
+            min_interval: interval(field(0)),
+            max_interval: interval(field(1)),
+            peripheral_latency: field(2),
+            supervision_timeout: (timeout != 0xFFFF).then(|| Duration::from_millis(timeout as u64 * 10)),
+        }
+    }
+}
+impl fmt::Display for PreferredConnectionParameters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let show = |d: Option<Duration>| d.map_or_else(|| "any".to_owned(), |d| format!("{}ms", d.as_secs_f64() * 1000.0));
+        write!(f, "interval {} to {}, latency {}, supervision timeout {}",
+            show(self.min_interval), show(self.max_interval), self.peripheral_latency, show(self.supervision_timeout),
+        )
+    }
+}
+
+/// A connected Aranet4. Cloning it is cheap, and clones share the connection. Requests from every handle to the same
+/// device (clones, and those connected separately, such as by each [`DiscoveredAranet::upgrade`]) are serialized, so
+/// tasks sharing a device never interleave their GATT operations, and share its [`RateLimit`].
+#[derive(Clone)]
 pub struct Aranet4<P: Peripheral> {
     device: P,
+    /// Held for the duration of each request, by every handle to the device
+    requests: Arc<tokio::sync::Mutex<TokenBucket>>,
+}
+
+/// The requests to each device, shared by every [`Aranet4`] connected to it. Devices are forgotten once no handle is
+/// connected to them, unless a [`RateLimit`] was set for them.
+static REQUESTS: OnceLock<std::sync::Mutex<HashMap<PeripheralId, Arc<tokio::sync::Mutex<TokenBucket>>>>> = OnceLock::new();
+
+/// The requests to a device, without a [`RateLimit`] for devices that haven't been limited before
+fn requests(id: PeripheralId) -> Arc<tokio::sync::Mutex<TokenBucket>> {
+    let mut requests = REQUESTS.get_or_init(Default::default).lock().unwrap_or_else(|e| e.into_inner());
+    requests.retain(|_, bucket| {
+        // only the map holds it, so it can't be locked
+        let unused = Arc::strong_count(bucket) == 1;
+        ! unused || bucket.try_lock().map_or(true, |bucket| bucket.limit != RateLimit::UNLIMITED)
+    });
+    let bucket = requests.entry(id).or_insert_with(|| Arc::new(tokio::sync::Mutex::new(TokenBucket::new(RateLimit::default()))));
+    Arc::clone(bucket)
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -395,23 +733,32 @@ impl<P: Peripheral + fmt::Debug> Aranet4<P> {
             return Err(btleplug::Error::NotSupported("device is not an Aranet4 device (or firmware is not v1.2.0+)".to_owned()));
         }
         log::debug!("created new Aranet4 struct, passed device {:?} had AR4_SERVICE", device);
-        Ok(Aranet4 { device })
+        let requests = requests(device.id());
+        Ok(Aranet4 { device, requests })
     }
 
     pub async fn current_readings(&self) -> btleplug::Result<CurrentReading> {
+        let _request = self.request().await;
         if ! dbg!(self.device.is_connected().await)? { return Err(btleplug::Error::NotConnected); }
         let raw = dbg!(read_uuid!(self.device, AR4_READ_CURRENT_READINGS, 9).await)?;
-        Ok(CurrentReading::parse(raw))
+        CurrentReading::parse(raw).map_err(|e| btleplug::Error::Other(Box::new(e)))
     }
 
     pub async fn current_readings_details(&self) -> btleplug::Result<CurrentReadingDetailed> {
+        CurrentReadingDetailed::parse(self.current_readings_details_raw().await?)
+            .map_err(|e| btleplug::Error::Other(Box::new(e)))
+    }
+
+    /// The unparsed value of the detailed current readings characteristic
+    pub async fn current_readings_details_raw(&self) -> btleplug::Result<[u8; 13]> {
+        let _request = self.request().await;
         if ! self.device.is_connected().await? { return Err(btleplug::Error::NotConnected); }
-        let raw = read_uuid!(self.device, AR4_READ_CURRENT_READINGS_DET, 13).await?;
-        Ok(CurrentReadingDetailed::parse(raw))
+        read_uuid!(self.device, AR4_READ_CURRENT_READINGS_DET, 13).await
     }
 
     /// Interval between environment samples, in seconds
     pub async fn interval(&self) -> btleplug::Result<u16> {
+        let _request = self.request().await;
         if ! self.device.is_connected().await? { return Err(btleplug::Error::NotConnected); }
         
         let raw = read_uuid!(self.device, AR4_READ_INTERVAL, 2).await?;
@@ -421,22 +768,57 @@ impl<P: Peripheral + fmt::Debug> Aranet4<P> {
 
     /// The name of the device.
     pub async fn name(&self) -> btleplug::Result<String> {
+        let _request = self.request().await;
         if ! self.device.is_connected().await? { return Err(btleplug::Error::NotConnected); }
         let raw = read_uuid!(self.device, GENERIC_READ_DEVICE_NAME).await?;
 
-        Ok(String::from_utf8(raw).map_err(|e| btleplug::Error::Other(Box::new(e)))?)
+        String::from_utf8(raw).map_err(|e| btleplug::Error::Other(Box::new(e)))
     }
 
     /// The version string of the firmware
     pub async fn version(&self) -> btleplug::Result<String> {
+        let _request = self.request().await;
         if ! self.device.is_connected().await? { return Err(btleplug::Error::NotConnected); }
         let raw = read_uuid!(self.device, COMMON_READ_SW_REV).await?;
 
-        Ok(String::from_utf8(raw).map_err(|e| btleplug::Error::Other(Box::new(e)))?)
+        String::from_utf8(raw).map_err(|e| btleplug::Error::Other(Box::new(e)))
+    }
+
+    /// The serial number of the device, which stays the same whichever adapter or platform it's connected from
+    pub async fn serial_number(&self) -> btleplug::Result<String> {
+        let _request = self.request().await;
+        if ! self.device.is_connected().await? { return Err(btleplug::Error::NotConnected); }
+        let raw = read_uuid!(self.device, COMMON_READ_SERIAL_NO).await?;
+
+        String::from_utf8(raw).map_err(|e| btleplug::Error::Other(Box::new(e)))
+    }
+
+    /// The hardware revision string of the device
+    pub async fn hardware_revision(&self) -> btleplug::Result<String> {
+        let _request = self.request().await;
+        if ! self.device.is_connected().await? { return Err(btleplug::Error::NotConnected); }
+        let raw = read_uuid!(self.device, COMMON_READ_HW_REV).await?;
+
+        String::from_utf8(raw).map_err(|e| btleplug::Error::Other(Box::new(e)))
+    }
+
+    /// The battery level, from 0.0 to 1.0
+    pub async fn battery(&self) -> btleplug::Result<f32> {
+        Ok(self.battery_raw().await? as f32 / 100.0)
+    }
+
+    /// The unparsed value of the battery level characteristic, in percent
+    pub async fn battery_raw(&self) -> btleplug::Result<u8> {
+        let _request = self.request().await;
+        if ! self.device.is_connected().await? { return Err(btleplug::Error::NotConnected); }
+        let raw = read_uuid!(self.device, BATTERY_READ, 1).await?;
+
+        Ok(raw[0])
     }
 
     /// The number of seconds since the last environment sample was taken
     pub async fn last_update_age(&self) -> btleplug::Result<u16> {
+        let _request = self.request().await;
         if ! self.device.is_connected().await? { return Err(btleplug::Error::NotConnected); }
         let raw = read_uuid!(self.device, AR4_READ_SECONDS_SINCE_UPDATE, 2).await?;
 
@@ -445,35 +827,285 @@ impl<P: Peripheral + fmt::Debug> Aranet4<P> {
 
     /// The number of seconds since the last environment sample was taken
     pub async fn total_readings(&self) -> btleplug::Result<u16> {
+        let _request = self.request().await;
         if ! self.device.is_connected().await? { return Err(btleplug::Error::NotConnected); }
         let raw = read_uuid!(self.device, AR4_READ_SECONDS_SINCE_UPDATE).await?;
 
         Ok(u16::from_le_bytes(raw.try_into().expect("expected total readings to be a 2-byte little endian integer")))
     }
 
-    pub fn as_ref(&self) -> &P {
+    /// The device's system ID, which identifies it even as its address changes
+    pub async fn system_id(&self) -> btleplug::Result<SystemId> {
+        let _request = self.request().await;
+        if ! self.device.is_connected().await? { return Err(btleplug::Error::NotConnected); }
+        Ok(SystemId::parse(read_uuid!(self.device, COMMON_SYSTEM_ID, 8).await?))
+    }
+
+    /// The device's external appearance
+    pub async fn appearance(&self) -> btleplug::Result<Appearance> {
+        let _request = self.request().await;
+        if ! self.device.is_connected().await? { return Err(btleplug::Error::NotConnected); }
+        Ok(Appearance::parse(read_uuid!(self.device, COMMON_APPEARANCE, 2).await?))
+    }
+
+    /// The connection parameters the device prefers
+    pub async fn preferred_connection_parameters(&self) -> btleplug::Result<PreferredConnectionParameters> {
+        let _request = self.request().await;
+        if ! self.device.is_connected().await? { return Err(btleplug::Error::NotConnected); }
+        Ok(PreferredConnectionParameters::parse(read_uuid!(self.device, COMMON_PREFERRED_CONNECT_PARAMS, 8).await?))
+    }
+
+    /// Writes a command to the device, waiting for it to be acknowledged. Devices with Smart Home integrations
+    /// disabled only accept commands from bonded devices.
+    pub async fn write_command(&self, command: &[u8]) -> btleplug::Result<()> {
+        let _request = self.request().await;
+        if ! self.device.is_connected().await? { return Err(btleplug::Error::NotConnected); }
+        log::trace!("writing command {:02x?} to AR4_WRITE_CMD on {:?}", command, &self.device);
+        self.device.write(&characteristics::AR4_WRITE_CMD, command, WriteType::WithResponse).await
+    }
+
+    /// Sets the range of the device's Bluetooth radio
+    pub async fn set_bluetooth_range(&self, range: BluetoothRange) -> btleplug::Result<()> {
+        self.write_command(&[commands::SET_BLUETOOTH_RANGE, range as u8]).await
+    }
+
+    /// Changes how often this device may be read or written, through this or any other handle to it, including those
+    /// connected later. Devices aren't limited unless this is called.
+    pub async fn set_rate_limit(&self, limit: RateLimit) {
+        *self.requests.lock().await = TokenBucket::new(limit);
+    }
+
+    /// Waits for this request's turn, within the rate limit. Other requests wait until the guard is dropped.
+    async fn request(&self) -> tokio::sync::MutexGuard<'_, TokenBucket> {
+        let mut bucket = self.requests.lock().await;
+        bucket.take().await;
+        bucket
+    }
+}
+
+impl<P: Peripheral> AsRef<P> for Aranet4<P> {
+    fn as_ref(&self) -> &P {
         &self.device
     }
 }
 
 #[derive(Debug, Clone)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct DiscoveredAranet {
+    /// The adapter the advertisement was received on. `None` for advertisements that weren't received live, such as
+    /// those replayed from a recording.
     #[cfg_attr(feature = "serde", serde(skip_serializing))]
-    pub adapter: Adapter,
+    #[cfg_attr(feature = "schema", schemars(skip))]
+    pub adapter: Option<Adapter>,
+    /// The OS's identifier for the device. Platform specific, such as `{"object_path": "/org/bluez/hci0/dev_..."}`
+    /// with BlueZ on Linux
+    #[cfg_attr(feature = "schema", schemars(with = "serde_json::Value"))]
     pub peripheral_id: PeripheralId,
     pub manufacturer_data: ManufacturerData,
     pub current_reading: Option<CurrentReadingDetailed>,
+    /// The unparsed manufacturer data of the advertisement
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    #[cfg_attr(feature = "schema", schemars(skip))]
+    pub raw_manufacturer_data: Vec<u8>,
+    /// When the advertisement was received. The reading's age is relative to this.
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    #[cfg_attr(feature = "schema", schemars(skip))]
+    pub received: SystemTime,
+}
+
+/// A measurement read from the device over GATT, alongside the raw characteristic values it was parsed from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ActiveReading {
+    pub reading: CurrentReadingDetailed,
+    pub raw_reading: [u8; 13],
+    pub raw_battery: u8,
 }
 
 impl DiscoveredAranet {
+    /// Parses an advertisement from the manufacturer data sent under [`uuids::MANUFACTURER_ID`]
+    pub fn parse(adapter: Option<Adapter>, peripheral_id: PeripheralId, data: &[u8]) -> Result<DiscoveredAranet, ParseError> {
+        let AdvertisementData { manufacturer_data, current_reading } = AdvertisementData::parse(data)?;
+        Ok(DiscoveredAranet {
+            adapter,
+            peripheral_id,
+            manufacturer_data,
+            current_reading,
+            raw_manufacturer_data: data.to_vec(),
+            received: SystemTime::now(),
+        })
+    }
+
+    /// The peripheral that sent this advertisement, if it was received live
+    pub async fn peripheral(&self) -> btleplug::Result<btleplug::platform::Peripheral> {
+        match &self.adapter {
+            Some(adapter) => adapter.peripheral(&self.peripheral_id).await,
+            None => Err(btleplug::Error::NotSupported("advertisement was not received from a bluetooth adapter".to_owned())),
+        }
+    }
+
+    /// Whether this advertisement was sent by the device with the specified address.
+    ///
+    /// Compares against the platform's peripheral ID, as advertisements do not otherwise carry the address.
+    /// BlueZ formats these like `hci0/dev_AA_BB_CC_DD_EE_FF`, while Windows uses the address itself.
+    pub fn is_device(&self, addr: BDAddr) -> bool {
+        let id = self.peripheral_id.to_string().replace('_', ":");
+        id.to_ascii_uppercase().ends_with(&addr.to_string().to_ascii_uppercase())
+    }
+
+    /// Whether this advertisement was sent by the specified device: by its address, or by the platform's peripheral
+    /// ID as displayed (such as `hci0/dev_AA_BB_CC_DD_EE_FF`). macOS doesn't expose addresses, so devices can only be
+    /// specified there by their peripheral ID.
+    pub fn is_device_id(&self, id_or_addr: &str) -> bool {
+        match id_or_addr.parse() {
+            Ok(addr) => self.is_device(addr),
+            Err(_) => self.peripheral_id.to_string() == id_or_addr,
+        }
+    }
+
+    /// The signal strength of the device's most recent advertisement, in dBm, if known.
+    pub async fn rssi(&self) -> btleplug::Result<Option<i16>> {
+        let periph = self.peripheral().await?;
+        Ok(periph.properties().await?.and_then(|p| p.rssi))
+    }
+
+    /// Connects to the device and reads its current measurement, disconnecting afterwards.
+    pub async fn read_current(&self) -> btleplug::Result<ActiveReading> {
+        self.read_current_with(&RetryPolicy::default()).await
+    }
+
+    /// As [`read_current`](Self::read_current), retrying the connection and each read as the policy allows.
+    pub async fn read_current_with(&self, retry: &RetryPolicy) -> btleplug::Result<ActiveReading> {
+        let aranet = retry.run("connect", || self.upgrade()).await?;
+        let result = async {
+            let raw_reading = retry.run("read current readings", || aranet.current_readings_details_raw()).await?;
+            let raw_battery = retry.run("read battery", || aranet.battery_raw()).await?;
+            let mut reading = CurrentReadingDetailed::parse(raw_reading)
+                .map_err(|e| btleplug::Error::Other(Box::new(e)))?;
+            reading.battery = raw_battery as f32 / 100.0;
+            Ok(ActiveReading { reading, raw_reading, raw_battery })
+        }.await;
+        if let Err(e) = aranet.as_ref().disconnect().await {
+            log::debug!("unable to disconnect from {:?} after reading: {}", self.peripheral_id, e);
+        }
+        result
+    }
+
     pub async fn upgrade(&self) -> btleplug::Result<Aranet4<btleplug::platform::Peripheral>> {
-        let periph = self.adapter.peripheral(&self.peripheral_id).await?;
+        let periph = self.peripheral().await?;
         if ! periph.is_connected().await? {
             log::debug!("connecting to device during DiscoveredAranet::upgrade({:?})", self);
             let () = periph.connect().await?;
         }
-        Ok(Aranet4::new(periph).await?)
+        Aranet4::new(periph).await
+    }
+}
+
+/// How many times to retry a failed Bluetooth operation (such as starting discovery, connecting, or reading a
+/// characteristic), and how long to wait before the first retry. The wait doubles after each further attempt.
+///
+/// The default is to not retry at all.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy { retries: 0, backoff: Duration::from_secs(1) }
+    }
+}
+
+impl RetryPolicy {
+    /// Whether an error may go away by trying again. Errors such as a denied permission or an unsupported device
+    /// won't, so fail immediately.
+    pub fn is_retryable(error: &btleplug::Error) -> bool {
+        !matches!(
+            error,
+            btleplug::Error::PermissionDenied
+                | btleplug::Error::NotSupported(_)
+                | btleplug::Error::Uuid(_)
+                | btleplug::Error::InvalidBDAddr(_)
+        )
+    }
+
+    /// Runs the operation, retrying it while it fails with a retryable error. `what` describes the operation in logs.
+    pub async fn run<T, F, Fut>(&self, what: &str, mut op: F) -> btleplug::Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = btleplug::Result<T>>,
+    {
+        let mut wait = self.backoff;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            match op().await {
+                Err(e) if attempt <= self.retries && Self::is_retryable(&e) => {
+                    log::warn!("unable to {} (attempt {} of {}): {}, retrying in {:?}", what, attempt, self.retries + 1, e, wait);
+                    tokio::time::sleep(wait).await;
+                    wait = wait.saturating_mul(2);
+                },
+                result => return result,
+            }
+        }
+    }
+}
+
+/// How often a connected device may be read or written, as each request costs its battery. Requests beyond the limit
+/// wait their turn, rather than failing.
+///
+/// Up to `burst` requests are made immediately, after which one more is allowed each `interval`. For example,
+/// `RateLimit { burst: 10, interval: Duration::from_secs(5) }` allows a few complete readings at once, but no more
+/// than one request every 5 seconds for a caller reading in a loop. The default is [`RateLimit::UNLIMITED`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub interval: Duration,
+}
+
+impl Default for RateLimit {
+    fn default() -> RateLimit {
+        RateLimit::UNLIMITED
+    }
+}
+
+impl RateLimit {
+    /// No limit at all
+    pub const UNLIMITED: RateLimit = RateLimit { burst: u32::MAX, interval: Duration::ZERO };
+}
+
+/// Enforces a [`RateLimit`], with a token for each request
+#[derive(Debug)]
+struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    /// When `tokens` was last refilled
+    refilled: tokio::time::Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: RateLimit) -> TokenBucket {
+        TokenBucket { limit, tokens: limit.burst as f64, refilled: tokio::time::Instant::now() }
+    }
+
+    /// Takes a token, waiting for one if there are none left
+    async fn take(&mut self) {
+        if self.limit.interval.is_zero() {
+            return;
+        }
+        let now = tokio::time::Instant::now();
+        let refill = now.duration_since(self.refilled).as_secs_f64() / self.limit.interval.as_secs_f64();
+        self.tokens = (self.tokens + refill).min(self.limit.burst as f64);
+        self.refilled = now;
+        if self.tokens < 1.0 {
+            let wait = self.limit.interval.mul_f64(1.0 - self.tokens);
+            log::debug!("rate limited, waiting {:?} before the next request", wait);
+            tokio::time::sleep(wait).await;
+            self.tokens = 1.0;
+            self.refilled = tokio::time::Instant::now();
+        }
+        self.tokens -= 1.0;
     }
 }
 
@@ -495,23 +1127,15 @@ pub async fn discover_aranet4(manager: &Manager) -> btleplug::Result<Pin<Box<dyn
         event_streams.push(inspected.filter_map(move |ce| {
             future::ready(match ce {
                 CentralEvent::ManufacturerDataAdvertisement { id, manufacturer_data } => {
-                    if let Some(data) = manufacturer_data.get(&uuids::MANUFACTURER_ID) {
-                        let raw_manuf = data[..7].try_into()
-                            .expect("Aranet4's Manufacturer ID used for advertisement data under 7 bytes!");
-                        let manufacturer_data = ManufacturerData::parse(raw_manuf);
-                        let current_reading = data[8..21].try_into()
-                            .map(CurrentReadingDetailed::parse)
-                            .ok();
-                        Some(DiscoveredAranet {
-                            adapter: adapter.clone(),
-                            peripheral_id: id,
-                            manufacturer_data,
-                            current_reading,
+                    // other manufacturer IDs are ignored
+                    manufacturer_data.get(&uuids::MANUFACTURER_ID)
+                        .and_then(|data| match DiscoveredAranet::parse(Some(adapter.clone()), id, data) {
+                            Ok(adv) => Some(adv),
+                            Err(e) => {
+                                log::debug!("BTLE Adapter#{} - Ignoring invalid advertisement: {}", adapter_idx, e);
+                                None
+                            },
                         })
-                    } else {
-                        /* unknown manufacturer ID */
-                        None
-                    }
                 },
                 _ => {
                     /* other discovery methods may be implemented in the future, for now - just manufacturer data */
@@ -524,3 +1148,134 @@ pub async fn discover_aranet4(manager: &Manager) -> btleplug::Result<Pin<Box<dyn
     log::debug!("listening on {} BTLE adapters", event_streams.len());
     Ok(Box::pin(futures::stream::select_all(event_streams)))
 }
+
+/// The advertisements of nearby Aranet4 devices, as a [`Stream`] of [`DiscoveredAranet`]
+pub struct AranetDiscovery(Pin<Box<dyn Stream<Item = DiscoveredAranet>>>);
+
+impl AranetDiscovery {
+    /// Starts scanning on every adapter, as [`discover_aranet4`]
+    pub async fn start(manager: &Manager) -> btleplug::Result<AranetDiscovery> {
+        Ok(AranetDiscovery(discover_aranet4(manager).await?))
+    }
+
+    /// Only the advertisements of one device, by its address or peripheral ID (see
+    /// [`DiscoveredAranet::is_device_id`])
+    pub fn for_device(self, id_or_addr: &str) -> AranetDiscovery {
+        let id_or_addr = id_or_addr.to_owned();
+        AranetDiscovery(Box::pin(self.0.filter(move |adv| future::ready(adv.is_device_id(&id_or_addr)))))
+    }
+}
+
+impl From<Pin<Box<dyn Stream<Item = DiscoveredAranet>>>> for AranetDiscovery {
+    fn from(discovered: Pin<Box<dyn Stream<Item = DiscoveredAranet>>>) -> AranetDiscovery {
+        AranetDiscovery(discovered)
+    }
+}
+
+impl Stream for AranetDiscovery {
+    type Item = DiscoveredAranet;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Option<DiscoveredAranet>> {
+        self.0.as_mut().poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use btleplug::api::{PeripheralProperties, Service, ValueNotification};
+    use super::*;
+
+    /// A connected Aranet4 that reports a fixed reading
+    #[derive(Debug, Clone)]
+    struct MockAranet4(PeripheralId);
+
+    impl MockAranet4 {
+        fn new(n: u32) -> MockAranet4 {
+            let addr = format!("02:00:00:00:00:{:02X}", n);
+            #[cfg(target_os = "linux")]
+            let id = serde_json::json!({ "object_path": format!("/org/bluez/hci0/dev_{}", addr.replace(':', "_")) });
+            #[cfg(any(target_os = "macos", target_os = "ios"))]
+            let id = serde_json::json!(format!("00000000-0000-0000-0000-{}", addr.replace(':', "")));
+            #[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "ios")))]
+            let id = serde_json::json!(addr);
+            MockAranet4(serde_json::from_value(id).unwrap())
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Peripheral for MockAranet4 {
+        fn id(&self) -> PeripheralId { self.0.clone() }
+        fn address(&self) -> BDAddr { BDAddr::default() }
+        async fn properties(&self) -> btleplug::Result<Option<PeripheralProperties>> { Ok(None) }
+        fn services(&self) -> BTreeSet<Service> {
+            BTreeSet::from([Service { uuid: uuids::AR4_SERVICE, primary: true, characteristics: BTreeSet::new() }])
+        }
+        async fn is_connected(&self) -> btleplug::Result<bool> { Ok(true) }
+        async fn connect(&self) -> btleplug::Result<()> { Ok(()) }
+        async fn disconnect(&self) -> btleplug::Result<()> { Ok(()) }
+        async fn discover_services(&self) -> btleplug::Result<()> { Ok(()) }
+        async fn write(&self, _: &Characteristic, _: &[u8], _: WriteType) -> btleplug::Result<()> { Ok(()) }
+        async fn read(&self, _: &Characteristic) -> btleplug::Result<Vec<u8>> {
+            Ok(vec![0x64, 0x02, 0xc9, 0x01, 0x8b, 0x27, 0x29, 0x57, 0x01])
+        }
+        async fn subscribe(&self, _: &Characteristic) -> btleplug::Result<()> { Ok(()) }
+        async fn unsubscribe(&self, _: &Characteristic) -> btleplug::Result<()> { Ok(()) }
+        async fn notifications(&self) -> btleplug::Result<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>> {
+            Ok(Box::pin(futures::stream::empty()))
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn handles_to_a_device_share_its_rate_limit() {
+        let limit = RateLimit { burst: 1, interval: Duration::from_secs(60) };
+        // as if upgraded twice, rather than cloned
+        let first = Aranet4::new(MockAranet4::new(1)).await.unwrap();
+        let second = Aranet4::new(MockAranet4::new(1)).await.unwrap();
+        let other = Aranet4::new(MockAranet4::new(2)).await.unwrap();
+        first.set_rate_limit(limit).await;
+        other.set_rate_limit(limit).await;
+
+        let start = tokio::time::Instant::now();
+        first.current_readings().await.unwrap();
+        other.current_readings().await.unwrap();
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        // the first handle took the only token
+        second.current_readings().await.unwrap();
+        assert!(start.elapsed() >= limit.interval, "waited {:?}", start.elapsed());
+
+        // the limit outlives the handles, while unlimited devices are forgotten
+        drop((first, second, other));
+        let unlimited = Aranet4::new(MockAranet4::new(3)).await.unwrap();
+        drop(unlimited);
+        let start = tokio::time::Instant::now();
+        Aranet4::new(MockAranet4::new(1)).await.unwrap().current_readings().await.unwrap();
+        assert!(start.elapsed() >= limit.interval, "waited {:?}", start.elapsed());
+        let known = REQUESTS.get().unwrap().lock().unwrap().contains_key(&MockAranet4::new(3).0);
+        assert!(! known);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn requests_are_unlimited_by_default() {
+        let aranet = Aranet4::new(MockAranet4::new(4)).await.unwrap();
+        let start = tokio::time::Instant::now();
+        for _ in 0..20 {
+            aranet.current_readings().await.unwrap();
+        }
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn discovers_one_device() {
+        let data = fixtures::ADVERTISEMENTS[0].data;
+        let advs: Vec<DiscoveredAranet> = (1..=3)
+            .map(|n| DiscoveredAranet::parse(None, MockAranet4::new(n).0, data).unwrap())
+            .collect();
+        let all: Pin<Box<dyn Stream<Item = DiscoveredAranet>>> = Box::pin(futures::stream::iter(advs));
+        let discovered: Vec<DiscoveredAranet> = AranetDiscovery::from(all)
+            .for_device("02:00:00:00:00:02")
+            .collect().await;
+        assert_eq!(discovered.len(), 1);
+        assert_eq!(discovered[0].peripheral_id, MockAranet4::new(2).0);
+    }
+}
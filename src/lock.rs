@@ -0,0 +1,68 @@
+//! An advisory lock serializing Bluetooth access between concurrent runs, for --lock.
+//!
+//! Adapters misbehave when several processes start scans or connect to devices at the same time, such as a cron job,
+//! a CGI request, and a daemon. The lock is only held while doing so, not while listening for advertisements, so a
+//! long running process doesn't starve the others.
+
+use std::error::Error;
+use std::fs::{File, TryLockError};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use btleplug::platform::Manager;
+
+static LOCK: OnceLock<RadioLock> = OnceLock::new();
+
+struct RadioLock {
+    path: PathBuf,
+    wait: Duration,
+}
+
+/// Held until dropped
+pub struct LockGuard(#[allow(dead_code)] File);
+
+/// The lock file used when --lock is given without one
+pub fn default_path() -> PathBuf {
+    std::env::temp_dir().join("aranet.lock")
+}
+
+/// Enables locking for the rest of the process, waiting at most `wait` seconds for it. Only the first call has any
+/// effect.
+pub fn init(path: PathBuf, wait: f64) {
+    let _ = LOCK.set(RadioLock { path, wait: Duration::from_secs_f64(wait) });
+}
+
+/// Waits for the lock, if enabled. Other runs are held off until the guard is dropped.
+pub async fn acquire() -> Result<Option<LockGuard>, String> {
+    let Some(lock) = LOCK.get() else { return Ok(None) };
+    let file = File::options().create(true).truncate(false).write(true).open(&lock.path)
+        .map_err(|e| format!("unable to open lock file {}: {}", lock.path.display(), e))?;
+
+    let deadline = Instant::now() + lock.wait;
+    let mut waiting = false;
+    loop {
+        match file.try_lock() {
+            Ok(()) => return Ok(Some(LockGuard(file))),
+            Err(TryLockError::WouldBlock) if Instant::now() < deadline => {
+                if ! waiting {
+                    log::info!("waiting for another run to release {}", lock.path.display());
+                    waiting = true;
+                }
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            },
+            Err(TryLockError::WouldBlock) => {
+                return Err(format!("gave up waiting {}s for another run to release {}", lock.wait.as_secs_f64(), lock.path.display()));
+            },
+            Err(TryLockError::Error(e)) => return Err(format!("unable to lock {}: {}", lock.path.display(), e)),
+        }
+    }
+}
+
+/// Starts scanning for Aranet4 devices as [`aranet::discover_aranet4`] does, while holding the lock. Retried as
+/// --retries allows.
+pub async fn discover(manager: &Manager) -> Result<crate::Advertisements, Box<dyn Error>> {
+    let _lock = acquire().await?;
+    crate::retry::policy().run("start discovery", || aranet::discover_aranet4(manager)).await
+        .map_err(crate::bluetooth_error)
+}
@@ -0,0 +1,197 @@
+//! Named metrics of a reading, and simple conditions over them (eg: `co2>1200`)
+
+use std::fmt;
+use std::str::FromStr;
+
+use aranet::CurrentReadingDetailed;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    /// CO2 concentration, in ppm
+    Co2,
+    /// Temperature, in °C
+    TemperatureC,
+    /// Temperature, in °F
+    TemperatureF,
+    /// Relative humidity, in percent
+    Humidity,
+    /// Pressure, in hPa
+    Pressure,
+    /// Battery level, in percent
+    Battery,
+    /// CO2 display status, 1 (green) to 3 (red)
+    Status,
+    /// Age of the measurement, in seconds
+    Age,
+}
+
+impl Metric {
+    pub const ALL: [Metric; 8] = [
+        Metric::Co2, Metric::TemperatureC, Metric::TemperatureF, Metric::Humidity,
+        Metric::Pressure, Metric::Battery, Metric::Status, Metric::Age,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Metric::Co2 => "co2",
+            Metric::TemperatureC => "temp_c",
+            Metric::TemperatureF => "temp_f",
+            Metric::Humidity => "humidity",
+            Metric::Pressure => "pressure",
+            Metric::Battery => "battery",
+            Metric::Status => "status",
+            Metric::Age => "age",
+        }
+    }
+
+    /// Number of decimal places to output this metric with: as set by --precision, otherwise those that are
+    /// meaningful given the device's resolution
+    pub fn precision(&self) -> usize {
+        let set = crate::precision::get();
+        match self {
+            Metric::TemperatureC | Metric::TemperatureF => set.temperature.unwrap_or(2),
+            Metric::Humidity => set.humidity.unwrap_or(0),
+            Metric::Pressure => set.pressure.unwrap_or(1),
+            Metric::Battery => set.battery.unwrap_or(0),
+            _ => 0,
+        }
+    }
+
+    /// The value of this metric within a reading, formatted to its precision
+    pub fn format(&self, r: &CurrentReadingDetailed) -> Option<String> {
+        self.value(r).map(|v| format!("{:.*}", self.precision(), v))
+    }
+
+    /// The value of this metric within a reading, if the device provided it
+    pub fn value(&self, r: &CurrentReadingDetailed) -> Option<f64> {
+        match self {
+            Metric::Co2 => r.co2_ppm.map(f64::from),
+            Metric::TemperatureC => r.temperature_c.map(f64::from),
+            Metric::TemperatureF => r.temperature_f().map(f64::from),
+            Metric::Humidity => Some((r.humidity as f64 * 100.0).round()),
+            Metric::Pressure => r.pressure_hpa.map(f64::from),
+            Metric::Battery => Some((r.battery as f64 * 100.0).round()),
+            Metric::Status => Some(r.status as u8 as f64),
+            Metric::Age => Some(r.age as f64),
+        }
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Metric {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(self.name())
+    }
+}
+
+impl FromStr for Metric {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "co2" | "co2_ppm" => Metric::Co2,
+            "temp" | "temp_c" | "temperature" | "temperature_c" => Metric::TemperatureC,
+            "temp_f" | "temperature_f" => Metric::TemperatureF,
+            "humidity" => Metric::Humidity,
+            "pressure" | "pressure_hpa" => Metric::Pressure,
+            "battery" => Metric::Battery,
+            "status" | "co2_status" => Metric::Status,
+            "age" => Metric::Age,
+            _ => return Err(format!(
+                "unknown metric {:?}, expected one of: {}",
+                s, Metric::ALL.iter().map(Metric::name).collect::<Vec<_>>().join(", ")
+            )),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+impl Comparison {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Comparison::Lt => "<",
+            Comparison::Le => "<=",
+            Comparison::Gt => ">",
+            Comparison::Ge => ">=",
+            Comparison::Eq => "==",
+            Comparison::Ne => "!=",
+        }
+    }
+
+    pub fn test(&self, lhs: f64, rhs: f64) -> bool {
+        match self {
+            Comparison::Lt => lhs < rhs,
+            Comparison::Le => lhs <= rhs,
+            Comparison::Gt => lhs > rhs,
+            Comparison::Ge => lhs >= rhs,
+            Comparison::Eq => lhs == rhs,
+            Comparison::Ne => lhs != rhs,
+        }
+    }
+}
+
+/// A comparison of a metric against a constant, such as `co2>1200` or `status==red`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Condition {
+    pub metric: Metric,
+    pub comparison: Comparison,
+    pub value: f64,
+}
+
+impl Condition {
+    /// Whether the reading satisfies this condition. Unavailable values never do.
+    pub fn test(&self, r: &CurrentReadingDetailed) -> bool {
+        self.metric.value(r)
+            .map(|v| self.comparison.test(v, self.value))
+            .unwrap_or(false)
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}{}", self.metric, self.comparison.symbol(), self.value)
+    }
+}
+
+impl FromStr for Condition {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // longest operators first, so '>=' isn't read as '>'
+        const OPERATORS: [(&str, Comparison); 7] = [
+            ("<=", Comparison::Le), (">=", Comparison::Ge), ("==", Comparison::Eq), ("!=", Comparison::Ne),
+            ("<", Comparison::Lt), (">", Comparison::Gt), ("=", Comparison::Eq),
+        ];
+
+        let Some((idx, op, comparison)) = OPERATORS.iter()
+            .filter_map(|(op, cmp)| s.find(op).map(|idx| (idx, *op, *cmp)))
+            .min_by_key(|(idx, op, _)| (*idx, usize::MAX - op.len()))
+        else {
+            return Err(format!("expected a condition like 'co2>1200', got {:?}", s));
+        };
+
+        let metric: Metric = s[..idx].trim().parse()?;
+        let rhs = s[idx + op.len()..].trim();
+        let value = match (metric, rhs.to_ascii_lowercase().as_str()) {
+            (Metric::Status, "green") => 1.0,
+            (Metric::Status, "yellow") => 2.0,
+            (Metric::Status, "red") => 3.0,
+            _ => rhs.parse().map_err(|_| format!("invalid value {:?} in condition {:?}", rhs, s))?,
+        };
+
+        Ok(Condition { metric, comparison, value })
+    }
+}
@@ -0,0 +1,228 @@
+//! Publishes readings to NATS, for --nats.
+//!
+//! Each value of a new reading is published to `<prefix>.<device>.<metric>`, where the device is its alias (or
+//! peripheral ID) with characters other than letters, digits, `-` and `_` replaced by `_`, and the payload is the
+//! value as text, as for --graphite. Messages carry headers so they can be captured by a JetStream stream:
+//! `Nats-Msg-Id` lets the stream drop duplicates (such as from two collectors hearing the same device), and
+//! `Aranet-Measured-At` is when the reading was measured, in seconds since the Unix epoch. Device tags are sent as
+//! `Aranet-Tag-<key>` headers.
+//!
+//! Messages are sent over a connection kept open between readings, and reconnected when it fails. TLS isn't
+//! supported.
+//!
+//! Protocol reference: https://docs.nats.io/reference/reference-protocols/nats-protocol
+
+use std::io;
+use std::time::{Duration, UNIX_EPOCH};
+
+use aranet::DiscoveredAranet;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufStream};
+use tokio::net::TcpStream;
+
+use crate::metric::Metric;
+use crate::seen::SeenMeasurements;
+
+/// Default port of a NATS server
+pub const DEFAULT_PORT: u16 = 4222;
+
+/// How long to wait for the server to reply
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The metrics published for each reading
+const METRICS: [Metric; 7] = [
+    Metric::Co2, Metric::TemperatureC, Metric::Humidity, Metric::Pressure, Metric::Battery, Metric::Status, Metric::Age,
+];
+
+/// Where to connect to, from a `nats://[user:password@]host[:port]` or `nats://token@host[:port]` URL
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NatsUrl {
+    /// `host:port`
+    server: String,
+    user: Option<String>,
+    password: Option<String>,
+    token: Option<String>,
+}
+
+/// Parses a --nats URL
+pub fn parse_url(s: &str) -> Result<NatsUrl, String> {
+    let authority = match s.split_once("://") {
+        Some(("nats", rest)) => rest,
+        Some(("tls", _)) => return Err("TLS connections to NATS (tls://) aren't supported".to_owned()),
+        Some((scheme, _)) => return Err(format!("unsupported scheme {:?}, expected nats://", scheme)),
+        None => s,
+    };
+    let authority = authority.trim_end_matches('/');
+    let (credentials, host) = match authority.rsplit_once('@') {
+        Some((credentials, host)) => (Some(credentials), host),
+        None => (None, authority),
+    };
+    let (user, password, token) = match credentials.map(|c| c.split_once(':')) {
+        Some(Some((user, password))) => (Some(user.to_owned()), Some(password.to_owned()), None),
+        Some(None) => (None, None, credentials.map(str::to_owned)),
+        None => (None, None, None),
+    };
+    if host.is_empty() {
+        return Err(format!("no host in NATS URL {:?}", s));
+    }
+    let has_port = match host.rsplit_once(':') {
+        Some((host, port)) => port.parse::<u16>().is_ok() && (!host.contains(':') || host.starts_with('[')),
+        None => false,
+    };
+    let server = if has_port { host.to_owned() } else { format!("{}:{}", host, DEFAULT_PORT) };
+    Ok(NatsUrl { server, user, password, token })
+}
+
+/// Replaces characters that would split a subject into more tokens, or act as a wildcard
+fn subject_token(s: &str) -> String {
+    s.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' }).collect()
+}
+
+/// Encodes an `HPUB` message with these headers
+fn hpub(subject: &str, headers: &[(&str, &str)], payload: &str) -> String {
+    let mut block = "NATS/1.0\r\n".to_owned();
+    for (name, value) in headers {
+        block.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    block.push_str("\r\n");
+    format!("HPUB {} {} {}\r\n{}{}\r\n", subject, block.len(), block.len() + payload.len(), block, payload)
+}
+
+pub struct NatsPublisher {
+    url: NatsUrl,
+    prefix: String,
+    connection: Option<BufStream<TcpStream>>,
+    /// To only publish each measurement once
+    seen: SeenMeasurements,
+}
+
+impl NatsPublisher {
+    pub fn new(url: NatsUrl, prefix: String) -> NatsPublisher {
+        let prefix = prefix.trim_matches('.').to_owned();
+        NatsPublisher { url, prefix, connection: None, seen: SeenMeasurements::new() }
+    }
+
+    /// Reads a line from the server, without its CRLF
+    async fn read_line(connection: &mut BufStream<TcpStream>) -> io::Result<String> {
+        let mut line = String::new();
+        let read = tokio::time::timeout(REPLY_TIMEOUT, connection.read_line(&mut line)).await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "no reply from NATS"))??;
+        if read == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed by NATS"));
+        }
+        Ok(line.trim_end().to_owned())
+    }
+
+    /// Sends a `PING` and waits for its `PONG`, so that anything sent before it has been processed. Errors the
+    /// server reports in the meantime, such as for authentication, are returned.
+    async fn flush(connection: &mut BufStream<TcpStream>) -> io::Result<()> {
+        connection.write_all(b"PING\r\n").await?;
+        connection.flush().await?;
+        loop {
+            let line = Self::read_line(connection).await?;
+            match line.split_once(' ').map_or(line.as_str(), |(op, _)| op) {
+                "PONG" => return Ok(()),
+                "PING" => {
+                    connection.write_all(b"PONG\r\n").await?;
+                    connection.flush().await?;
+                },
+                "-ERR" => return Err(io::Error::other(format!("NATS replied {}", &line[5..]))),
+                _ => {},
+            }
+        }
+    }
+
+    /// Connects, and authenticates if needed
+    async fn connect(&self) -> io::Result<BufStream<TcpStream>> {
+        let mut connection = BufStream::new(TcpStream::connect(&self.url.server).await?);
+        let info = Self::read_line(&mut connection).await?;
+        let info: serde_json::Value = info.strip_prefix("INFO ").and_then(|info| serde_json::from_str(info).ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "not a NATS server"))?;
+        if info["tls_required"] == true {
+            return Err(io::Error::other("the NATS server requires TLS, which isn't supported"));
+        }
+        if info["headers"] != true {
+            return Err(io::Error::other("the NATS server doesn't support headers, which need NATS 2.2 or later"));
+        }
+
+        let mut options = serde_json::json!({
+            "verbose": false,
+            "pedantic": false,
+            "headers": true,
+            "lang": "rust",
+            "version": env!("CARGO_PKG_VERSION"),
+            "name": "aranet",
+        });
+        if let Some(user) = &self.url.user {
+            options["user"] = user.as_str().into();
+        }
+        if let Some(password) = &self.url.password {
+            options["pass"] = password.as_str().into();
+        }
+        if let Some(token) = &self.url.token {
+            options["auth_token"] = token.as_str().into();
+        }
+        connection.write_all(format!("CONNECT {}\r\n", options).as_bytes()).await?;
+        Self::flush(&mut connection).await?;
+        log::debug!("connected to nats {}", self.url.server);
+        Ok(connection)
+    }
+
+    /// `HPUB` messages for a reading. Unavailable values (eg: CO2 during calibration) are skipped.
+    fn messages(&self, adv: &DiscoveredAranet) -> String {
+        let Some(r) = adv.current_reading else { return String::new() };
+        let measured = adv.received.checked_sub(Duration::from_secs(r.age as u64)).unwrap_or(adv.received);
+        let timestamp = measured.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0).to_string();
+        let device = subject_token(&crate::alias::display(adv));
+        let tags: Vec<(String, &str)> = crate::tags::of(adv).into_iter()
+            .map(|(key, value)| (format!("Aranet-Tag-{}", key), value))
+            .collect();
+
+        METRICS.iter()
+            .filter_map(|metric| {
+                let value = metric.format(&r)?;
+                let subject = match self.prefix.is_empty() {
+                    true => format!("{}.{}", device, metric),
+                    false => format!("{}.{}.{}", self.prefix, device, metric),
+                };
+                let id = format!("{}.{}", subject, timestamp);
+                let mut headers = vec![("Nats-Msg-Id", id.as_str()), ("Aranet-Measured-At", timestamp.as_str())];
+                headers.extend(tags.iter().map(|(name, value)| (name.as_str(), *value)));
+                Some(hpub(&subject, &headers, &value))
+            })
+            .collect()
+    }
+
+    /// Sends the messages over the open connection, connecting first if needed
+    async fn send(&mut self, messages: &str) -> io::Result<()> {
+        let connection = match &mut self.connection {
+            Some(connection) => connection,
+            None => self.connection.insert(self.connect().await?),
+        };
+        connection.write_all(messages.as_bytes()).await?;
+        Self::flush(connection).await
+    }
+
+    /// Publishes the advertisement's reading, if it's a new measurement
+    pub async fn reading(&mut self, adv: &DiscoveredAranet) -> io::Result<()> {
+        if adv.current_reading.is_none() || ! self.seen.is_new(adv) {
+            return Ok(());
+        }
+        let messages = self.messages(adv);
+        log::debug!("publishing {} metrics to nats {}", messages.matches("HPUB ").count(), self.url.server);
+
+        let reused = self.connection.is_some();
+        let mut result = self.send(&messages).await;
+        if let Err(e) = &result {
+            // the server may have closed the connection, or restarted. Duplicates are dropped by Nats-Msg-Id.
+            self.connection = None;
+            if reused {
+                log::debug!("reconnecting to nats {} after: {}", self.url.server, e);
+                result = self.send(&messages).await;
+                if result.is_err() {
+                    self.connection = None;
+                }
+            }
+        }
+        result
+    }
+}
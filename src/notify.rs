@@ -0,0 +1,83 @@
+//! Desktop notifications when the air quality worsens or the battery runs low, for use on a workstation.
+
+use std::collections::HashMap;
+
+use aranet::{DiscoveredAranet, DisplayStatus};
+use notify_rust::{Notification, Urgency};
+
+#[derive(Debug, Default, Clone, Copy)]
+struct DeviceState {
+    status: Option<DisplayStatus>,
+    battery_low: bool,
+}
+
+pub struct DesktopNotifier {
+    /// Battery percentage below which to notify
+    battery_threshold: f32,
+    devices: HashMap<String, DeviceState>,
+}
+
+impl DesktopNotifier {
+    pub fn new(battery_threshold: f32) -> DesktopNotifier {
+        DesktopNotifier { battery_threshold, devices: HashMap::new() }
+    }
+
+    /// Notifies when the CO2 status rises into yellow or red, or the battery drops below the threshold.
+    ///
+    /// Each is only notified when crossed, rather than for every reading while it stays there.
+    pub async fn reading(&mut self, adv: &DiscoveredAranet) {
+        let Some(r) = &adv.current_reading else { return };
+        let device = crate::alias::display(adv);
+        let prev = self.devices.get(&device).copied().unwrap_or_default();
+        let battery_low = r.battery * 100.0 < self.battery_threshold;
+        self.devices.insert(device.clone(), DeviceState { status: Some(r.status), battery_low });
+
+        let worsened = prev.status.map(|s| (r.status as u8) > (s as u8)).unwrap_or(r.status != DisplayStatus::Green);
+        if worsened {
+            let (summary, urgency) = match r.status {
+                DisplayStatus::Red => ("CO2 is high", Urgency::Critical),
+                _ => ("CO2 is rising", Urgency::Normal),
+            };
+            let body = match r.co2_ppm {
+                Some(ppm) => format!("{} ppm at {}, consider airing out the room", ppm, device),
+                None => format!("CO2 status is {:?} at {}", r.status, device),
+            };
+            show(summary, body, urgency).await;
+        }
+
+        if battery_low && ! prev.battery_low {
+            show("Aranet4 battery low", format!("{:.0}% remaining at {}", r.battery * 100.0, device), Urgency::Normal).await;
+        }
+    }
+
+    /// Notifies about an alert from the config file firing or resolving
+    #[cfg(feature = "config")]
+    pub async fn alert(&self, event: &crate::alerts::AlertEvent) {
+        let urgency = match event.transition {
+            crate::alerts::Transition::Firing => Urgency::Critical,
+            crate::alerts::Transition::Resolved => Urgency::Low,
+        };
+        let body = format!("{} is {:.*} at {} (threshold {})",
+            event.metric, event.metric.precision(), event.value, event.device, event.threshold);
+        show(&format!("Alert {} {}", event.name, event.transition), body, urgency).await;
+    }
+}
+
+async fn show(summary: &str, body: String, urgency: Urgency) {
+    let mut notification = Notification::new();
+    notification.appname("aranet").summary(summary).body(&body);
+    // urgency is a freedesktop extension, unsupported elsewhere
+    #[cfg(all(unix, not(target_os = "macos")))]
+    notification.urgency(urgency);
+    #[cfg(not(all(unix, not(target_os = "macos"))))]
+    let _ = urgency;
+
+    log::debug!("showing desktop notification: {}: {}", summary, body);
+    // the notification server is talked to synchronously
+    let result = tokio::task::spawn_blocking(move || notification.show().map(|_| ())).await;
+    match result {
+        Ok(Ok(())) => {},
+        Ok(Err(e)) => log::warn!("unable to show desktop notification: {}", e),
+        Err(e) => log::warn!("unable to show desktop notification: {}", e),
+    }
+}
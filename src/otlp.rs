@@ -0,0 +1,125 @@
+//! Exports readings as OpenTelemetry gauges to an OTLP/HTTP endpoint, such as an OpenTelemetry Collector.
+//!
+//! Uses the JSON encoding of OTLP, which collectors accept alongside protobuf on the same `/v1/metrics` path. Each
+//! device is its own resource, identified by `device.id` and its alias (if it has one) as `aranet.alias`.
+
+use std::time::{Duration, UNIX_EPOCH};
+
+use aranet::DiscoveredAranet;
+use serde_json::{json, Value};
+
+use crate::metric::Metric;
+use crate::seen::SeenMeasurements;
+
+/// Delay before the first retry, doubling for each one after
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The exported metrics, as (metric, name, unit in UCUM, description)
+const GAUGES: [(Metric, &str, &str, &str); 5] = [
+    (Metric::Co2, "aranet.co2", "ppm", "CO2 concentration"),
+    (Metric::TemperatureC, "aranet.temperature", "Cel", "Temperature"),
+    (Metric::Humidity, "aranet.humidity", "%", "Relative humidity"),
+    (Metric::Pressure, "aranet.pressure", "hPa", "Atmospheric pressure"),
+    (Metric::Battery, "aranet.battery", "%", "Battery level"),
+];
+
+pub struct OtlpExporter {
+    client: reqwest::Client,
+    url: String,
+    headers: Vec<(String, String)>,
+    retries: u32,
+    /// To only export each measurement once
+    seen: SeenMeasurements,
+}
+
+fn attribute(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+impl OtlpExporter {
+    /// Exports to `endpoint`, with `/v1/metrics` appended as for `OTEL_EXPORTER_OTLP_ENDPOINT` unless it's already
+    /// there
+    pub fn new(endpoint: String, headers: Vec<(String, String)>, retries: u32) -> OtlpExporter {
+        let client = reqwest::Client::builder()
+            .user_agent(concat!("aranet/", env!("CARGO_PKG_VERSION")))
+            .timeout(Duration::from_secs(10))
+            .build()
+            .expect("unable to initialize HTTP client");
+        let url = match endpoint.trim_end_matches('/') {
+            url if url.ends_with("/v1/metrics") => url.to_owned(),
+            url => format!("{}/v1/metrics", url),
+        };
+        OtlpExporter { client, url, headers, retries, seen: SeenMeasurements::new() }
+    }
+
+    /// Exports the advertisement's reading, if it's a new measurement
+    pub async fn reading(&mut self, adv: &DiscoveredAranet) -> Result<(), reqwest::Error> {
+        let Some(reading) = adv.current_reading else { return Ok(()) };
+        if ! self.seen.is_new(adv) {
+            return Ok(());
+        }
+
+        let measured = adv.received.checked_sub(Duration::from_secs(reading.age as u64)).unwrap_or(adv.received);
+        let time = measured.duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0).to_string();
+        let metrics: Vec<Value> = GAUGES.iter()
+            .filter_map(|(metric, name, unit, description)| {
+                let value = metric.value(&reading)?;
+                Some(json!({
+                    "name": name,
+                    "unit": unit,
+                    "description": description,
+                    "gauge": { "dataPoints": [{ "timeUnixNano": time, "asDouble": value }] },
+                }))
+            })
+            .collect();
+
+        let mut attributes = vec![
+            attribute("service.name", "aranet"),
+            attribute("device.id", &adv.peripheral_id.to_string()),
+            attribute("device.manufacturer", "SAF Tehnika"),
+            attribute("device.model.name", "Aranet4"),
+        ];
+        if let Some(name) = crate::alias::name(adv) {
+            attributes.push(attribute("aranet.alias", name));
+        }
+        for (key, value) in crate::tags::of(adv) {
+            attributes.push(attribute(key, value));
+        }
+        let body = json!({
+            "resourceMetrics": [{
+                "resource": { "attributes": attributes },
+                "scopeMetrics": [{
+                    "scope": { "name": "aranet", "version": env!("CARGO_PKG_VERSION") },
+                    "metrics": metrics,
+                }],
+            }],
+        });
+        self.post(serde_json::to_vec(&body).expect("unable to serialize OTLP metrics as JSON")).await
+    }
+
+    /// POSTs the body, retrying failed requests with exponential backoff
+    async fn post(&self, body: Vec<u8>) -> Result<(), reqwest::Error> {
+        let mut backoff = INITIAL_BACKOFF;
+        let mut attempt = 0;
+        loop {
+            let mut request = self.client.post(&self.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body.clone());
+            for (name, value) in &self.headers {
+                request = request.header(name, value);
+            }
+
+            log::debug!("exporting metrics to {}", self.url);
+            match request.send().await.and_then(|r| r.error_for_status()) {
+                Ok(_) => return Ok(()),
+                Err(e) if attempt < self.retries => {
+                    log::debug!("OTLP export failed, retrying in {:?}: {}", backoff, e);
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                },
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
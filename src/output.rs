@@ -0,0 +1,406 @@
+//! Appending readings to a file, for --output, rotating it by size or age.
+//!
+//! Readings are written as JSON Lines, or with --output-format=csv, as the records of `aranet export --to csv` with a
+//! header line at the start of each file.
+//!
+//! Rotation works like logrotate's default: the file is renamed to `FILE.1` (shifting older files to `FILE.2` and so
+//! on), and a new file is started. Files beyond --rotate-keep are deleted. With --rotate-gzip, rotated files are
+//! compressed with the system's `gzip`, becoming `FILE.1.gz`. If that fails, the uncompressed `FILE.1` is kept, and the
+//! file isn't rotated again until it can be compressed, rather than overwriting it.
+//!
+//! Logged files are read back by [`read`], such as for `aranet stats`.
+
+use std::error::Error;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use aranet::DiscoveredAranet;
+use btleplug::platform::PeripheralId;
+use serde_json::Value;
+
+use crate::filter::DeviceFilter;
+use crate::metric::Metric;
+use crate::seen::SeenMeasurements;
+
+/// When to start a new file
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Rotation {
+    /// Once the file would grow beyond this many bytes
+    pub max_size: Option<u64>,
+    /// Once the file was started this long ago
+    pub every: Option<Duration>,
+    /// The number of rotated files to keep
+    pub keep: usize,
+    /// Compress rotated files with gzip
+    pub gzip: bool,
+}
+
+/// How readings are written to the --output file
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FileFormat {
+    /// One JSON object per line, as --format=json. Can be read back by `aranet stats` and `aranet export`
+    #[default]
+    Json,
+    /// Comma separated values, with a header line, as `aranet export --to csv`
+    Csv,
+}
+
+pub struct OutputFile {
+    path: PathBuf,
+    rotation: Rotation,
+    format: FileFormat,
+    /// The open file, with its size and when it was started
+    file: Option<(File, u64, SystemTime)>,
+    /// To only write each measurement once
+    seen: SeenMeasurements,
+}
+
+/// Parses a --rotate-size: bytes, or a number with a suffix of K, M, or G (powers of 1024)
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let trimmed = s.trim().trim_end_matches(['b', 'B']);
+    let (num, unit) = match trimmed.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => (&trimmed[..i], c.to_ascii_uppercase()),
+        _ => (trimmed, ' '),
+    };
+    let multiplier: u64 = match unit {
+        ' ' => 1,
+        'K' => 1 << 10,
+        'M' => 1 << 20,
+        'G' => 1 << 30,
+        _ => return Err(format!("invalid size {:?}, expected bytes or a suffix of K, M, or G", s)),
+    };
+    match num.trim().parse::<u64>() {
+        Ok(0) => Err("size must be greater than 0".to_owned()),
+        Ok(n) => n.checked_mul(multiplier).ok_or_else(|| format!("size {:?} is too large", s)),
+        Err(_) => Err(format!("invalid size {:?}, expected bytes or a suffix of K, M, or G", s)),
+    }
+}
+
+impl OutputFile {
+    pub fn new(path: PathBuf, rotation: Rotation, format: FileFormat) -> OutputFile {
+        OutputFile { path, rotation, format, file: None, seen: SeenMeasurements::new() }
+    }
+
+    /// The path of the `n`th most recently rotated file
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut path = OsString::from(self.path.as_os_str());
+        path.push(format!(".{}", n));
+        if self.rotation.gzip {
+            path.push(".gz");
+        }
+        PathBuf::from(path)
+    }
+
+    /// Opens the file for appending, continuing an existing one from when it was created
+    fn open(&self) -> io::Result<(File, u64, SystemTime)> {
+        let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        let meta = file.metadata()?;
+        let started = meta.created().or_else(|_| meta.modified()).unwrap_or_else(|_| SystemTime::now());
+        Ok((file, meta.len(), started))
+    }
+
+    fn needs_rotation(&self, size: u64, started: SystemTime, additional: u64) -> bool {
+        let too_large = self.rotation.max_size.is_some_and(|max| size + additional > max);
+        let too_old = self.rotation.every.is_some_and(|every| started.elapsed().is_ok_and(|age| age >= every));
+        // a single line larger than --rotate-size still has to go somewhere
+        size > 0 && (too_large || too_old)
+    }
+
+    /// Moves the current file to `FILE.1`, shifting and pruning older ones
+    async fn rotate(&mut self) -> io::Result<()> {
+        let mut first = OsString::from(self.path.as_os_str());
+        first.push(".1");
+        // the last compression failed, so try again rather than overwrite it
+        if self.rotation.gzip && self.rotation.keep > 0 && Path::new(&first).exists() && ! compress(&first).await {
+            log::warn!("not rotating --output {} until {} is compressed", self.path.display(), first.to_string_lossy());
+            return Ok(());
+        }
+
+        self.file = None;
+        if self.rotation.keep == 0 {
+            return fs::remove_file(&self.path);
+        }
+
+        match fs::remove_file(self.rotated(self.rotation.keep)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {},
+        }
+        for n in (1..self.rotation.keep).rev() {
+            match fs::rename(self.rotated(n), self.rotated(n + 1)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {},
+            }
+        }
+
+        fs::rename(&self.path, &first)?;
+        log::debug!("rotated --output {} to {}", self.path.display(), first.to_string_lossy());
+
+        if self.rotation.gzip {
+            // a failed compression leaves the file uncompressed, rather than losing it
+            compress(&first).await;
+        }
+        Ok(())
+    }
+
+    /// The advertisement's reading as a line of the file's format
+    fn line(&self, adv: &DiscoveredAranet) -> Option<String> {
+        let named = crate::NamedAranet::new(adv);
+        match self.format {
+            FileFormat::Json => {
+                let mut line = serde_json::to_string(&named).expect("unable to serialize reading as JSON");
+                line.push('\n');
+                Some(line)
+            },
+            FileFormat::Csv => {
+                // read back as `aranet export` would, for the same columns
+                let value = serde_json::to_value(&named).expect("unable to serialize reading as JSON");
+                let logged = serde_json::from_value(value).ok().and_then(Logged::from_line)?;
+                Some(crate::export::csv_row(&logged))
+            },
+        }
+    }
+
+    /// Appends the advertisement's reading to the file, if it's a new measurement, rotating the file first if it's
+    /// due
+    pub async fn write(&mut self, adv: &DiscoveredAranet) -> io::Result<()> {
+        if ! self.seen.is_new(adv) {
+            return Ok(());
+        }
+        let Some(mut line) = self.line(adv) else { return Ok(()) };
+
+        let (size, started) = match &self.file {
+            Some((_, size, started)) => (*size, *started),
+            None => {
+                let opened = self.open()?;
+                let state = (opened.1, opened.2);
+                self.file = Some(opened);
+                state
+            },
+        };
+        if self.needs_rotation(size, started, line.len() as u64) {
+            self.rotate().await?;
+            self.file = Some(self.open()?);
+        }
+
+        let (file, size, _) = self.file.as_mut().expect("output file is open");
+        if self.format == FileFormat::Csv && *size == 0 {
+            line.insert_str(0, &crate::export::csv_header());
+        }
+        file.write_all(line.as_bytes())?;
+        *size += line.len() as u64;
+        Ok(())
+    }
+}
+
+/// Compresses a rotated file with gzip, returning whether it was
+async fn compress(path: &OsStr) -> bool {
+    match tokio::process::Command::new("gzip").arg("-f").arg(path).status().await {
+        Ok(status) if status.success() => true,
+        Ok(status) => {
+            log::warn!("unable to compress {}: gzip exited with {}", path.to_string_lossy(), status);
+            false
+        },
+        Err(e) => {
+            log::warn!("unable to compress {}: unable to run gzip: {}", path.to_string_lossy(), e);
+            false
+        },
+    }
+}
+
+/// A line of an --output file
+#[derive(serde::Deserialize)]
+struct LoggedLine {
+    #[serde(default)]
+    name: Option<String>,
+    peripheral_id: PeripheralId,
+    current_reading: Option<LoggedValues>,
+    #[serde(default)]
+    measured_at: Option<Value>,
+}
+
+/// A reading read back from an --output file
+pub struct Logged {
+    /// The peripheral ID of the device
+    pub device: String,
+    /// The device's alias when the reading was logged, if it had one
+    pub name: Option<String>,
+    pub values: LoggedValues,
+    /// Seconds since the Unix epoch when the reading was measured, if it was logged with a timestamp
+    pub measured: Option<i64>,
+}
+
+/// The values of a reading as logged, in the units of the JSON output
+#[derive(serde::Deserialize)]
+pub struct LoggedValues {
+    co2_ppm: Option<f64>,
+    temperature_c: Option<f64>,
+    pressure_hpa: Option<f64>,
+    humidity: f64,
+    battery: f64,
+    /// The CO2 status, such as `Green`
+    pub status: String,
+    pub interval: u16,
+    age: u16,
+}
+
+impl Logged {
+    /// The reading of a logged line, if it has one
+    fn from_line(line: LoggedLine) -> Option<Logged> {
+        let values = line.current_reading?;
+        let measured = line.measured_at.as_ref().and_then(epoch_secs);
+        Some(Logged { device: line.peripheral_id.to_string(), name: line.name, values, measured })
+    }
+}
+
+impl LoggedValues {
+    /// The value of the metric, as with [`Metric::value`]
+    pub fn value(&self, metric: Metric) -> Option<f64> {
+        match metric {
+            Metric::Co2 => self.co2_ppm,
+            Metric::TemperatureC => self.temperature_c,
+            Metric::TemperatureF => self.temperature_c.map(|c| c * 9.0 / 5.0 + 32.0),
+            Metric::Humidity => Some((self.humidity * 100.0).round()),
+            Metric::Pressure => self.pressure_hpa,
+            Metric::Battery => Some((self.battery * 100.0).round()),
+            Metric::Status => match self.status.to_ascii_lowercase().as_str() {
+                "green" => Some(1.0),
+                "yellow" => Some(2.0),
+                "red" => Some(3.0),
+                _ => None,
+            },
+            Metric::Age => Some(self.age as f64),
+        }
+    }
+}
+
+/// Seconds since the Unix epoch of a logged timestamp, in either --timestamp-format
+fn epoch_secs(stamp: &Value) -> Option<i64> {
+    match stamp {
+        Value::Number(n) => n.as_i64(),
+        #[cfg(feature = "timestamps")]
+        Value::String(s) => s.parse::<jiff::Timestamp>().ok().map(|t| t.as_second()),
+        _ => None,
+    }
+}
+
+/// Opens a logged file, decompressing it with gzip if it was compressed by --rotate-gzip. `-` is stdin.
+fn open_logged(path: &Path) -> io::Result<Box<dyn BufRead>> {
+    if path == Path::new("-") {
+        return Ok(Box::new(io::stdin().lock()));
+    }
+    if path.extension().is_some_and(|ext| ext == "gz") {
+        let output = std::process::Command::new("gzip").arg("-dc").arg(path).output()
+            .map_err(|e| io::Error::new(e.kind(), format!("unable to run gzip: {}", e)))?;
+        if ! output.status.success() {
+            let msg = String::from_utf8_lossy(&output.stderr).trim().to_owned();
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("gzip exited with {}: {}", output.status, msg)));
+        }
+        return Ok(Box::new(io::Cursor::new(output.stdout)));
+    }
+    Ok(Box::new(BufReader::new(File::open(path)?)))
+}
+
+/// Reads the readings logged to these files from matching devices, measured within `since` before now if given.
+/// Lines that aren't readings are skipped with a warning.
+pub fn read(paths: &[PathBuf], filter: &DeviceFilter, since: Option<Duration>) -> Result<Vec<Logged>, Box<dyn Error>> {
+    let cutoff = since
+        .map(|since| SystemTime::now().checked_sub(since).unwrap_or(UNIX_EPOCH))
+        .map(|cutoff| cutoff.duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0));
+
+    let mut logged = Vec::new();
+    let mut untimed = 0;
+    for path in paths {
+        let reader = open_logged(path).map_err(|e| format!("unable to read {}: {}", path.display(), e))?;
+        for (i, line) in reader.lines().enumerate() {
+            let line = line.map_err(|e| format!("unable to read {}: {}", path.display(), e))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let parsed: LoggedLine = match serde_json::from_str(&line) {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    log::warn!("skipping line {} of {}: {}", i + 1, path.display(), e);
+                    continue;
+                },
+            };
+            if ! filter.matches_id(&parsed.peripheral_id) {
+                continue;
+            }
+            let Some(reading) = Logged::from_line(parsed) else { continue };
+            match (cutoff, reading.measured) {
+                (Some(cutoff), Some(measured)) if measured < cutoff => continue,
+                (Some(_), None) => {
+                    untimed += 1;
+                    continue;
+                },
+                _ => {},
+            }
+            logged.push(reading);
+        }
+    }
+    if untimed > 0 {
+        log::warn!("skipped {} readings without a measured_at timestamp, as --since needs one", untimed);
+    }
+    Ok(logged)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    /// A reading measured `age` seconds ago, so each age is a new measurement
+    fn reading(age: u8) -> DiscoveredAranet {
+        let data = [
+            0x20, 0x13, 0x04, 0x01, 0x00, 0x0c, 0x0f, 0x01,
+            0x64, 0x02, 0xc9, 0x01, 0x8b, 0x27, 0x29, 0x57, 0x01, 0x2c, 0x01, age, 0x00,
+        ];
+        DiscoveredAranet::parse(None, crate::simulate::peripheral_id(0), &data).unwrap()
+    }
+
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("aranet-output-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn rotates_csv_with_headers() {
+        let dir = dir("csv");
+        let path = dir.join("readings.csv");
+        let rotation = Rotation { max_size: Some(1), keep: 2, ..Rotation::default() };
+        let mut output = OutputFile::new(path.clone(), rotation, FileFormat::Csv);
+        output.write(&reading(42)).await.unwrap();
+        output.write(&reading(12)).await.unwrap();
+
+        for path in [path.clone(), dir.join("readings.csv.1")] {
+            let written = fs::read_to_string(&path).unwrap();
+            let lines: Vec<&str> = written.lines().collect();
+            assert_eq!(lines.len(), 2, "{}", written);
+            assert_eq!(lines[0], crate::export::csv_header().trim_end());
+            assert!(lines[1].contains(",612,"), "{}", lines[1]);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn keeps_uncompressed_rotations() {
+        let dir = dir("gzip");
+        let path = dir.join("readings.jsonl");
+        let first = dir.join("readings.jsonl.1");
+        // as if the last compression failed, and this one will too
+        fs::write(&first, "old\n").unwrap();
+        fs::create_dir(dir.join("readings.jsonl.1.gz")).unwrap();
+        let rotation = Rotation { max_size: Some(1), keep: 2, gzip: true, ..Rotation::default() };
+        let mut output = OutputFile::new(path.clone(), rotation, FileFormat::Json);
+        output.write(&reading(42)).await.unwrap();
+        output.write(&reading(12)).await.unwrap();
+
+        assert_eq!(fs::read_to_string(&first).unwrap(), "old\n");
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
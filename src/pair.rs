@@ -0,0 +1,136 @@
+//! Pairs with a device through BlueZ, entering the PIN shown on its screen, for `aranet pair`.
+//!
+//! With its Smart Home integrations disabled, an Aranet4 only lets bonded devices read its measurements, so this is
+//! needed once before --active or --prefer-connect will work. btleplug can't pair, so this registers a BlueZ agent
+//! over D-Bus, which BlueZ asks for the PIN while pairing.
+
+use std::error::Error;
+use std::io::{self, BufRead, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use btleplug::platform::Manager;
+use dbus::channel::MatchingReceiver;
+use dbus::message::MatchRule;
+use dbus::nonblock::stdintf::org_freedesktop_dbus::Properties;
+use dbus::nonblock::{Proxy, SyncConnection};
+use dbus::{MethodErr, Path};
+use dbus_crossroads::{Crossroads, IfaceBuilder};
+use futures::StreamExt;
+
+use crate::filter::DeviceFilter;
+
+/// How long to wait for the device to advertise, if no --timeout is given
+const DEFAULT_TIMEOUT: f64 = 30.0;
+/// Long enough to read the PIN off the device and type it in
+const PAIR_TIMEOUT: Duration = Duration::from_secs(120);
+
+const AGENT_PATH: &str = "/io/github/aranet/agent";
+const DEVICE_INTERFACE: &str = "org.bluez.Device1";
+const AGENT_MANAGER_INTERFACE: &str = "org.bluez.AgentManager1";
+
+/// Asks for the PIN on the terminal, as the 6 digit passkey BlueZ expects
+async fn prompt_passkey(device: Path<'static>) -> Result<u32, MethodErr> {
+    let line = tokio::task::spawn_blocking(move || {
+        eprint!("Enter the PIN shown on the device's screen ({}): ", device);
+        io::stderr().flush()?;
+        let mut line = String::new();
+        io::stdin().lock().read_line(&mut line)?;
+        Ok::<_, io::Error>(line)
+    }).await
+        .map_err(|e| MethodErr::failed(&e))?
+        .map_err(|e| MethodErr::failed(&e))?;
+    line.trim().parse::<u32>().ok()
+        .filter(|pin| *pin <= 999_999)
+        .ok_or_else(|| ("org.bluez.Error.Rejected", format!("invalid PIN {:?}, expected up to 6 digits", line.trim())).into())
+}
+
+/// Serves `org.bluez.Agent1` on `conn`, prompting for the PIN when asked
+fn serve_agent(conn: &Arc<SyncConnection>) {
+    let mut cr = Crossroads::new();
+    cr.set_async_support(Some((conn.clone(), Box::new(|x| { tokio::spawn(x); }))));
+    let iface = cr.register("org.bluez.Agent1", |b: &mut IfaceBuilder<()>| {
+        b.method_with_cr_async("RequestPasskey", ("device",), ("passkey",), |mut ctx, _, (device,): (Path<'static>,)| async move {
+            let passkey = prompt_passkey(device).await;
+            ctx.reply(passkey.map(|p| (p,)))
+        });
+        // legacy pairing asks for the same PIN as a string
+        b.method_with_cr_async("RequestPinCode", ("device",), ("pincode",), |mut ctx, _, (device,): (Path<'static>,)| async move {
+            let pin = prompt_passkey(device).await;
+            ctx.reply(pin.map(|p| (format!("{:06}", p),)))
+        });
+        b.method("Cancel", (), (), |_, _, ()| {
+            eprintln!();
+            log::warn!("pairing was cancelled by the device");
+            Ok(())
+        });
+        b.method("Release", (), (), |_, _, ()| Ok(()));
+    });
+    cr.insert(AGENT_PATH, &[iface], ());
+
+    let cr = Arc::new(Mutex::new(cr));
+    conn.start_receive(MatchRule::new_method_call(), Box::new(move |msg, conn| {
+        let _ = cr.lock().unwrap().handle_message(msg, conn);
+        true
+    }));
+}
+
+/// Pairs with the first device matching the filter, waiting at most `timeout` seconds for it to advertise, then checks
+/// the bond works by reading a measurement from it
+pub async fn run(filter: DeviceFilter, timeout: Option<f64>) -> Result<(), Box<dyn Error>> {
+    let manager = Manager::new().await?;
+    let mut discovered = crate::lock::discover(&manager).await?;
+
+    let timeout = timeout.unwrap_or(DEFAULT_TIMEOUT);
+    let find = async {
+        while let Some(adv) = discovered.next().await {
+            if filter.matches(&adv) {
+                return Some(adv);
+            }
+        }
+        None
+    };
+    let adv = match tokio::time::timeout(Duration::from_secs_f64(timeout), find).await {
+        Ok(Some(adv)) => adv,
+        Ok(None) => return Err("Unable to discover devices. No Bluetooth adapters present.".into()),
+        Err(_) => return Err(format!("No advertisement received within {}s.", timeout).into()),
+    };
+    let name = crate::alias::display(&adv);
+
+    let (resource, conn) = dbus_tokio::connection::new_system_sync()?;
+    tokio::spawn(resource);
+    // peripheral IDs are BlueZ object paths, without the `/org/bluez/` prefix
+    let device = Proxy::new("org.bluez", format!("/org/bluez/{}", adv.peripheral_id), PAIR_TIMEOUT, conn.clone());
+
+    if device.get::<bool>(DEVICE_INTERFACE, "Paired").await? {
+        println!("Already paired with {}", name);
+    } else {
+        serve_agent(&conn);
+        let agents = Proxy::new("org.bluez", "/org/bluez", Duration::from_secs(5), conn.clone());
+        agents.method_call::<(), _, _, _>(AGENT_MANAGER_INTERFACE, "RegisterAgent", (Path::from(AGENT_PATH), "KeyboardOnly")).await
+            .map_err(|e| format!("unable to register a BlueZ pairing agent: {}", e))?;
+
+        let paired = async {
+            let _lock = crate::lock::acquire().await?;
+            println!("Pairing with {}", name);
+            device.method_call::<(), _, _, _>(DEVICE_INTERFACE, "Pair", ()).await
+                .map_err(|e| format!("unable to pair with {}: {}", name, e))?;
+            // so it may reconnect without asking again
+            device.set(DEVICE_INTERFACE, "Trusted", true).await
+                .map_err(|e| format!("unable to trust {}: {}", name, e))?;
+            Ok::<_, Box<dyn Error>>(())
+        }.await;
+
+        if let Err(e) = agents.method_call::<(), _, _, _>(AGENT_MANAGER_INTERFACE, "UnregisterAgent", (Path::from(AGENT_PATH),)).await {
+            log::debug!("unable to unregister the BlueZ pairing agent: {}", e);
+        }
+        paired?;
+        println!("Paired with {}", name);
+    }
+
+    let _lock = crate::lock::acquire().await?;
+    adv.read_current_with(&crate::retry::policy()).await
+        .map_err(|e| format!("unable to read a measurement from {} using the bond: {}", name, e))?;
+    println!("Read a measurement from {} successfully", name);
+    Ok(())
+}
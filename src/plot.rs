@@ -0,0 +1,227 @@
+//! SVG time-series charts of readings logged with --output, for `aranet plot`.
+//!
+//! One line is drawn per device, broken where readings are missing (such as when the device was out of range). Parts
+//! of the value axis are shaded for the CO2 status levels, or for each --threshold condition, so periods above a
+//! limit stand out. The chart is written as plain SVG, which browsers and most image viewers can open directly.
+
+use std::error::Error;
+use std::fmt::Write as _;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use jiff::Timestamp;
+
+use crate::filter::DeviceFilter;
+use crate::metric::{Comparison, Condition, Metric};
+use crate::output;
+use crate::xml::escape;
+
+/// Space around the plotted area, for the title, axis labels, and legend: (top, right, bottom, left)
+const MARGIN: (f64, f64, f64, f64) = (40.0, 20.0, 40.0, 60.0);
+
+/// Line colors for each device, in turn
+const COLORS: [&str; 6] = ["#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd", "#8c564b"];
+
+/// Possible steps between time axis labels, in seconds
+const TIME_STEPS: [i64; 12] = [
+    60, 300, 900, 1800, 3600, 3 * 3600, 6 * 3600, 12 * 3600, 86400, 2 * 86400, 7 * 86400, 28 * 86400,
+];
+
+/// A gap longer than this many of the device's intervals breaks its line
+const GAP_INTERVALS: f64 = 2.5;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct PlotArgs {
+    /// Files of readings logged with --output, as for `aranet stats`. Use `-` for stdin
+    #[arg(value_name = "FILE", required = true)]
+    files: Vec<PathBuf>,
+    /// The metric to plot: co2, temp_c, temp_f, humidity, pressure, or battery
+    #[arg(short, long, value_name = "METRIC", default_value = "co2")]
+    metric: Metric,
+    /// Only plot readings measured within this long before now: seconds, or a duration such as `7d`
+    #[arg(long, value_name = "DURATION", value_parser = crate::parse_duration)]
+    since: Option<Duration>,
+    /// Shade the values matching these conditions of the plotted metric, such as `temp_c>26`. Defaults to the CO2
+    /// status levels when plotting CO2. May be repeated
+    #[arg(long, value_name = "CONDITION")]
+    threshold: Vec<Condition>,
+    /// Write the chart to this file, rather than stdout
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+    /// The width of the chart, in pixels
+    #[arg(long, value_name = "PIXELS", default_value_t = 900)]
+    width: u32,
+    /// The height of the chart, in pixels
+    #[arg(long, value_name = "PIXELS", default_value_t = 400)]
+    height: u32,
+}
+
+/// A shaded range of values, with its fill color
+struct Band {
+    low: f64,
+    high: f64,
+    color: &'static str,
+}
+
+impl Band {
+    fn from_condition(condition: &Condition) -> Result<Band, String> {
+        let color = "#d62728";
+        match condition.comparison {
+            Comparison::Lt | Comparison::Le => Ok(Band { low: f64::NEG_INFINITY, high: condition.value, color }),
+            Comparison::Gt | Comparison::Ge => Ok(Band { low: condition.value, high: f64::INFINITY, color }),
+            Comparison::Eq | Comparison::Ne => {
+                Err(format!("--threshold {} must be a <, <=, >, or >= comparison", condition))
+            },
+        }
+    }
+}
+
+fn unit(metric: Metric) -> &'static str {
+    match metric {
+        Metric::Co2 => "ppm",
+        Metric::TemperatureC => "°C",
+        Metric::TemperatureF => "°F",
+        Metric::Humidity | Metric::Battery => "%",
+        Metric::Pressure => "hPa",
+        Metric::Status => "level",
+        Metric::Age => "s",
+    }
+}
+
+/// A round step between value axis labels, giving about `ticks` of them over the span
+fn value_step(span: f64, ticks: f64) -> f64 {
+    let rough = span / ticks;
+    let magnitude = 10f64.powf(rough.log10().floor());
+    let step = [1.0, 2.0, 5.0, 10.0].into_iter()
+        .find(|m| m * magnitude >= rough)
+        .unwrap_or(10.0);
+    step * magnitude
+}
+
+struct Series {
+    label: String,
+    /// (measured, value, interval) of each reading, in order of measurement
+    points: Vec<(i64, f64, u16)>,
+}
+
+pub fn run(args: PlotArgs, filter: DeviceFilter) -> Result<(), Box<dyn Error>> {
+    let bands = match (args.threshold.is_empty(), args.metric) {
+        (true, Metric::Co2) => vec![
+            Band { low: f64::NEG_INFINITY, high: 1000.0, color: "#2ca02c" },
+            Band { low: 1000.0, high: 1400.0, color: "#ffbf00" },
+            Band { low: 1400.0, high: f64::INFINITY, color: "#d62728" },
+        ],
+        _ => args.threshold.iter()
+            .map(|condition| match condition.metric == args.metric {
+                true => Band::from_condition(condition),
+                false => Err(format!("--threshold {} is not of the plotted metric, {}", condition, args.metric)),
+            })
+            .collect::<Result<_, _>>()?,
+    };
+
+    let mut series: Vec<Series> = Vec::new();
+    for logged in output::read(&args.files, &filter, args.since)? {
+        let (Some(measured), Some(value)) = (logged.measured, logged.values.value(args.metric)) else { continue };
+        let label = logged.name.unwrap_or(logged.device);
+        let idx = match series.iter().position(|s| s.label == label) {
+            Some(idx) => idx,
+            None => {
+                series.push(Series { label, points: Vec::new() });
+                series.len() - 1
+            },
+        };
+        series[idx].points.push((measured, value, logged.values.interval));
+    }
+    if series.is_empty() {
+        return Err("no readings with measured_at timestamps to plot".into());
+    }
+    for s in &mut series {
+        s.points.sort_by_key(|(measured, _, _)| *measured);
+    }
+
+    let svg = chart(&args, &series, &bands);
+    match &args.output {
+        Some(path) => std::fs::write(path, svg).map_err(|e| format!("unable to write {}: {}", path.display(), e))?,
+        None => print!("{}", svg),
+    }
+    Ok(())
+}
+
+fn chart(args: &PlotArgs, series: &[Series], bands: &[Band]) -> String {
+    let (width, height) = (args.width as f64, args.height as f64);
+    let (top, right, bottom, left) = MARGIN;
+    let (plot_w, plot_h) = ((width - left - right).max(1.0), (height - top - bottom).max(1.0));
+
+    let all = || series.iter().flat_map(|s| s.points.iter());
+    let start = all().map(|p| p.0).min().unwrap_or(0);
+    let end = all().map(|p| p.0).max().unwrap_or(0).max(start + 1);
+    let (mut min, mut max) = all().fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), p| (lo.min(p.1), hi.max(p.1)));
+    let pad = ((max - min) * 0.05).max(1.0);
+    min -= pad;
+    max += pad;
+
+    let x = |t: i64| left + (t - start) as f64 / (end - start) as f64 * plot_w;
+    let y = |v: f64| top + (max - v) / (max - min) * plot_h;
+
+    let mut svg = String::new();
+    let _ = writeln!(svg, r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}" font-family="sans-serif" font-size="12">"#, w = width, h = height);
+    let _ = writeln!(svg, r#"<rect width="100%" height="100%" fill="white"/>"#);
+    let _ = writeln!(svg, r#"<text x="{}" y="20" font-size="14" font-weight="bold">{} ({})</text>"#, left, args.metric, escape(unit(args.metric)));
+
+    for band in bands {
+        let (low, high) = (band.low.max(min), band.high.min(max));
+        if low < high {
+            let _ = writeln!(svg, r#"<rect x="{}" y="{:.1}" width="{}" height="{:.1}" fill="{}" fill-opacity="0.12"/>"#,
+                left, y(high), plot_w, y(low) - y(high), band.color);
+        }
+    }
+
+    // value axis, with a grid line at each label
+    let step = value_step(max - min, 6.0);
+    let decimals = if step >= 1.0 { 0 } else { (-step.log10()).ceil() as usize };
+    let mut v = (min / step).ceil() * step;
+    while v <= max {
+        let _ = writeln!(svg, r##"<line x1="{}" y1="{y:.1}" x2="{}" y2="{y:.1}" stroke="#ddd"/>"##, left, left + plot_w, y = y(v));
+        let _ = writeln!(svg, r#"<text x="{}" y="{:.1}" text-anchor="end">{:.*}</text>"#, left - 6.0, y(v) + 4.0, decimals, v);
+        v += step;
+    }
+
+    // time axis, with labels aligned to the step within the time zone
+    let zone = crate::timestamp::zone();
+    let span = end - start;
+    let step = TIME_STEPS.into_iter().find(|s| span / s <= 8).unwrap_or(span / 8 + 1);
+    let offset = Timestamp::from_second(start).map(|t| zone.to_offset(t).seconds() as i64).unwrap_or(0);
+    let format = if step >= 86400 { "%b %d" } else if span > 86400 { "%b %d %H:%M" } else { "%H:%M" };
+    let mut t = (start + offset).div_euclid(step) * step - offset;
+    while t <= end {
+        if t >= start {
+            let label = Timestamp::from_second(t).map(|ts| ts.to_zoned(zone.clone()).strftime(format).to_string()).unwrap_or_default();
+            let _ = writeln!(svg, r##"<line x1="{x:.1}" y1="{}" x2="{x:.1}" y2="{}" stroke="#ddd"/>"##, top, top + plot_h, x = x(t));
+            let _ = writeln!(svg, r#"<text x="{:.1}" y="{}" text-anchor="middle">{}</text>"#, x(t), top + plot_h + 18.0, label);
+        }
+        t += step;
+    }
+    let _ = writeln!(svg, r#"<rect x="{}" y="{}" width="{}" height="{}" fill="none" stroke="black"/>"#, left, top, plot_w, plot_h);
+
+    // legend entries are right aligned after the title, estimating the width of their labels
+    let entry_width = |s: &Series| 16.0 + 7.0 * s.label.chars().count() as f64 + 14.0;
+    let mut lx = width - right - series.iter().map(entry_width).sum::<f64>();
+    for (i, s) in series.iter().enumerate() {
+        let color = COLORS[i % COLORS.len()];
+        let mut path = String::new();
+        let mut prev: Option<i64> = None;
+        for (measured, value, interval) in &s.points {
+            let gap = prev.is_none_or(|prev| (measured - prev) as f64 > *interval as f64 * GAP_INTERVALS);
+            let _ = write!(path, "{}{:.1},{:.1} ", if gap { "M" } else { "L" }, x(*measured), y(*value));
+            prev = Some(*measured);
+        }
+        let _ = writeln!(svg, r#"<path d="{}" fill="none" stroke="{}" stroke-width="1.5"/>"#, path.trim_end(), color);
+
+        let _ = writeln!(svg, r#"<rect x="{:.1}" y="11" width="12" height="12" fill="{}"/>"#, lx, color);
+        let _ = writeln!(svg, r#"<text x="{:.1}" y="21">{}</text>"#, lx + 16.0, escape(&s.label));
+        lx += entry_width(s);
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
@@ -0,0 +1,72 @@
+//! Decimal places of values in text and JSON output, as set by --precision.
+
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use aranet::Precision;
+
+use crate::metric::Metric;
+
+static PRECISION: OnceLock<Precision> = OnceLock::new();
+
+/// A --precision argument: decimal places for every value (`1`), or for one metric (`temp_c=2`)
+#[derive(Debug, Clone, Copy)]
+pub struct PrecisionArg {
+    metric: Option<Metric>,
+    places: usize,
+}
+
+impl FromStr for PrecisionArg {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (metric, places) = match s.split_once('=') {
+            Some((metric, places)) => (Some(metric.trim().parse::<Metric>()?), places.trim()),
+            None => (None, s.trim()),
+        };
+        let places = places.parse().ok()
+            .filter(|places| *places <= 6)
+            .ok_or_else(|| format!("invalid number of decimal places {:?}, expected 0 to 6", places))?;
+        if let Some(metric @ (Metric::Co2 | Metric::Status | Metric::Age)) = metric {
+            return Err(format!("{} is always a whole number", metric));
+        }
+        Ok(PrecisionArg { metric, places })
+    }
+}
+
+/// Sets the precision for the rest of the process. Per-metric arguments take precedence over those for every value,
+/// regardless of order. Only the first call has any effect.
+pub fn init(args: &[PrecisionArg]) {
+    let mut precision = Precision::default();
+    for arg in args.iter().filter(|arg| arg.metric.is_none()) {
+        let places = Some(arg.places);
+        precision = Precision { temperature: places, humidity: places, pressure: places, battery: places };
+    }
+    for arg in args {
+        let places = Some(arg.places);
+        match arg.metric {
+            Some(Metric::TemperatureC | Metric::TemperatureF) => precision.temperature = places,
+            Some(Metric::Humidity) => precision.humidity = places,
+            Some(Metric::Pressure) => precision.pressure = places,
+            Some(Metric::Battery) => precision.battery = places,
+            _ => {},
+        }
+    }
+    let _ = PRECISION.set(precision);
+}
+
+/// The precision for text output. Unset values keep their default for each unit.
+pub fn get() -> Precision {
+    PRECISION.get().copied().unwrap_or_default()
+}
+
+/// The precision for JSON output. Unset values are rounded to the device's resolution, so float artifacts (such as
+/// `21.400002`) aren't output.
+#[cfg(feature = "serde_json")]
+pub fn json() -> Precision {
+    Precision {
+        temperature: Some(Metric::TemperatureC.precision()),
+        humidity: Some(Metric::Humidity.precision()),
+        pressure: Some(Metric::Pressure.precision()),
+        battery: Some(Metric::Battery.precision()),
+    }
+}
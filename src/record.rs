@@ -0,0 +1,187 @@
+//! Recording advertisements to a file, and replaying them later through the normal pipeline.
+//!
+//! Recordings are JSON Lines, one advertisement per line with the unparsed manufacturer data, so they can be used to
+//! reproduce parsing bugs or test output formats without a live device. Peripheral IDs are platform specific (eg: a
+//! BlueZ object path on Linux), so a recording is best replayed on the platform it was made on.
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use aranet::DiscoveredAranet;
+use btleplug::platform::{Manager, PeripheralId};
+use futures::StreamExt;
+
+use crate::error::{CliError, ErrorKind};
+use crate::filter::DeviceFilter;
+use crate::Advertisements;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct RecordArgs {
+    /// The file to append recorded advertisements to, as JSON Lines. Defaults to stdout
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+}
+
+/// An advertisement as stored in a recording, and in the reading `--cache`
+#[derive(serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct RecordedAdvertisement {
+    /// Seconds since the Unix epoch when the advertisement was received
+    pub timestamp: f64,
+    /// The OS's identifier for the device, as within --format=json output
+    #[cfg_attr(feature = "schema", schemars(with = "serde_json::Value"))]
+    peripheral_id: PeripheralId,
+    /// Hex dump of the manufacturer data, as within --raw output
+    manufacturer_data: String,
+}
+
+impl RecordedAdvertisement {
+    /// Records an advertisement, as of when it was received
+    pub fn new(adv: &DiscoveredAranet) -> RecordedAdvertisement {
+        RecordedAdvertisement {
+            timestamp: adv.received.duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0),
+            peripheral_id: adv.peripheral_id.clone(),
+            manufacturer_data: crate::hex(&adv.raw_manufacturer_data),
+        }
+    }
+
+    /// Parses the recorded manufacturer data
+    pub fn parse(self) -> Result<DiscoveredAranet, String> {
+        let data = parse_hex(&self.manufacturer_data)
+            .filter(|data| data.len() >= 7)
+            .ok_or("manufacturer data is not at least 7 bytes of hex")?;
+        let mut adv = DiscoveredAranet::parse(None, self.peripheral_id, &data).map_err(|e| e.to_string())?;
+        adv.received = UNIX_EPOCH + Duration::from_secs_f64(self.timestamp.max(0.0));
+        Ok(adv)
+    }
+}
+
+/// Parses bytes from space separated hex, as from [`crate::hex`]
+pub fn parse_hex(s: &str) -> Option<Vec<u8>> {
+    s.split_whitespace()
+        .map(|b| u8::from_str_radix(b, 16).ok())
+        .collect()
+}
+
+/// Records advertisements from devices matching the filter, for `timeout` seconds or until interrupted
+pub async fn run(args: RecordArgs, filter: DeviceFilter, timeout: Option<f64>) -> Result<(), Box<dyn Error>> {
+    let mut output: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(File::options().create(true).append(true).open(path)
+            .map_err(|e| format!("unable to open {}: {}", path.display(), e))?),
+        None => Box::new(io::stdout()),
+    };
+
+    let manager = Manager::new().await?;
+    let mut discovered = crate::lock::discover(&manager).await?;
+
+    log::info!("recording advertisements");
+    let mut count = 0usize;
+    let record = async {
+        while let Some(adv) = discovered.next().await {
+            if ! filter.matches(&adv) {
+                continue;
+            }
+            let line = serde_json::to_string(&RecordedAdvertisement::new(&adv)).expect("unable to serialize advertisement as JSON");
+            // flushed per line, so an interrupted recording is still usable
+            writeln!(output, "{}", line)?;
+            output.flush()?;
+            count += 1;
+        }
+        Err::<(), Box<dyn Error>>("Unable to discover devices. No Bluetooth adapters present.".into())
+    };
+
+    match timeout {
+        Some(timeout) => match tokio::time::timeout(Duration::from_secs_f64(timeout), record).await {
+            Ok(result) => result?,
+            Err(_) => log::info!("recorded {} advertisements in {}s", count, timeout),
+        },
+        None => record.await?,
+    }
+    Ok(())
+}
+
+/// Loads a recording, returning its advertisements as a stream in place of [`aranet::discover_aranet4`]'s.
+///
+/// Advertisements are replayed immediately rather than at their recorded pace.
+pub fn replay(path: &Path) -> Result<Advertisements, Box<dyn Error>> {
+    let file = File::open(path).map_err(|e| format!("unable to open {}: {}", path.display(), e))?;
+
+    let mut advertisements = Vec::new();
+    for (idx, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let invalid = |reason: &dyn std::fmt::Display| {
+            CliError::new(ErrorKind::Parse, format!("{}:{}: {}", path.display(), idx + 1, reason))
+        };
+
+        let recorded: RecordedAdvertisement = serde_json::from_str(&line).map_err(|e| invalid(&e))?;
+        advertisements.push(recorded.parse().map_err(|e| invalid(&e))?);
+    }
+    log::info!("replaying {} advertisements from {}", advertisements.len(), path.display());
+
+    Ok(Box::pin(futures::stream::iter(advertisements)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADVERTISEMENT: &str = "20 13 04 01 00 0c 0f 01 64 02 c9 01 8b 27 29 57 01 2c 01 2a 00";
+
+    fn recording(name: &str, lines: &[String]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("aranet-record-test-{}-{}.jsonl", name, std::process::id()));
+        std::fs::write(&path, lines.join("\n")).unwrap();
+        path
+    }
+
+    fn line(manufacturer_data: &str) -> String {
+        serde_json::json!({
+            "timestamp": 1700000000.5,
+            "peripheral_id": crate::simulate::peripheral_id(0),
+            "manufacturer_data": manufacturer_data,
+        }).to_string()
+    }
+
+    #[tokio::test]
+    async fn replays_recordings() {
+        let path = recording("valid", &[line(ADVERTISEMENT), String::new(), line("00 00 02 01 00 0c 0f")]);
+        let advertisements: Vec<_> = replay(&path).unwrap().collect().await;
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(advertisements.len(), 2);
+        let adv = &advertisements[0];
+        assert_eq!(adv.received, UNIX_EPOCH + Duration::from_secs_f64(1700000000.5));
+        assert_eq!(adv.current_reading.unwrap().co2_ppm, Some(612));
+        assert!(advertisements[1].current_reading.is_none());
+
+        // and records them as they were
+        let recorded = serde_json::to_value(RecordedAdvertisement::new(adv)).unwrap();
+        assert_eq!(recorded, serde_json::from_str::<serde_json::Value>(&line(ADVERTISEMENT)).unwrap());
+    }
+
+    #[test]
+    fn rejects_invalid_recordings() {
+        let unknown_status = ADVERTISEMENT.replace("57 01 2c", "57 04 2c");
+        let invalid = [
+            ("status", line(&unknown_status), "unknown CO2 status 4"),
+            ("short", line("20 13 04"), "manufacturer data is not at least 7 bytes of hex"),
+            ("hex", line("20 13 zz 01 00 0c 0f"), "manufacturer data is not at least 7 bytes of hex"),
+            ("json", "{\"timestamp\": 1".to_owned(), "EOF while parsing"),
+        ];
+        for (name, invalid, reason) in invalid {
+            let path = recording(name, &[line(ADVERTISEMENT), invalid]);
+            let error = replay(&path).err().unwrap();
+            std::fs::remove_file(&path).unwrap();
+
+            let error = error.downcast::<CliError>().unwrap();
+            assert_eq!(error.kind, ErrorKind::Parse, "{}", name);
+            // with the line it's on
+            assert!(error.message.contains(":2: ") && error.message.contains(reason), "{}: {}", name, error.message);
+        }
+    }
+}
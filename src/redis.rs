@@ -0,0 +1,184 @@
+//! Publishes readings to a Redis channel, for --redis.
+//!
+//! Each new reading is published as the JSON output's object with `PUBLISH`. With --redis-key, it's also stored with
+//! `SET` under `<key>:<device>`, where the device is its alias (or peripheral ID), expiring after --redis-ttl so a
+//! device that has gone quiet doesn't leave a reading that looks current.
+//!
+//! Commands are sent in the RESP protocol over a connection kept open between readings, and reconnected when it
+//! fails. TLS (`rediss://`) isn't supported.
+//!
+//! Protocol reference: https://redis.io/docs/latest/develop/reference/protocol-spec/
+
+use std::io;
+use std::time::Duration;
+
+use aranet::DiscoveredAranet;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufStream};
+use tokio::net::TcpStream;
+
+use crate::seen::SeenMeasurements;
+
+/// Default port of a Redis server
+pub const DEFAULT_PORT: u16 = 6379;
+
+/// How long to wait for the server to reply to each command
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where to connect to, from a `redis://[[user]:password@]host[:port][/db]` URL
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RedisUrl {
+    /// `host:port`
+    server: String,
+    user: Option<String>,
+    password: Option<String>,
+    db: u32,
+}
+
+/// Parses a --redis URL
+pub fn parse_url(s: &str) -> Result<RedisUrl, String> {
+    let rest = match s.split_once("://") {
+        Some(("redis", rest)) => rest,
+        Some(("rediss", _)) => return Err("TLS connections to Redis (rediss://) aren't supported".to_owned()),
+        Some((scheme, _)) => return Err(format!("unsupported scheme {:?}, expected redis://", scheme)),
+        None => s,
+    };
+    let (authority, db) = match rest.split_once('/') {
+        Some((authority, "")) => (authority, 0),
+        Some((authority, db)) => (authority, db.parse().map_err(|_| format!("invalid database number {:?}", db))?),
+        None => (rest, 0),
+    };
+    let (credentials, host) = match authority.rsplit_once('@') {
+        Some((credentials, host)) => (Some(credentials), host),
+        None => (None, authority),
+    };
+    let (user, password) = match credentials.map(|c| c.split_once(':')) {
+        Some(Some((user, password))) => ((!user.is_empty()).then(|| user.to_owned()), Some(password.to_owned())),
+        // a lone credential is the password, as with the legacy AUTH command
+        Some(None) => (None, credentials.map(str::to_owned)),
+        None => (None, None),
+    };
+    if host.is_empty() {
+        return Err(format!("no host in Redis URL {:?}", s));
+    }
+    let has_port = match host.rsplit_once(':') {
+        Some((host, port)) => port.parse::<u16>().is_ok() && (!host.contains(':') || host.starts_with('[')),
+        None => false,
+    };
+    let server = if has_port { host.to_owned() } else { format!("{}:{}", host, DEFAULT_PORT) };
+    Ok(RedisUrl { server, user, password, db })
+}
+
+/// Encodes a command as a RESP array of bulk strings
+fn command(args: &[&str]) -> Vec<u8> {
+    let mut out = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        out.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        out.extend_from_slice(arg.as_bytes());
+        out.extend_from_slice(b"\r\n");
+    }
+    out
+}
+
+pub struct RedisPublisher {
+    url: RedisUrl,
+    channel: String,
+    /// The prefix of the keys the latest reading of each device is SET under
+    key: Option<String>,
+    ttl: Option<Duration>,
+    connection: Option<BufStream<TcpStream>>,
+    /// To only publish each measurement once
+    seen: SeenMeasurements,
+}
+
+impl RedisPublisher {
+    pub fn new(url: RedisUrl, channel: String, key: Option<String>, ttl: Option<Duration>) -> RedisPublisher {
+        RedisPublisher { url, channel, key, ttl, connection: None, seen: SeenMeasurements::new() }
+    }
+
+    /// Sends a command, returning the first line of its reply. Error replies are returned as errors.
+    async fn send(connection: &mut BufStream<TcpStream>, args: &[&str]) -> io::Result<String> {
+        connection.write_all(&command(args)).await?;
+        connection.flush().await?;
+
+        let read = async {
+            let mut line = String::new();
+            if connection.read_line(&mut line).await? == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed by Redis"));
+            }
+            let line = line.trim_end().to_owned();
+            // skip over the contents of a bulk string reply, along with its trailing CRLF
+            if let Some(len) = line.strip_prefix('$').and_then(|len| len.parse::<u64>().ok()) {
+                let mut contents = Vec::new();
+                (&mut *connection).take(len + 2).read_to_end(&mut contents).await?;
+            }
+            Ok(line)
+        };
+        let line = tokio::time::timeout(REPLY_TIMEOUT, read).await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "no reply from Redis"))??;
+        match line.strip_prefix('-') {
+            Some(error) => Err(io::Error::other(format!("{} replied {}", args[0], error))),
+            None => Ok(line),
+        }
+    }
+
+    /// Connects, authenticating and selecting the database if needed
+    async fn connect(&self) -> io::Result<BufStream<TcpStream>> {
+        let mut connection = BufStream::new(TcpStream::connect(&self.url.server).await?);
+        if let Some(password) = &self.url.password {
+            match &self.url.user {
+                Some(user) => Self::send(&mut connection, &["AUTH", user, password]).await?,
+                None => Self::send(&mut connection, &["AUTH", password]).await?,
+            };
+        }
+        if self.url.db != 0 {
+            Self::send(&mut connection, &["SELECT", &self.url.db.to_string()]).await?;
+        }
+        log::debug!("connected to redis {}", self.url.server);
+        Ok(connection)
+    }
+
+    /// Sends the commands for a reading over the open connection, connecting first if needed
+    async fn send_all(&mut self, commands: &[Vec<&str>]) -> io::Result<()> {
+        let connection = match &mut self.connection {
+            Some(connection) => connection,
+            None => self.connection.insert(self.connect().await?),
+        };
+        for args in commands {
+            Self::send(connection, args).await?;
+        }
+        Ok(())
+    }
+
+    /// Publishes the advertisement's reading, if it's a new measurement
+    pub async fn reading(&mut self, adv: &DiscoveredAranet) -> io::Result<()> {
+        if adv.current_reading.is_none() || ! self.seen.is_new(adv) {
+            return Ok(());
+        }
+        let json = serde_json::to_string(&crate::NamedAranet::new(adv)).expect("unable to serialize reading as JSON");
+        let key = self.key.as_ref().map(|prefix| format!("{}:{}", prefix, crate::alias::display(adv)));
+        let ttl = self.ttl.map(|ttl| ttl.as_secs().max(1).to_string());
+        let channel = self.channel.clone();
+
+        let mut commands = vec![vec!["PUBLISH", channel.as_str(), json.as_str()]];
+        match (&key, &ttl) {
+            (Some(key), Some(ttl)) => commands.push(vec!["SET", key, &json, "EX", ttl]),
+            (Some(key), None) => commands.push(vec!["SET", key, &json]),
+            (None, _) => {},
+        }
+
+        let reused = self.connection.is_some();
+        let mut result = self.send_all(&commands).await;
+        if let Err(e) = &result {
+            // the server may have closed an idle connection, or restarted
+            self.connection = None;
+            if reused {
+                log::debug!("reconnecting to redis {} after: {}", self.url.server, e);
+                result = self.send_all(&commands).await;
+                if result.is_err() {
+                    self.connection = None;
+                }
+            }
+        }
+        result
+    }
+}
@@ -0,0 +1,126 @@
+//! A registry of devices by serial number, persisted to disk, for --registry.
+//!
+//! The OS identifies devices by peripheral IDs, which aren't stable: macOS assigns its own to each device on each
+//! machine, and BlueZ's include the adapter a device was heard on. A device's serial number never changes, so each
+//! new peripheral ID is connected to once to read it, and recorded under it with the device's alias. JSON output then
+//! includes the serial number, so a device can be followed whichever ID it's reported under.
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use aranet::DiscoveredAranet;
+use btleplug::api::Peripheral;
+use btleplug::platform::PeripheralId;
+
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+struct RegistryEntry {
+    /// Every peripheral ID the device has been heard with
+    peripheral_ids: BTreeSet<String>,
+    /// The device's alias when it was last registered, if it had one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    alias: Option<String>,
+    /// When the device was first registered, in seconds since the Unix epoch
+    registered: u64,
+}
+
+/// Entries by serial number
+type Entries = BTreeMap<String, RegistryEntry>;
+
+struct Registry {
+    path: PathBuf,
+    entries: Mutex<Entries>,
+    /// Peripheral IDs whose serial number couldn't be read, which aren't tried again until restarted
+    failed: Mutex<HashSet<String>>,
+}
+
+static REGISTRY: OnceLock<Registry> = OnceLock::new();
+
+/// Loads the registry, to be kept up to date for the rest of the process. Only the first call has any effect.
+pub fn init(path: PathBuf) -> io::Result<()> {
+    let entries = match fs::read(&path) {
+        Ok(data) => serde_json::from_slice(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Entries::new(),
+        Err(e) => return Err(e),
+    };
+    let _ = REGISTRY.set(Registry { path, entries: Mutex::new(entries), failed: Mutex::new(HashSet::new()) });
+    Ok(())
+}
+
+/// The serial number of the device that sent this advertisement, if it has been registered
+pub fn serial(adv: &DiscoveredAranet) -> Option<String> {
+    serial_of(&adv.peripheral_id)
+}
+
+/// The serial number of the device with this peripheral ID, if it has been registered
+pub fn serial_of(id: &PeripheralId) -> Option<String> {
+    let id = id.to_string();
+    REGISTRY.get()?.entries.lock().unwrap().iter()
+        .find(|(_, entry)| entry.peripheral_ids.contains(&id))
+        .map(|(serial, _)| serial.clone())
+}
+
+/// Connects to the device to read its serial number, disconnecting afterwards
+async fn read_serial(adv: &DiscoveredAranet) -> Result<String, Box<dyn Error>> {
+    let _lock = crate::lock::acquire().await?;
+    let retry = crate::retry::policy();
+    let aranet = retry.run("connect", || adv.upgrade()).await?;
+    let serial = retry.run("read the serial number", || aranet.serial_number()).await;
+    if let Err(e) = aranet.as_ref().disconnect().await {
+        log::debug!("unable to disconnect from {}: {}", adv.peripheral_id, e);
+    }
+    Ok(serial?.trim_matches(|c: char| c.is_whitespace() || c == '\0').to_owned())
+}
+
+/// Registers the device that sent this advertisement, if its peripheral ID is new, by connecting to it to read its
+/// serial number. Failures are logged, rather than stopping its readings from being used.
+pub async fn register(adv: &DiscoveredAranet) {
+    let Some(registry) = REGISTRY.get() else { return };
+    let id = adv.peripheral_id.to_string();
+    if serial(adv).is_some() || registry.failed.lock().unwrap().contains(&id) {
+        return;
+    }
+
+    log::debug!("connecting to {} to read its serial number for --registry", id);
+    let read = match read_serial(adv).await {
+        Ok(serial) if serial.is_empty() => Err("the device reported an empty serial number".to_owned()),
+        Ok(serial) => Ok(serial),
+        Err(e) => Err(e.to_string()),
+    };
+    let serial = match read {
+        Ok(serial) => serial,
+        Err(e) => {
+            log::warn!("unable to read the serial number of {} for --registry: {}", crate::alias::display(adv), e);
+            registry.failed.lock().unwrap().insert(id);
+            return;
+        },
+    };
+
+    let mut entries = registry.entries.lock().unwrap();
+    let entry = entries.entry(serial.clone()).or_insert_with(|| RegistryEntry {
+        registered: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+        ..RegistryEntry::default()
+    });
+    if ! entry.peripheral_ids.is_empty() {
+        log::info!("{} is serial number {}, previously seen as {:?}", id, serial, entry.peripheral_ids);
+    }
+    entry.peripheral_ids.insert(id);
+    if let Some(alias) = crate::alias::name(adv) {
+        entry.alias = Some(alias.to_owned());
+    }
+    if let Err(e) = save(&registry.path, &entries) {
+        log::warn!("unable to write --registry {}: {}", registry.path.display(), e);
+    }
+}
+
+fn save(path: &Path, entries: &Entries) -> io::Result<()> {
+    // written to a temporary file first, so concurrent runs never see a partial registry
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, serde_json::to_vec_pretty(entries).expect("unable to serialize device registry"))?;
+    fs::rename(&tmp, path)
+}
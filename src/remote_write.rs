@@ -0,0 +1,167 @@
+//! Pushes readings with the Prometheus remote write protocol, for --remote-write.
+//!
+//! This lets a collector behind NAT feed Prometheus, Grafana Mimir, or Grafana Cloud, which can't scrape it. Each new
+//! reading is sent as one sample of each series below, labelled with `device` (its alias, or peripheral ID) and the
+//! device's tags, timestamped with when it was measured.
+//!
+//! | series                        | unit            |
+//! |-------------------------------|-----------------|
+//! | `aranet_co2_ppm`              | ppm             |
+//! | `aranet_temperature_celsius`  | °C              |
+//! | `aranet_humidity_percent`     | %               |
+//! | `aranet_pressure_hpa`         | hPa             |
+//! | `aranet_battery_percent`      | %               |
+//! | `aranet_co2_status`           | 1-3 (green-red) |
+//!
+//! The protobuf `WriteRequest` is encoded by hand, as it's only a few fields, and compressed as a snappy block made
+//! of literals only. That's valid snappy which any decoder accepts, at the cost of not actually being smaller.
+//!
+//! Protocol reference: https://prometheus.io/docs/specs/prw/remote_write_spec/
+
+use std::time::{Duration, UNIX_EPOCH};
+
+use aranet::DiscoveredAranet;
+
+use crate::metric::Metric;
+use crate::seen::SeenMeasurements;
+
+/// Delay before the first retry, doubling for each one after
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The pushed series, as (metric, name)
+const SERIES: [(Metric, &str); 6] = [
+    (Metric::Co2, "aranet_co2_ppm"),
+    (Metric::TemperatureC, "aranet_temperature_celsius"),
+    (Metric::Humidity, "aranet_humidity_percent"),
+    (Metric::Pressure, "aranet_pressure_hpa"),
+    (Metric::Battery, "aranet_battery_percent"),
+    (Metric::Status, "aranet_co2_status"),
+];
+
+/// Appends a protobuf varint
+fn varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Appends a length delimited protobuf field, such as a string or an embedded message
+fn bytes_field(out: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    varint(out, field << 3 | 2);
+    varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+/// Encodes a `TimeSeries` with a single sample. Labels must be sorted by name.
+fn time_series(labels: &[(&str, &str)], value: f64, timestamp_ms: i64) -> Vec<u8> {
+    let mut series = Vec::new();
+    for (name, value) in labels {
+        let mut label = Vec::new();
+        bytes_field(&mut label, 1, name.as_bytes());
+        bytes_field(&mut label, 2, value.as_bytes());
+        bytes_field(&mut series, 1, &label);
+    }
+    let mut sample = Vec::new();
+    // value is a double (fixed 64-bit), timestamp an int64 (varint)
+    varint(&mut sample, 1 << 3 | 1);
+    sample.extend_from_slice(&value.to_le_bytes());
+    varint(&mut sample, 2 << 3);
+    varint(&mut sample, timestamp_ms as u64);
+    bytes_field(&mut series, 2, &sample);
+    series
+}
+
+/// Compresses data as a snappy block of literals, each up to 64 KiB
+fn snappy(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / 65536 * 3 + 13);
+    varint(&mut out, data.len() as u64);
+    for literal in data.chunks(65536) {
+        // a literal with its length minus one in the next 2 bytes
+        let len = literal.len() as u16 - 1;
+        out.push(61 << 2);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(literal);
+    }
+    out
+}
+
+pub struct RemoteWriter {
+    client: reqwest::Client,
+    url: String,
+    headers: Vec<(String, String)>,
+    retries: u32,
+    /// To only push each measurement once
+    seen: SeenMeasurements,
+}
+
+impl RemoteWriter {
+    /// Pushes to the remote write `url`. Credentials in the URL are sent with basic authentication, as Grafana Cloud
+    /// expects.
+    pub fn new(url: String, headers: Vec<(String, String)>, retries: u32) -> RemoteWriter {
+        let client = reqwest::Client::builder()
+            .user_agent(concat!("aranet/", env!("CARGO_PKG_VERSION")))
+            .timeout(Duration::from_secs(10))
+            .build()
+            .expect("unable to initialize HTTP client");
+        RemoteWriter { client, url, headers, retries, seen: SeenMeasurements::new() }
+    }
+
+    /// The `WriteRequest` for a reading
+    fn write_request(adv: &DiscoveredAranet) -> Vec<u8> {
+        let Some(reading) = adv.current_reading else { return Vec::new() };
+        let measured = adv.received.checked_sub(Duration::from_secs(reading.age as u64)).unwrap_or(adv.received);
+        let timestamp_ms = measured.duration_since(UNIX_EPOCH).map(|d| d.as_millis() as i64).unwrap_or(0);
+        let device = crate::alias::display(adv);
+        let tags = crate::tags::of(adv);
+
+        let mut request = Vec::new();
+        for (metric, name) in SERIES {
+            let Some(value) = metric.value(&reading) else { continue };
+            let mut labels = vec![("__name__", name), ("device", device.as_str())];
+            // the device label is this device's, and names starting with __ are reserved
+            labels.extend(tags.iter().copied().filter(|(key, _)| *key != "device" && ! key.starts_with("__")));
+            labels.sort();
+            bytes_field(&mut request, 1, &time_series(&labels, value, timestamp_ms));
+        }
+        request
+    }
+
+    /// Pushes the advertisement's reading, if it's a new measurement
+    pub async fn reading(&mut self, adv: &DiscoveredAranet) -> Result<(), reqwest::Error> {
+        if adv.current_reading.is_none() || ! self.seen.is_new(adv) {
+            return Ok(());
+        }
+        self.post(snappy(&Self::write_request(adv))).await
+    }
+
+    /// POSTs the body, retrying failed requests with exponential backoff
+    async fn post(&self, body: Vec<u8>) -> Result<(), reqwest::Error> {
+        let mut backoff = INITIAL_BACKOFF;
+        let mut attempt = 0;
+        loop {
+            let mut request = self.client.post(&self.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/x-protobuf")
+                .header(reqwest::header::CONTENT_ENCODING, "snappy")
+                .header("X-Prometheus-Remote-Write-Version", "0.1.0")
+                .body(body.clone());
+            for (name, value) in &self.headers {
+                request = request.header(name, value);
+            }
+
+            log::debug!("pushing samples to {}", self.url);
+            match request.send().await.and_then(|r| r.error_for_status()) {
+                Ok(_) => return Ok(()),
+                // samples rejected as invalid (4xx, other than rate limiting) will be rejected again
+                Err(e) if attempt < self.retries && e.status().is_none_or(|s| s.is_server_error() || s == 429) => {
+                    log::debug!("remote write failed, retrying in {:?}: {}", backoff, e);
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                },
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
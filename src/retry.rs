@@ -0,0 +1,20 @@
+//! Retrying flaky Bluetooth operations, for --retries and --backoff.
+//!
+//! Some adapters (particularly on single board computers) fail to start a scan or drop connections now and then, so
+//! discovery, connecting, and reads are retried with [`aranet::RetryPolicy`] before giving up.
+
+use std::sync::OnceLock;
+
+use aranet::RetryPolicy;
+
+static POLICY: OnceLock<RetryPolicy> = OnceLock::new();
+
+/// Sets the retry policy for the rest of the process. Only the first call has any effect.
+pub fn init(policy: RetryPolicy) {
+    let _ = POLICY.set(policy);
+}
+
+/// The retry policy, which doesn't retry if --retries wasn't given
+pub fn policy() -> RetryPolicy {
+    POLICY.get().copied().unwrap_or_default()
+}
@@ -0,0 +1,38 @@
+//! JSON Schemas of the JSON output, for `aranet schema`.
+//!
+//! Generated from the same types that are serialized, so they can't drift from what's actually output.
+
+use std::error::Error;
+
+use schemars::generate::SchemaSettings;
+
+use crate::record::RecordedAdvertisement;
+use crate::NamedAranet;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct SchemaArgs {
+    /// The output to describe
+    #[arg(value_enum, default_value_t = SchemaOutput::Reading)]
+    output: SchemaOutput,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+enum SchemaOutput {
+    /// A single reading, as output by default (and per line with --repeat)
+    Reading,
+    /// The array of readings output with --all
+    All,
+    /// A line of a `record`ing, as read by --replay
+    Record,
+}
+
+pub fn run(args: SchemaArgs) -> Result<(), Box<dyn Error>> {
+    let generator = SchemaSettings::draft2020_12().for_serialize().into_generator();
+    let schema = match args.output {
+        SchemaOutput::Reading => generator.into_root_schema_for::<NamedAranet>(),
+        SchemaOutput::All => generator.into_root_schema_for::<Vec<NamedAranet>>(),
+        SchemaOutput::Record => generator.into_root_schema_for::<RecordedAdvertisement>(),
+    };
+    println!("{}", serde_json::to_string_pretty(&schema)?);
+    Ok(())
+}
@@ -0,0 +1,81 @@
+//! Tracks which measurements have already been handled, as devices keep advertising the same measurement until
+//! they take the next one.
+
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+use aranet::DiscoveredAranet;
+
+/// How far apart the measurement times of two advertisements may be while still being of the same measurement. Ages
+/// are whole seconds, and advertisements take a moment to arrive.
+const TOLERANCE: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Default)]
+pub struct SeenMeasurements {
+    /// When the last seen measurement of each device was taken
+    last_measured: HashMap<String, SystemTime>,
+}
+
+impl SeenMeasurements {
+    pub fn new() -> SeenMeasurements {
+        SeenMeasurements::default()
+    }
+
+    /// Whether the advertisement carries a measurement that hasn't been seen before. Advertisements without a
+    /// reading never do.
+    ///
+    /// Measurements are told apart by when they were taken, rather than by their age resetting, so a measurement
+    /// is still new when the advertisements of the ones before it were missed.
+    pub fn is_new(&mut self, adv: &DiscoveredAranet) -> bool {
+        let Some(r) = &adv.current_reading else { return false };
+        let measured = adv.received.checked_sub(Duration::from_secs(r.age.into())).unwrap_or(adv.received);
+        // measurements are taken at least an interval apart, which may be shorter than the tolerance when simulated
+        let tolerance = TOLERANCE.min(Duration::from_secs(r.interval.into()) / 2);
+        let id = adv.peripheral_id.to_string();
+        if let Some(prev) = self.last_measured.get(&id) {
+            // in either direction, as the clock may have been changed since
+            let apart = measured.duration_since(*prev).unwrap_or_else(|e| e.duration());
+            if apart <= tolerance {
+                return false;
+            }
+        }
+        self.last_measured.insert(id, measured);
+        true
+    }
+}
+
+#[cfg(all(test, feature = "serde_json"))]
+mod tests {
+    use super::*;
+
+    /// An advertisement received `at` seconds in, of a measurement taken every minute and now `age` seconds old
+    fn advertisement(at: u64, age: u16) -> DiscoveredAranet {
+        let mut data = vec![
+            0x20, 0x13, 0x04, 0x01, 0x00, 0x0c, 0x0f, 0x01,
+            0x64, 0x02, 0xc9, 0x01, 0x8b, 0x27, 0x29, 0x57, 0x01, 0x3c, 0x00,
+        ];
+        data.extend(age.to_le_bytes());
+        let mut adv = DiscoveredAranet::parse(None, crate::simulate::peripheral_id(0), &data).unwrap();
+        adv.received = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000 + at);
+        adv
+    }
+
+    #[test]
+    fn repeated_measurements() {
+        let mut seen = SeenMeasurements::new();
+        assert!(seen.is_new(&advertisement(100, 10)));
+        assert!(! seen.is_new(&advertisement(104, 14)));
+        // arriving late, so a second older than expected
+        assert!(! seen.is_new(&advertisement(109, 20)));
+        assert!(seen.is_new(&advertisement(152, 2)));
+    }
+
+    #[test]
+    fn missed_advertisements() {
+        let mut seen = SeenMeasurements::new();
+        assert!(seen.is_new(&advertisement(100, 50)));
+        // the next measurement's advertisements were all missed, but the one after is older than the last seen
+        assert!(seen.is_new(&advertisement(170, 60)));
+        assert!(! seen.is_new(&advertisement(175, 65)));
+    }
+}
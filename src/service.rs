@@ -0,0 +1,210 @@
+//! Generates service definitions that run `aranet daemon`, for `aranet service generate`, and installs it as a
+//! Windows service (see [`crate::winservice`]).
+//!
+//! The generated unit (or plist) runs this binary with the current configuration: the config file in use (as an
+//! absolute path, as services don't share the user's config directory), `ARANET_OPT_*` environment variables, and any
+//! options given after `--`.
+
+use std::error::Error;
+use std::ffi::OsString;
+use std::fmt::Write;
+use std::path::PathBuf;
+
+/// The launchd job label, and the plist's file name
+const LAUNCHD_LABEL: &str = "io.github.aranet";
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct ServiceArgs {
+    #[command(subcommand)]
+    command: ServiceCommand,
+}
+
+#[derive(clap::Subcommand, Debug, Clone)]
+enum ServiceCommand {
+    /// Print a systemd unit or launchd plist running `aranet daemon` with the current configuration: the config file,
+    /// `ARANET_OPT_*` environment variables, and options given after `--`
+    Generate {
+        /// Generate a systemd unit, to save as /etc/systemd/system/aranet.service
+        #[arg(long, conflicts_with = "launchd", required_unless_present = "launchd")]
+        systemd: bool,
+        /// Generate a launchd agent, to save as ~/Library/LaunchAgents/io.github.aranet.plist
+        #[arg(long)]
+        launchd: bool,
+        /// The user to run the systemd service as, rather than root
+        #[arg(long, value_name = "USER", conflicts_with = "launchd")]
+        run_as: Option<String>,
+        /// Options to run aranet with, such as sinks, after `--`
+        #[arg(last = true, value_name = "OPTIONS")]
+        options: Vec<OsString>,
+    },
+    /// Install a Windows service running `aranet daemon` with the current configuration, starting on boot: the config
+    /// file, `ARANET_OPT_*` environment variables, and options given after `--`. Requires an Administrator prompt
+    #[cfg(all(windows, feature = "windows_service"))]
+    Install {
+        /// Options to run aranet with, such as sinks, after `--`
+        #[arg(last = true, value_name = "OPTIONS")]
+        options: Vec<OsString>,
+    },
+    /// Stop and remove the Windows service. Requires an Administrator prompt
+    #[cfg(all(windows, feature = "windows_service"))]
+    Uninstall,
+    /// Run as the Windows service, as `aranet daemon`. Only the service control manager can start it
+    #[cfg(all(windows, feature = "windows_service"))]
+    #[command(hide = true)]
+    Run,
+}
+
+impl ServiceArgs {
+    /// Whether this runs as the Windows service, rather than managing a service
+    #[cfg(all(windows, feature = "windows_service"))]
+    pub fn runs_service(&self) -> bool {
+        matches!(self.command, ServiceCommand::Run)
+    }
+}
+
+/// What the service runs, as command line arguments and environment variables
+struct Invocation {
+    args: Vec<String>,
+    env: Vec<(String, String)>,
+}
+
+impl Invocation {
+    /// Runs this binary with the config file, options, and then the command, such as `daemon`
+    fn new(config: Option<PathBuf>, options: Vec<OsString>, command: &[&str]) -> Result<Invocation, Box<dyn Error>> {
+        let text = |s: OsString| s.into_string().map_err(|s| format!("{:?} isn't valid UTF-8", s));
+        let exe = std::env::current_exe().map_err(|e| format!("unable to find the aranet binary: {}", e))?;
+        let mut args = vec![text(exe.into_os_string())?];
+        if let Some(config) = config {
+            let config = config.canonicalize().map_err(|e| format!("unable to find {}: {}", config.display(), e))?;
+            args.extend(["--config".to_owned(), text(config.into_os_string())?]);
+        }
+        for option in options {
+            args.push(text(option)?);
+        }
+        args.extend(command.iter().map(|arg| arg.to_string()));
+
+        let env = std::env::vars()
+            .filter(|(name, _)| name.starts_with(crate::env::PREFIX))
+            .collect();
+        Ok(Invocation { args, env })
+    }
+}
+
+/// Quotes a word for a systemd unit file, with `%` specifiers and `$` variables escaped
+fn systemd_quote(s: &str) -> String {
+    let escaped = s.replace('%', "%%").replace('$', "$$");
+    let plain = ! escaped.is_empty() && ! escaped.contains(|c: char| c.is_whitespace() || "\"'\\;".contains(c));
+    match plain {
+        true => escaped,
+        false => format!("\"{}\"", escaped.replace('\\', "\\\\").replace('"', "\\\"")),
+    }
+}
+
+fn systemd(invocation: &Invocation, run_as: Option<&str>) -> String {
+    let exec: Vec<String> = invocation.args.iter().map(|arg| systemd_quote(arg)).collect();
+    let mut unit = String::new();
+    unit.push_str("# Generated by `aranet service generate --systemd`. Install with:\n");
+    unit.push_str("#   sudo cp aranet.service /etc/systemd/system/ && sudo systemctl enable --now aranet\n");
+    unit.push_str("[Unit]\n");
+    unit.push_str("Description=Aranet4 collector\n");
+    unit.push_str("Wants=bluetooth.target network-online.target\n");
+    unit.push_str("After=bluetooth.target network-online.target\n");
+    unit.push_str("\n[Service]\n");
+    unit.push_str("Type=notify\n");
+    let _ = writeln!(unit, "ExecStart={}", exec.join(" "));
+    for (name, value) in &invocation.env {
+        let _ = writeln!(unit, "Environment={}", systemd_quote(&format!("{}={}", name, value)));
+    }
+    if let Some(user) = run_as {
+        let _ = writeln!(unit, "User={}", user);
+    }
+    unit.push_str("Restart=on-failure\n");
+    // immediate restarts fail the same way while the Bluetooth stack recovers
+    unit.push_str("RestartSec=10s\n");
+    // fed while Bluetooth is working, so a wedged Bluetooth stack restarts the service
+    unit.push_str("WatchdogSec=5min\n");
+    // devices are read through BlueZ over D-Bus, so no capabilities are needed
+    unit.push_str("CapabilityBoundingSet=\n");
+    unit.push_str("NoNewPrivileges=yes\n");
+    unit.push_str("ProtectSystem=full\n");
+    unit.push_str("RestrictAddressFamilies=AF_UNIX AF_INET AF_INET6\n");
+    unit.push_str("\n[Install]\n");
+    unit.push_str("WantedBy=multi-user.target\n");
+    unit
+}
+
+/// Escapes text for a plist's XML
+fn plist_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+fn launchd(invocation: &Invocation) -> String {
+    let mut plist = String::new();
+    plist.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    plist.push_str("<!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" ");
+    plist.push_str("\"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n");
+    // an agent in the user's session, as macOS only grants Bluetooth access to apps a user has allowed. XML comments
+    // can't contain `--`, so the command's options are left out.
+    let _ = writeln!(plist, "<!-- Generated by `aranet service generate`. Install with:");
+    let _ = writeln!(plist, "  cp {0}.plist ~/Library/LaunchAgents/", LAUNCHD_LABEL);
+    let _ = writeln!(plist, "  launchctl load ~/Library/LaunchAgents/{0}.plist -->", LAUNCHD_LABEL);
+    plist.push_str("<plist version=\"1.0\">\n<dict>\n");
+    let _ = writeln!(plist, "    <key>Label</key>\n    <string>{}</string>", LAUNCHD_LABEL);
+    plist.push_str("    <key>ProgramArguments</key>\n    <array>\n");
+    for arg in &invocation.args {
+        let _ = writeln!(plist, "        <string>{}</string>", plist_escape(arg));
+    }
+    plist.push_str("    </array>\n");
+    if ! invocation.env.is_empty() {
+        plist.push_str("    <key>EnvironmentVariables</key>\n    <dict>\n");
+        for (name, value) in &invocation.env {
+            let _ = writeln!(plist, "        <key>{}</key>", plist_escape(name));
+            let _ = writeln!(plist, "        <string>{}</string>", plist_escape(value));
+        }
+        plist.push_str("    </dict>\n");
+    }
+    plist.push_str("    <key>RunAtLoad</key>\n    <true/>\n");
+    // restarted when it fails, but not once stopped cleanly
+    plist.push_str("    <key>KeepAlive</key>\n    <dict>\n");
+    plist.push_str("        <key>SuccessfulExit</key>\n        <false/>\n    </dict>\n");
+    plist.push_str("    <key>ThrottleInterval</key>\n    <integer>10</integer>\n");
+    plist.push_str("    <key>ProcessType</key>\n    <string>Background</string>\n");
+    if let Some(home) = std::env::var_os("HOME") {
+        let log = PathBuf::from(home).join("Library/Logs/aranet.log");
+        let log = plist_escape(&log.to_string_lossy());
+        let _ = writeln!(plist, "    <key>StandardOutPath</key>\n    <string>{}</string>", log);
+        let _ = writeln!(plist, "    <key>StandardErrorPath</key>\n    <string>{}</string>", log);
+    }
+    plist.push_str("</dict>\n</plist>\n");
+    plist
+}
+
+/// Prints the service definition, or installs or uninstalls the Windows service. `config` is the config file in use,
+/// if any.
+pub fn run(args: ServiceArgs, config: Option<PathBuf>) -> Result<(), Box<dyn Error>> {
+    match args.command {
+        ServiceCommand::Generate { systemd: _, launchd, run_as, options } => {
+            let invocation = Invocation::new(config, options, &["daemon"])?;
+            match launchd {
+                true => print!("{}", self::launchd(&invocation)),
+                false => print!("{}", systemd(&invocation, run_as.as_deref())),
+            }
+        },
+        #[cfg(all(windows, feature = "windows_service"))]
+        ServiceCommand::Install { options } => {
+            let invocation = Invocation::new(config, options, &["service", "run"])?;
+            // the executable is run by path, without the program name
+            crate::winservice::install(&invocation.args[1..], &invocation.env)?;
+            println!("Installed the aranet service. Start it with `sc start aranet`");
+        },
+        #[cfg(all(windows, feature = "windows_service"))]
+        ServiceCommand::Uninstall => {
+            crate::winservice::uninstall()?;
+            println!("Uninstalled the aranet service");
+        },
+        // handled by main, as it runs the daemon
+        #[cfg(all(windows, feature = "windows_service"))]
+        ServiceCommand::Run => unreachable!("`aranet service run` runs the daemon"),
+    }
+    Ok(())
+}
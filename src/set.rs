@@ -0,0 +1,118 @@
+//! Changes a device's settings, for `aranet set`.
+//!
+//! Each setting is written as a command over GATT, so the device has to be connectable: with its Smart Home
+//! integrations disabled, that means paired first (see `aranet pair`). Changes are confirmed before they're written,
+//! unless --yes is given, and --dry-run shows what would be changed without connecting.
+
+use std::error::Error;
+use std::io::{IsTerminal, Write};
+use std::time::Duration;
+
+use aranet::{BluetoothRange, DiscoveredAranet};
+use btleplug::api::Peripheral;
+use btleplug::platform::Manager;
+use futures::StreamExt;
+
+use crate::error::{CliError, ErrorKind};
+use crate::filter::DeviceFilter;
+
+/// How long to wait for the device to advertise, if no --timeout is given
+const DEFAULT_TIMEOUT: f64 = 30.0;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct SetArgs {
+    #[command(subcommand)]
+    setting: Setting,
+    /// Show what would be changed, without connecting to the device
+    #[arg(long, global = true)]
+    dry_run: bool,
+    /// Don't ask before changing the setting, such as in provisioning scripts
+    #[arg(long, global = true)]
+    yes: bool,
+}
+
+#[derive(clap::Subcommand, Debug, Clone)]
+enum Setting {
+    /// Set the range of a device's Bluetooth radio (select it with --device)
+    BtRange {
+        /// normal, or extended
+        range: BluetoothRange,
+    },
+}
+
+impl Setting {
+    /// What the setting changes, completing "Set ..."
+    fn describe(&self, device: &str) -> String {
+        match self {
+            Setting::BtRange { range } => format!("the Bluetooth range of {} to {}", device, range),
+        }
+    }
+}
+
+/// Asks whether to go ahead, on the terminal
+async fn confirm(question: &str) -> Result<bool, Box<dyn Error>> {
+    if ! std::io::stdin().is_terminal() {
+        return Err("changing a device's settings needs a terminal to confirm it, or --yes".into());
+    }
+    print!("{} [y/N] ", question);
+    std::io::stdout().flush()?;
+    let answer = tokio::task::spawn_blocking(|| {
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line).map(|_| line)
+    }).await??;
+    Ok(matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Connects to the device and writes the setting, disconnecting afterwards
+async fn write(adv: &DiscoveredAranet, setting: &Setting) -> Result<(), Box<dyn Error>> {
+    let _lock = crate::lock::acquire().await?;
+    let retry = crate::retry::policy();
+    let aranet = retry.run("connect", || adv.upgrade()).await.map_err(crate::bluetooth_error)?;
+    let result = match setting {
+        Setting::BtRange { range } => retry.run("set the bluetooth range", || aranet.set_bluetooth_range(*range)).await,
+    };
+    if let Err(e) = aranet.as_ref().disconnect().await {
+        log::debug!("unable to disconnect from {}: {}", adv.peripheral_id, e);
+    }
+    result.map_err(crate::bluetooth_error)
+}
+
+/// Changes the setting of the first device matching the filter, waiting at most `timeout` seconds for it to advertise
+pub async fn run(args: SetArgs, filter: DeviceFilter, timeout: Option<f64>) -> Result<(), Box<dyn Error>> {
+    let manager = Manager::new().await?;
+    let mut discovered = crate::lock::discover(&manager).await?;
+
+    let timeout = timeout.unwrap_or(DEFAULT_TIMEOUT);
+    let find = async {
+        while let Some(adv) = discovered.next().await {
+            if filter.matches(&adv) {
+                return Some(adv);
+            }
+        }
+        None
+    };
+    let adv = match tokio::time::timeout(Duration::from_secs_f64(timeout), find).await {
+        Ok(Some(adv)) => adv,
+        Ok(None) => {
+            let msg = "Unable to discover devices. No Bluetooth adapters present.";
+            return Err(CliError::new(ErrorKind::NoAdapter, msg).into());
+        },
+        Err(_) => {
+            let msg = format!("No advertisement received within {}s.", timeout);
+            return Err(CliError::new(ErrorKind::NoDevice, msg).into());
+        },
+    };
+    let change = args.setting.describe(&crate::alias::display(&adv));
+
+    if args.dry_run {
+        println!("Would set {} (dry run, nothing was changed)", change);
+        return Ok(());
+    }
+    if ! args.yes && ! confirm(&format!("Set {}?", change)).await? {
+        println!("Nothing was changed");
+        return Ok(());
+    }
+    write(&adv, &args.setting).await?;
+    println!("Set {}", change);
+    Ok(())
+}
@@ -0,0 +1,264 @@
+//! Simulated Aranet4 devices for --simulate, for demoing dashboards and load testing sinks without hardware.
+//!
+//! Each device is a room that fills up during the working day: CO2 rises with its occupants and is ventilated back
+//! towards outdoor levels, with occasional meetings packing the room, while temperature and humidity follow the
+//! heating and the people in it. Batteries slowly run down. Readings are encoded as advertisements, and parsed as
+//! real ones are, so they pass through the normal pipeline (including --raw output).
+
+use std::time::{Duration, Instant};
+
+use aranet::DiscoveredAranet;
+use btleplug::platform::PeripheralId;
+
+use crate::Advertisements;
+
+/// How often each device advertises, as real devices do every few seconds
+const ADVERTISE_EVERY: Duration = Duration::from_secs(4);
+/// How often each device measures, in simulated seconds
+const INTERVAL: u64 = 60;
+/// The longest step the room's conditions are advanced by at once, in simulated seconds
+const STEP: f64 = 60.0;
+/// The simulated time of day when the simulation starts, in seconds: 7am, before the rooms fill up
+const START: f64 = 7.0 * 3600.0;
+
+/// Outdoor CO2, in ppm
+const OUTDOOR_CO2: f64 = 420.0;
+/// CO2 exhaled by each occupant, in litres per hour
+const EXHALED_CO2: f64 = 18.0;
+/// Battery used per simulated day, in %
+const BATTERY_PER_DAY: f64 = 0.25;
+
+/// A small deterministic generator, so each device behaves the same on each run
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        // never zero, which xorshift can't leave
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    /// Uniform in [0, 1)
+    fn next(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn range(&mut self, low: f64, high: f64) -> f64 {
+        low + (high - low) * self.next()
+    }
+
+    /// Roughly normally distributed, with a standard deviation of 1
+    fn noise(&mut self) -> f64 {
+        (0..12).map(|_| self.next()).sum::<f64>() - 6.0
+    }
+}
+
+/// The room a simulated device is in
+struct Room {
+    rng: Rng,
+    /// In cubic metres
+    volume: f64,
+    /// Air changes per hour
+    ventilation: f64,
+    /// How many people the room holds in a meeting
+    capacity: f64,
+    /// Simulated seconds until the current meeting ends, if one is on
+    meeting: f64,
+    occupants: f64,
+    co2: f64,
+    temperature: f64,
+    humidity: f64,
+    pressure: f64,
+    /// Offset from sea level pressure, for the building's altitude
+    altitude: f64,
+    /// In %
+    battery: f64,
+}
+
+impl Room {
+    fn new(seed: u64) -> Room {
+        let mut rng = Rng::new(seed);
+        let altitude = rng.range(-25.0, 5.0);
+        Room {
+            volume: rng.range(30.0, 120.0),
+            ventilation: rng.range(0.5, 2.0),
+            capacity: rng.range(2.0, 10.0).round(),
+            meeting: 0.0,
+            occupants: 0.0,
+            co2: OUTDOOR_CO2 + rng.range(0.0, 60.0),
+            temperature: rng.range(18.5, 20.0),
+            humidity: rng.range(30.0, 45.0),
+            pressure: 1013.25 + altitude,
+            altitude,
+            battery: rng.range(80.0, 100.0),
+            rng,
+        }
+    }
+
+    /// Advances the room's conditions by `dt` simulated seconds, ending at `time` seconds since the first midnight
+    fn step(&mut self, time: f64, dt: f64) {
+        let hour = time % 86400.0 / 3600.0;
+        let working = (8.0..18.0).contains(&hour);
+
+        // about one meeting a day, lasting half an hour to an hour and a half
+        if self.meeting > 0.0 {
+            self.meeting -= dt;
+        } else if working && self.rng.next() < dt / (10.0 * 3600.0) {
+            self.meeting = self.rng.range(1800.0, 5400.0);
+        }
+        let expected = match (working, self.meeting > 0.0) {
+            (true, true) => self.capacity,
+            // the room empties out over lunch
+            (true, false) if (12.0..13.0).contains(&hour) => self.capacity * 0.15,
+            (true, false) => self.capacity * 0.4,
+            (false, _) => 0.0,
+        };
+        // people come and go a few at a time, rather than all at once
+        self.occupants += (expected - self.occupants) * (1.0 - (-dt / 600.0).exp()) + self.rng.noise() * 0.1;
+        self.occupants = self.occupants.clamp(0.0, self.capacity);
+
+        let hours = dt / 3600.0;
+        let ventilated = 1.0 - (-self.ventilation * hours).exp();
+        let exhaled = self.occupants * EXHALED_CO2 * hours / (self.volume * 1000.0) * 1e6;
+        self.co2 += (OUTDOOR_CO2 - self.co2) * ventilated + exhaled + self.rng.noise() * 2.0;
+        self.co2 = self.co2.max(OUTDOOR_CO2 - 20.0);
+
+        let settle = 1.0 - (-hours).exp();
+        let heating = match (6.0..20.0).contains(&hour) {
+            true => 21.5,
+            false => 18.5,
+        };
+        self.temperature += (heating + self.occupants * 0.15 - self.temperature) * settle + self.rng.noise() * 0.02;
+        self.humidity += (38.0 + self.occupants * 1.5 - self.humidity) * settle + self.rng.noise() * 0.2;
+        self.humidity = self.humidity.clamp(0.0, 100.0);
+        // weather systems drifting through
+        let drift = self.rng.noise() * 0.03 * (dt / 60.0).sqrt();
+        self.pressure += (1013.25 + self.altitude - self.pressure) * hours / 48.0 + drift;
+
+        self.battery = (self.battery - BATTERY_PER_DAY * dt / 86400.0).max(0.0);
+    }
+
+    /// The reading as the device encodes it, with the interval and age in real seconds
+    fn encode(&self, interval: u16, age: u16) -> [u8; 13] {
+        let co2 = self.co2.round() as u16;
+        let status = match co2 {
+            ..=999 => 1,
+            1000..=1399 => 2,
+            _ => 3,
+        };
+        let mut data = [0; 13];
+        data[0..2].copy_from_slice(&co2.to_le_bytes());
+        data[2..4].copy_from_slice(&((self.temperature / 0.05).round() as u16).to_le_bytes());
+        data[4..6].copy_from_slice(&((self.pressure * 10.0).round() as u16).to_le_bytes());
+        data[6] = self.humidity.round() as u8;
+        data[7] = self.battery.ceil() as u8;
+        data[8] = status;
+        data[9..11].copy_from_slice(&interval.to_le_bytes());
+        data[11..13].copy_from_slice(&age.to_le_bytes());
+        data
+    }
+}
+
+/// A simulated device's ID, as the platform would identify it
+pub fn peripheral_id(n: u32) -> PeripheralId {
+    let [a, b, c, d] = n.to_be_bytes();
+    // a locally administered address, which no real device has
+    let addr = format!("02:00:{:02X}:{:02X}:{:02X}:{:02X}", a, b, c, d);
+    #[cfg(target_os = "linux")]
+    let id = serde_json::json!({ "object_path": format!("/org/bluez/hci0/dev_{}", addr.replace(':', "_")) });
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    let id = serde_json::json!(format!("00000000-0000-0000-0000-{}", addr.replace(':', "")));
+    #[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "ios")))]
+    let id = serde_json::json!(addr);
+    serde_json::from_value(id).expect("unable to construct a simulated peripheral ID")
+}
+
+struct Device {
+    id: PeripheralId,
+    room: Room,
+    /// When the latest reading was measured
+    measured: Instant,
+    /// When the latest reading was measured, in simulated seconds since the first midnight
+    time: f64,
+}
+
+struct Simulation {
+    devices: Vec<Device>,
+    /// The device to advertise next
+    next: usize,
+    ticks: tokio::time::Interval,
+    /// The measurement interval, in real seconds
+    interval: Duration,
+    speed: u32,
+}
+
+impl Simulation {
+    /// Measures any readings the device has taken since it last advertised, and returns its advertisement
+    fn advertise(&mut self, idx: usize) -> DiscoveredAranet {
+        let now = Instant::now();
+        let interval = self.interval;
+        let simulated = interval.as_secs_f64() * self.speed as f64;
+        let device = &mut self.devices[idx];
+        while now >= device.measured + interval {
+            device.measured += interval;
+            let mut remaining = simulated;
+            while remaining > 0.0 {
+                let dt = remaining.min(STEP);
+                remaining -= dt;
+                device.time += dt;
+                device.room.step(device.time, dt);
+            }
+        }
+
+        let age = now.duration_since(device.measured).as_secs().min(u16::MAX.into()) as u16;
+        let interval = interval.as_secs().min(u16::MAX.into()) as u16;
+        // smart home integrations enabled, on firmware 1.4.19, with the measurement after the 8th byte
+        let mut data = vec![0x20, 19, 4, 1, 0x00, 0x0c, 0x0f, 0x01];
+        data.extend(device.room.encode(interval, age));
+        DiscoveredAranet::parse(None, device.id.clone(), &data).expect("simulated advertisements are always valid")
+    }
+}
+
+/// Simulates this many devices, with each simulated day passing `speed` times faster, returning their advertisements
+/// as a stream in place of [`aranet::discover_aranet4`]'s.
+///
+/// Devices measure about every minute of simulated time, reporting that in real seconds (at least 1) as their interval.
+pub fn simulate(devices: u32, speed: u32) -> Advertisements {
+    let interval = Duration::from_secs((INTERVAL / u64::from(speed)).max(1));
+    let start = Instant::now();
+    let devices: Vec<Device> = (0..devices)
+        .map(|n| {
+            let mut room = Room::new(n.into());
+            // settle the room's conditions before its first reading
+            for step in 0..60 {
+                room.step(START - 3600.0 + step as f64 * STEP, STEP);
+            }
+            // as if the devices had been turned on at different times
+            let offset = interval.mul_f64(room.rng.next());
+            Device {
+                id: peripheral_id(n),
+                room,
+                measured: start.checked_sub(offset).unwrap_or(start),
+                time: START,
+            }
+        })
+        .collect();
+    log::info!("simulating {} devices, measuring every {}s", devices.len(), interval.as_secs());
+
+    // the devices' advertisements are spread out, rather than all sent at once. Sped up devices advertise each of
+    // their measurements.
+    let ticks = tokio::time::interval(ADVERTISE_EVERY.min(interval) / devices.len().max(1) as u32);
+    let simulation = Simulation { devices, next: 0, ticks, interval, speed };
+    Box::pin(futures::stream::unfold(simulation, |mut simulation| async move {
+        if simulation.devices.is_empty() {
+            return None;
+        }
+        simulation.ticks.tick().await;
+        let idx = simulation.next;
+        simulation.next = (idx + 1) % simulation.devices.len();
+        let adv = simulation.advertise(idx);
+        Some((adv, simulation))
+    }))
+}
@@ -0,0 +1,693 @@
+//! A minimal SNMP AgentX subagent (RFC 2741), exposing the latest reading of each discovered Aranet4.
+//!
+//! Registers a subtree with the master agent (eg: net-snmp's `snmpd` with `master agentx` set) and answers
+//! read requests for it. Values are integers, as SNMP has no floating point type.
+//!
+//! Table layout, under `<base>.1.1.<column>.<device index>`:
+//!
+//! | column | name            | type        | unit           |
+//! |--------|-----------------|-------------|----------------|
+//! | 1      | aranetIndex     | Integer32   |                |
+//! | 2      | aranetDevice    | OCTET STRING| peripheral id  |
+//! | 3      | aranetCO2       | Gauge32     | ppm            |
+//! | 4      | aranetTemp      | Integer32   | 0.1 °C         |
+//! | 5      | aranetHumidity  | Gauge32     | %              |
+//! | 6      | aranetPressure  | Gauge32     | 0.1 hPa        |
+//! | 7      | aranetBattery   | Gauge32     | %              |
+//! | 8      | aranetStatus    | Integer32   | 1-3 (green-red)|
+//! | 9      | aranetAge       | Gauge32     | seconds        |
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use aranet::CurrentReadingDetailed;
+use btleplug::platform::Manager;
+use futures::StreamExt;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Default base OID. A placeholder under the IANA 'experimental' arc, which isn't assigned to this project (it has no
+/// registered enterprise number), so may clash with other subagents. Deployments should pass --base-oid with an OID
+/// under their own organisation's arc.
+const DEFAULT_BASE_OID: &str = "1.3.6.1.3.7020";
+#[cfg(unix)]
+const DEFAULT_AGENTX_SOCKET: &str = "/var/agentx/master";
+#[cfg(not(unix))]
+const DEFAULT_AGENTX_SOCKET: &str = "tcp:localhost:705";
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct SnmpArgs {
+    /// Address of the master agent's AgentX socket. Either a unix socket path, or `tcp:host:port`.
+    #[arg(long, default_value = DEFAULT_AGENTX_SOCKET)]
+    agentx: String,
+    /// The OID to register the Aranet4 MIB subtree under. The default is an unassigned placeholder under the
+    /// experimental arc, so should be replaced with one under your organisation's enterprise number.
+    #[arg(long, default_value = DEFAULT_BASE_OID, value_parser = parse_oid)]
+    base_oid: Oid,
+}
+
+type Oid = Vec<u32>;
+
+fn parse_oid(s: &str) -> Result<Oid, String> {
+    let oid: Oid = s.trim_start_matches('.')
+        .split('.')
+        .map(|n| n.parse::<u32>().map_err(|_| format!("invalid OID component: {:?}", n)))
+        .collect::<Result<_, _>>()?;
+    if oid.len() < 2 || oid.len() > 100 {
+        return Err("OIDs must have between 2 and 100 components".to_owned());
+    }
+    Ok(oid)
+}
+
+// AgentX PDU types (RFC 2741 section 6.1)
+const PDU_OPEN: u8 = 1;
+const PDU_CLOSE: u8 = 2;
+const PDU_REGISTER: u8 = 3;
+const PDU_GET: u8 = 5;
+const PDU_GET_NEXT: u8 = 6;
+const PDU_GET_BULK: u8 = 7;
+const PDU_TEST_SET: u8 = 8;
+const PDU_COMMIT_SET: u8 = 9;
+const PDU_UNDO_SET: u8 = 10;
+const PDU_CLEANUP_SET: u8 = 11;
+const PDU_RESPONSE: u8 = 18;
+
+/// The longest PDU payload accepted. Requests for a few varbinds are far shorter, so anything longer is corrupt.
+const MAX_PAYLOAD: usize = 1024 * 1024;
+
+const FLAG_NON_DEFAULT_CONTEXT: u8 = 0x08;
+const FLAG_NETWORK_BYTE_ORDER: u8 = 0x10;
+
+// res.error values
+const ERR_NONE: u16 = 0;
+const ERR_NOT_WRITABLE: u16 = 17;
+const ERR_PARSE: u16 = 266;
+
+// varbind types
+const TYPE_INTEGER: u16 = 2;
+const TYPE_OCTET_STRING: u16 = 4;
+const TYPE_GAUGE32: u16 = 66;
+const TYPE_NO_SUCH_OBJECT: u16 = 128;
+const TYPE_NO_SUCH_INSTANCE: u16 = 129;
+const TYPE_END_OF_MIB_VIEW: u16 = 130;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    Integer(i32),
+    OctetString(Vec<u8>),
+    Gauge32(u32),
+    NoSuchObject,
+    NoSuchInstance,
+    EndOfMibView,
+}
+
+#[derive(Debug)]
+enum AgentXError {
+    Io(io::Error),
+    /// The master agent sent a PDU we could not decode
+    Parse,
+    /// The master agent refused a request, with its res.error code
+    Refused(&'static str, u16),
+    /// The master agent closed the session
+    Closed,
+}
+impl fmt::Display for AgentXError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AgentXError::Io(e) => write!(f, "AgentX connection error: {}", e),
+            AgentXError::Parse => write!(f, "unable to parse PDU from AgentX master agent"),
+            AgentXError::Refused(req, code) => write!(f, "AgentX master agent refused {} request (error {})", req, code),
+            AgentXError::Closed => write!(f, "AgentX master agent closed the session"),
+        }
+    }
+}
+impl Error for AgentXError {}
+impl From<io::Error> for AgentXError {
+    fn from(e: io::Error) -> Self {
+        AgentXError::Io(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Header {
+    pdu_type: u8,
+    flags: u8,
+    session_id: u32,
+    transaction_id: u32,
+    packet_id: u32,
+}
+
+/// Decodes AgentX payloads, honoring the byte order flag of the PDU header
+struct Reader<'a> {
+    buf: &'a [u8],
+    big_endian: bool,
+}
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.buf.len() < n { return None; }
+        let (head, tail) = self.buf.split_at(n);
+        self.buf = tail;
+        Some(head)
+    }
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+    fn u16(&mut self) -> Option<u16> {
+        let b: [u8; 2] = self.take(2)?.try_into().unwrap();
+        Some(if self.big_endian { u16::from_be_bytes(b) } else { u16::from_le_bytes(b) })
+    }
+    fn u32(&mut self) -> Option<u32> {
+        let b: [u8; 4] = self.take(4)?.try_into().unwrap();
+        Some(if self.big_endian { u32::from_be_bytes(b) } else { u32::from_le_bytes(b) })
+    }
+    /// Returns the OID and its `include` flag
+    fn oid(&mut self) -> Option<(Oid, bool)> {
+        let n_subid = self.u8()?;
+        let prefix = self.u8()?;
+        let include = self.u8()? != 0;
+        let _reserved = self.u8()?;
+        let mut oid = Vec::with_capacity(n_subid as usize + 5);
+        if prefix != 0 {
+            oid.extend_from_slice(&[1, 3, 6, 1, prefix as u32]);
+        }
+        for _ in 0..n_subid {
+            oid.push(self.u32()?);
+        }
+        Some((oid, include))
+    }
+    fn octets(&mut self) -> Option<&'a [u8]> {
+        let len = self.u32()? as usize;
+        let data = self.take(len)?;
+        self.take((4 - len % 4) % 4)?;
+        Some(data)
+    }
+    /// Parses a SearchRangeList, until the end of the payload
+    fn search_ranges(&mut self) -> Option<Vec<(Oid, bool, Oid)>> {
+        let mut ranges = Vec::new();
+        while !self.buf.is_empty() {
+            let (start, include) = self.oid()?;
+            let (end, _) = self.oid()?;
+            ranges.push((start, include, end));
+        }
+        Some(ranges)
+    }
+}
+
+/// Encodes AgentX payloads, always in network byte order
+#[derive(Default)]
+struct Writer {
+    buf: Vec<u8>,
+}
+impl Writer {
+    fn u8(&mut self, v: u8) { self.buf.push(v); }
+    fn u16(&mut self, v: u16) { self.buf.extend_from_slice(&v.to_be_bytes()); }
+    fn u32(&mut self, v: u32) { self.buf.extend_from_slice(&v.to_be_bytes()); }
+    fn oid(&mut self, oid: &[u32], include: bool) {
+        let (prefix, rest) = match oid {
+            [1, 3, 6, 1, p, rest @ ..] if *p > 0 && *p < 256 => (*p as u8, rest),
+            _ => (0, oid),
+        };
+        self.u8(rest.len() as u8);
+        self.u8(prefix);
+        self.u8(include as u8);
+        self.u8(0);
+        for &sub in rest {
+            self.u32(sub);
+        }
+    }
+    fn octets(&mut self, data: &[u8]) {
+        self.u32(data.len() as u32);
+        self.buf.extend_from_slice(data);
+        self.buf.resize(self.buf.len() + (4 - data.len() % 4) % 4, 0);
+    }
+    fn varbind(&mut self, name: &[u32], value: &Value) {
+        let ty = match value {
+            Value::Integer(_) => TYPE_INTEGER,
+            Value::OctetString(_) => TYPE_OCTET_STRING,
+            Value::Gauge32(_) => TYPE_GAUGE32,
+            Value::NoSuchObject => TYPE_NO_SUCH_OBJECT,
+            Value::NoSuchInstance => TYPE_NO_SUCH_INSTANCE,
+            Value::EndOfMibView => TYPE_END_OF_MIB_VIEW,
+        };
+        self.u16(ty);
+        self.u16(0);
+        self.oid(name, false);
+        match value {
+            Value::Integer(i) => self.u32(*i as u32),
+            Value::OctetString(s) => self.octets(s),
+            Value::Gauge32(g) => self.u32(*g),
+            _ => {},
+        }
+    }
+    fn into_pdu(self, pdu_type: u8, session_id: u32, transaction_id: u32, packet_id: u32) -> Vec<u8> {
+        let mut pdu = Vec::with_capacity(20 + self.buf.len());
+        pdu.extend_from_slice(&[1, pdu_type, FLAG_NETWORK_BYTE_ORDER, 0]);
+        pdu.extend_from_slice(&session_id.to_be_bytes());
+        pdu.extend_from_slice(&transaction_id.to_be_bytes());
+        pdu.extend_from_slice(&packet_id.to_be_bytes());
+        pdu.extend_from_slice(&(self.buf.len() as u32).to_be_bytes());
+        pdu.extend_from_slice(&self.buf);
+        pdu
+    }
+}
+
+async fn read_pdu<S: AsyncRead + Unpin>(stream: &mut S) -> Result<(Header, Vec<u8>), AgentXError> {
+    let mut raw = [0u8; 20];
+    stream.read_exact(&mut raw).await?;
+    let flags = raw[2];
+    let mut r = Reader { buf: &raw[4..], big_endian: flags & FLAG_NETWORK_BYTE_ORDER != 0 };
+    let header = Header {
+        pdu_type: raw[1],
+        flags,
+        session_id: r.u32().unwrap(),
+        transaction_id: r.u32().unwrap(),
+        packet_id: r.u32().unwrap(),
+    };
+    let len = r.u32().unwrap() as usize;
+    if len > MAX_PAYLOAD {
+        return Err(AgentXError::Parse);
+    }
+    let mut payload = vec![0u8; len];
+    stream.read_exact(&mut payload).await?;
+    Ok((header, payload))
+}
+
+/// Latest readings from each device, in the order they were first seen
+type Devices = Arc<Mutex<Vec<(String, CurrentReadingDetailed)>>>;
+
+/// Builds the sorted list of every instance within the subtree
+fn instances(base: &[u32], devices: &[(String, CurrentReadingDetailed)]) -> Vec<(Oid, Value)> {
+    let mut out = Vec::new();
+    for column in 1..=9u32 {
+        for (idx, (id, r)) in devices.iter().enumerate() {
+            let value = match column {
+                1 => Some(Value::Integer(idx as i32 + 1)),
+                2 => Some(Value::OctetString(id.as_bytes().to_vec())),
+                3 => r.co2_ppm.map(|c| Value::Gauge32(c as u32)),
+                4 => r.temperature_c.map(|t| Value::Integer((t * 10.0).round() as i32)),
+                5 => Some(Value::Gauge32((r.humidity * 100.0).round() as u32)),
+                6 => r.pressure_hpa.map(|p| Value::Gauge32((p * 10.0).round() as u32)),
+                7 => Some(Value::Gauge32((r.battery * 100.0).round() as u32)),
+                8 => Some(Value::Integer(r.status as i32)),
+                9 => Some(Value::Gauge32(r.age as u32)),
+                _ => unreachable!(),
+            };
+            if let Some(value) = value {
+                let mut oid = base.to_vec();
+                oid.extend_from_slice(&[1, 1, column, idx as u32 + 1]);
+                out.push((oid, value));
+            }
+        }
+    }
+    out
+}
+
+fn get(base: &[u32], instances: &[(Oid, Value)], oid: &[u32]) -> Value {
+    match instances.iter().find(|(o, _)| o == oid) {
+        Some((_, v)) => v.clone(),
+        None if oid.starts_with(base) => Value::NoSuchInstance,
+        None => Value::NoSuchObject,
+    }
+}
+
+fn get_next(instances: &[(Oid, Value)], start: &[u32], include: bool, end: &[u32]) -> (Oid, Value) {
+    instances.iter()
+        .find(|(o, _)| {
+            let after_start = if include { o.as_slice() >= start } else { o.as_slice() > start };
+            after_start && (end.is_empty() || o.as_slice() < end)
+        })
+        .cloned()
+        .unwrap_or_else(|| (start.to_vec(), Value::EndOfMibView))
+}
+
+/// Answers a Get/GetNext/GetBulk request, returning the response payload
+fn respond(header: &Header, payload: &[u8], base: &[u32], devices: &Devices, uptime: u32) -> Writer {
+    let mut r = Reader { buf: payload, big_endian: header.flags & FLAG_NETWORK_BYTE_ORDER != 0 };
+    let mut w = Writer::default();
+    w.u32(uptime);
+
+    let parsed = (|| {
+        if header.flags & FLAG_NON_DEFAULT_CONTEXT != 0 {
+            r.octets()?;
+        }
+        let bulk = if header.pdu_type == PDU_GET_BULK { Some((r.u16()?, r.u16()?)) } else { None };
+        Some((bulk, r.search_ranges()?))
+    })();
+    let Some((bulk, ranges)) = parsed else {
+        w.u16(ERR_PARSE);
+        w.u16(0);
+        return w;
+    };
+    w.u16(ERR_NONE);
+    w.u16(0);
+
+    let instances = instances(base, &devices.lock().unwrap());
+    match (header.pdu_type, bulk) {
+        (PDU_GET, _) => {
+            for (oid, _, _) in ranges {
+                w.varbind(&oid, &get(base, &instances, &oid));
+            }
+        },
+        (PDU_GET_BULK, Some((non_repeaters, max_repetitions))) => {
+            let (single, repeating) = ranges.split_at((non_repeaters as usize).min(ranges.len()));
+            for (start, include, end) in single {
+                let (oid, value) = get_next(&instances, start, *include, end);
+                w.varbind(&oid, &value);
+            }
+            let mut cursors: Vec<(Oid, bool, Oid)> = repeating.to_vec();
+            for _ in 0..max_repetitions {
+                if cursors.is_empty() { break; }
+                for (start, include, end) in cursors.iter_mut() {
+                    let (oid, value) = get_next(&instances, start, *include, end);
+                    w.varbind(&oid, &value);
+                    *start = oid;
+                    *include = false;
+                }
+            }
+        },
+        _ => {
+            for (start, include, end) in ranges {
+                let (oid, value) = get_next(&instances, &start, include, &end);
+                w.varbind(&oid, &value);
+            }
+        },
+    }
+    w
+}
+
+/// Opens a session, registers the subtree, and answers requests until the connection fails
+async fn run_session<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S, base: &[u32], devices: &Devices, started: Instant) -> Result<(), AgentXError> {
+    let mut packet_id = 0u32;
+
+    // agentx-Open-PDU
+    let mut w = Writer::default();
+    w.u8(0); // use the master agent's default timeout
+    w.buf.extend_from_slice(&[0, 0, 0]);
+    w.oid(base, false);
+    w.octets(concat!("aranet ", env!("CARGO_PKG_VERSION")).as_bytes());
+    packet_id += 1;
+    stream.write_all(&w.into_pdu(PDU_OPEN, 0, 0, packet_id)).await?;
+    let (header, payload) = read_pdu(&mut stream).await?;
+    let (session_id, error) = response_error(&header, &payload)?;
+    if error != ERR_NONE {
+        return Err(AgentXError::Refused("open", error));
+    }
+    log::debug!("opened AgentX session {}", session_id);
+
+    // agentx-Register-PDU
+    let mut w = Writer::default();
+    w.buf.extend_from_slice(&[0, 127, 0, 0]); // timeout, priority, range_subid, reserved
+    w.oid(base, false);
+    packet_id += 1;
+    stream.write_all(&w.into_pdu(PDU_REGISTER, session_id, 0, packet_id)).await?;
+    let (header, payload) = read_pdu(&mut stream).await?;
+    let (_, error) = response_error(&header, &payload)?;
+    if error != ERR_NONE {
+        return Err(AgentXError::Refused("register", error));
+    }
+    log::info!("registered SNMP subtree {} with AgentX master agent", format_oid(base));
+
+    loop {
+        let (header, payload) = read_pdu(&mut stream).await?;
+        log::trace!("received AgentX PDU type {} ({} bytes)", header.pdu_type, payload.len());
+        let uptime = (started.elapsed().as_millis() / 10) as u32;
+        let response = match header.pdu_type {
+            PDU_GET | PDU_GET_NEXT | PDU_GET_BULK => Some(respond(&header, &payload, base, devices, uptime)),
+            PDU_TEST_SET | PDU_COMMIT_SET | PDU_UNDO_SET => {
+                let mut w = Writer::default();
+                w.u32(uptime);
+                w.u16(if header.pdu_type == PDU_TEST_SET { ERR_NOT_WRITABLE } else { ERR_NONE });
+                w.u16(0);
+                Some(w)
+            },
+            PDU_CLEANUP_SET | PDU_RESPONSE => None,
+            PDU_CLOSE => return Err(AgentXError::Closed),
+            other => {
+                log::debug!("ignoring unsupported AgentX PDU type {}", other);
+                None
+            },
+        };
+        if let Some(w) = response {
+            stream.write_all(&w.into_pdu(PDU_RESPONSE, header.session_id, header.transaction_id, header.packet_id)).await?;
+        }
+    }
+}
+
+/// Returns the session ID and res.error of an agentx-Response-PDU
+fn response_error(header: &Header, payload: &[u8]) -> Result<(u32, u16), AgentXError> {
+    if header.pdu_type != PDU_RESPONSE {
+        return Err(AgentXError::Parse);
+    }
+    let mut r = Reader { buf: payload, big_endian: header.flags & FLAG_NETWORK_BYTE_ORDER != 0 };
+    let _uptime = r.u32().ok_or(AgentXError::Parse)?;
+    let error = r.u16().ok_or(AgentXError::Parse)?;
+    Ok((header.session_id, error))
+}
+
+fn format_oid(oid: &[u32]) -> String {
+    oid.iter().map(|n| n.to_string()).collect::<Vec<_>>().join(".")
+}
+
+async fn connect_and_serve(addr: &str, base: &[u32], devices: &Devices, started: Instant) -> Result<(), AgentXError> {
+    if let Some(tcp) = addr.strip_prefix("tcp:") {
+        let stream = tokio::net::TcpStream::connect(tcp).await?;
+        return run_session(stream, base, devices, started).await;
+    }
+    #[cfg(unix)] {
+        let stream = tokio::net::UnixStream::connect(addr).await?;
+        run_session(stream, base, devices, started).await
+    }
+    #[cfg(not(unix))] {
+        Err(AgentXError::Io(io::Error::new(io::ErrorKind::Unsupported, "unix sockets are not supported on this platform, use tcp:host:port")))
+    }
+}
+
+/// Runs the subagent forever, updating the table from advertisements and reconnecting to the master agent as needed.
+pub async fn run(args: SnmpArgs, filter: crate::filter::DeviceFilter) -> Result<(), Box<dyn Error>> {
+    let manager = Manager::new().await?;
+    let mut discovered = crate::lock::discover(&manager).await?;
+    let devices: Devices = Default::default();
+
+    let listener = {
+        let devices = devices.clone();
+        async move {
+            while let Some(adv) = discovered.next().await {
+                if ! filter.matches(&adv) {
+                    continue;
+                }
+                let id = crate::alias::display(&adv);
+                let Some(reading) = adv.current_reading else { continue };
+                let mut devices = devices.lock().unwrap();
+                match devices.iter_mut().find(|(i, _)| *i == id) {
+                    Some((_, r)) => *r = reading,
+                    None => {
+                        log::info!("tracking new Aranet4 {} as SNMP row {}", id, devices.len() + 1);
+                        devices.push((id, reading));
+                    },
+                }
+            }
+        }
+    };
+
+    if args.base_oid == parse_oid(DEFAULT_BASE_OID).unwrap() {
+        log::warn!("registering under the placeholder OID {}, pass --base-oid with one assigned to you", DEFAULT_BASE_OID);
+    }
+    let started = Instant::now();
+    let agent = async {
+        loop {
+            if let Err(e) = connect_and_serve(&args.agentx, &args.base_oid, &devices, started).await {
+                log::warn!("{}, reconnecting in 5s", e);
+            }
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+    };
+
+    tokio::select! {
+        () = listener => Err("Unable to discover devices. No Bluetooth adapters present.".into()),
+        never = agent => never,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use aranet::DisplayStatus;
+
+    use super::*;
+
+    const BASE: &[u32] = &[1, 3, 6, 1, 4, 1, 32473, 1];
+
+    fn devices() -> Devices {
+        let reading = CurrentReadingDetailed {
+            co2_ppm: Some(612),
+            temperature_c: Some(22.85),
+            pressure_hpa: Some(1012.3),
+            humidity: 0.41,
+            battery: 0.87,
+            status: DisplayStatus::Green,
+            interval: 300,
+            age: 42,
+        };
+        Arc::new(Mutex::new(vec![("office".to_owned(), reading)]))
+    }
+
+    fn column(column: u32) -> Oid {
+        [BASE, &[1, 1, column, 1]].concat()
+    }
+
+    /// A SearchRangeList, of each OID without an end
+    fn ranges(oids: &[(&[u32], bool)]) -> Writer {
+        let mut w = Writer::default();
+        for (oid, include) in oids {
+            w.oid(oid, *include);
+            w.oid(&[], false);
+        }
+        w
+    }
+
+    /// Decodes a VarBind, as encoded by [`Writer::varbind`]
+    fn varbind(r: &mut Reader<'_>) -> Option<(Oid, Value)> {
+        let ty = r.u16()?;
+        let _reserved = r.u16()?;
+        let (name, _) = r.oid()?;
+        let value = match ty {
+            TYPE_INTEGER => Value::Integer(r.u32()? as i32),
+            TYPE_OCTET_STRING => Value::OctetString(r.octets()?.to_vec()),
+            TYPE_GAUGE32 => Value::Gauge32(r.u32()?),
+            TYPE_NO_SUCH_OBJECT => Value::NoSuchObject,
+            TYPE_NO_SUCH_INSTANCE => Value::NoSuchInstance,
+            TYPE_END_OF_MIB_VIEW => Value::EndOfMibView,
+            _ => return None,
+        };
+        Some((name, value))
+    }
+
+    /// Decodes a response payload's res.error and VarBindList
+    fn varbinds(payload: &[u8]) -> (u16, Vec<(Oid, Value)>) {
+        let mut r = Reader { buf: payload, big_endian: true };
+        let _uptime = r.u32().unwrap();
+        let error = r.u16().unwrap();
+        let _index = r.u16().unwrap();
+        let mut varbinds = Vec::new();
+        while ! r.buf.is_empty() {
+            varbinds.push(varbind(&mut r).unwrap());
+        }
+        (error, varbinds)
+    }
+
+    /// Answers a request with the given payload, returning the response's res.error and VarBindList
+    fn answer(pdu_type: u8, payload: &[u8]) -> (u16, Vec<(Oid, Value)>) {
+        let header = Header { pdu_type, flags: FLAG_NETWORK_BYTE_ORDER, session_id: 1, transaction_id: 1, packet_id: 1 };
+        varbinds(&respond(&header, payload, BASE, &devices(), 0).buf)
+    }
+
+    /// A response to a PDU, without an error
+    fn reply(session_id: u32, header: &Header) -> Vec<u8> {
+        let mut w = Writer::default();
+        w.u32(0);
+        w.u16(ERR_NONE);
+        w.u16(0);
+        w.into_pdu(PDU_RESPONSE, session_id, header.transaction_id, header.packet_id)
+    }
+
+    #[test]
+    fn oid_prefix_compression() {
+        for (oid, prefix) in [(BASE, 4), (&[1, 3, 6, 1, 3, 7020], 3), (&[1, 2, 840, 10008], 0)] {
+            let mut w = Writer::default();
+            w.oid(oid, true);
+            let n_subid = if prefix == 0 { oid.len() } else { oid.len() - 5 };
+            assert_eq!(w.buf[..4], [n_subid as u8, prefix, 1, 0], "{:?}", oid);
+            assert_eq!(w.buf.len(), 4 + 4 * n_subid);
+
+            let mut r = Reader { buf: &w.buf, big_endian: true };
+            assert_eq!(r.oid(), Some((oid.to_vec(), true)));
+            assert!(r.buf.is_empty());
+        }
+    }
+
+    #[test]
+    fn octet_string_padding() {
+        for len in 0..=9 {
+            let data: Vec<u8> = (1..=len).collect();
+            let mut w = Writer::default();
+            w.octets(&data);
+            assert_eq!(w.buf.len(), 4 + data.len().div_ceil(4) * 4);
+            assert!(w.buf[4 + data.len()..].iter().all(|&b| b == 0));
+
+            let mut r = Reader { buf: &w.buf, big_endian: true };
+            assert_eq!(r.octets(), Some(&data[..]));
+            assert!(r.buf.is_empty());
+        }
+    }
+
+    #[test]
+    fn get() {
+        let missing = [BASE, &[1, 1, 3, 2]].concat();
+        let request = ranges(&[(&column(3), false), (&column(2), false), (&missing, false), (&[1, 3, 6, 1, 2, 1], false)]);
+        assert_eq!(answer(PDU_GET, &request.buf), (ERR_NONE, vec![
+            (column(3), Value::Gauge32(612)),
+            (column(2), Value::OctetString(b"office".to_vec())),
+            (missing, Value::NoSuchInstance),
+            (vec![1, 3, 6, 1, 2, 1], Value::NoSuchObject),
+        ]));
+    }
+
+    #[test]
+    fn get_next() {
+        let request = ranges(&[(BASE, false), (&column(3), false), (&column(4), true), (&column(9), false)]);
+        assert_eq!(answer(PDU_GET_NEXT, &request.buf), (ERR_NONE, vec![
+            (column(1), Value::Integer(1)),
+            (column(4), Value::Integer(229)),
+            (column(4), Value::Integer(229)),
+            (column(9), Value::EndOfMibView),
+        ]));
+    }
+
+    #[tokio::test]
+    async fn session() {
+        let (agent, mut master) = tokio::io::duplex(4096);
+        let session = tokio::spawn(async move { run_session(agent, BASE, &devices(), Instant::now()).await });
+
+        let (header, payload) = read_pdu(&mut master).await.unwrap();
+        assert_eq!(header.pdu_type, PDU_OPEN);
+        let mut r = Reader { buf: &payload, big_endian: header.flags & FLAG_NETWORK_BYTE_ORDER != 0 };
+        assert_eq!(r.u32(), Some(0)); // timeout, and reserved
+        assert_eq!(r.oid(), Some((BASE.to_vec(), false)));
+        assert_eq!(r.octets(), Some(concat!("aranet ", env!("CARGO_PKG_VERSION")).as_bytes()));
+        master.write_all(&reply(42, &header)).await.unwrap();
+
+        let (header, payload) = read_pdu(&mut master).await.unwrap();
+        assert_eq!((header.pdu_type, header.session_id), (PDU_REGISTER, 42));
+        let mut r = Reader { buf: &payload, big_endian: header.flags & FLAG_NETWORK_BYTE_ORDER != 0 };
+        assert_eq!(r.take(4), Some(&[0, 127, 0, 0][..])); // timeout, priority, range_subid, reserved
+        assert_eq!(r.oid(), Some((BASE.to_vec(), false)));
+        master.write_all(&reply(42, &header)).await.unwrap();
+
+        master.write_all(&ranges(&[(&column(7), false)]).into_pdu(PDU_GET, 42, 9, 3)).await.unwrap();
+        let (header, payload) = read_pdu(&mut master).await.unwrap();
+        assert_eq!((header.pdu_type, header.session_id, header.transaction_id, header.packet_id), (PDU_RESPONSE, 42, 9, 3));
+        assert_eq!(varbinds(&payload), (ERR_NONE, vec![(column(7), Value::Gauge32(87))]));
+
+        master.write_all(&Writer::default().into_pdu(PDU_CLOSE, 42, 0, 4)).await.unwrap();
+        assert!(matches!(session.await.unwrap(), Err(AgentXError::Closed)));
+    }
+
+    #[tokio::test]
+    async fn truncated_pdus() {
+        let pdu = ranges(&[(&column(3), false)]).into_pdu(PDU_GET, 1, 1, 1);
+        for len in [0, 10, 20, pdu.len() - 1] {
+            let result = read_pdu(&mut &pdu[..len]).await;
+            assert!(matches!(result, Err(AgentXError::Io(ref e)) if e.kind() == io::ErrorKind::UnexpectedEof), "{}", len);
+        }
+        let mut oversized = pdu.clone();
+        oversized[16..20].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(matches!(read_pdu(&mut &oversized[..]).await, Err(AgentXError::Parse)));
+
+        // a complete PDU, with a truncated search range within it
+        let payload = &pdu[20..pdu.len() - 2];
+        assert_eq!(answer(PDU_GET, payload), (ERR_PARSE, Vec::new()));
+        let header = Header { pdu_type: PDU_RESPONSE, flags: FLAG_NETWORK_BYTE_ORDER, session_id: 1, transaction_id: 1, packet_id: 1 };
+        assert!(matches!(response_error(&header, &[0, 0, 0, 0, 0]), Err(AgentXError::Parse)));
+    }
+}
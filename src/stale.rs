@@ -0,0 +1,44 @@
+//! Flagging readings older than the device's measurement interval, as set by --stale-after, so an old value isn't
+//! mistaken for a current one. Readings older than --max-age aren't used at all.
+
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use aranet::DiscoveredAranet;
+
+static STALE_AFTER: OnceLock<f64> = OnceLock::new();
+static MAX_AGE: OnceLock<Duration> = OnceLock::new();
+
+/// Sets how many measurement intervals old a reading may be for the rest of the process. Only the first call has
+/// any effect.
+pub fn init(intervals: f64) {
+    let _ = STALE_AFTER.set(intervals);
+}
+
+/// Sets the age past which readings are skipped for the rest of the process. Only the first call has any effect.
+pub fn init_max_age(max_age: Duration) {
+    let _ = MAX_AGE.set(max_age);
+}
+
+/// How many measurement intervals old a reading may be before it's stale
+pub fn intervals() -> f64 {
+    STALE_AFTER.get().copied().unwrap_or(1.0)
+}
+
+/// Whether the advertisement's reading is stale. Advertisements without a reading never are.
+pub fn is_stale(adv: &DiscoveredAranet) -> bool {
+    adv.current_reading.is_some_and(|r| r.is_stale(intervals()))
+}
+
+/// A warning to show alongside a stale reading
+pub fn warning(adv: &DiscoveredAranet) -> Option<String> {
+    let r = adv.current_reading.filter(|_| is_stale(adv))?;
+    Some(format!("Warning: stale reading, measured {}s ago but the device measures every {}s", r.age, r.interval))
+}
+
+/// Whether the advertisement's reading is older than --max-age, so shouldn't be used. Advertisements without a reading
+/// never are.
+pub fn too_old(adv: &DiscoveredAranet) -> bool {
+    let Some(max_age) = MAX_AGE.get() else { return false };
+    adv.current_reading.is_some_and(|r| Duration::from_secs(r.age as u64) > *max_age)
+}
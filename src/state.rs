@@ -0,0 +1,270 @@
+//! The in-memory state of a long running collector: the devices heard and their latest readings, how each sink is
+//! doing, and counts of errors. `aranet daemon` dumps it on SIGUSR1, to see what a collector has been up to without
+//! restarting it with more logging.
+//!
+//! The same counts are written as Prometheus metrics by `aranet daemon --metrics-file`, so monitoring can alert on
+//! the collector itself (such as the radio going quiet) rather than only on the readings it sends.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{self, Write as _};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use aranet::{CurrentReadingDetailed, DiscoveredAranet};
+
+/// Shared with the task dumping it, so cheap to clone
+#[derive(Clone)]
+pub struct State(Arc<Mutex<Inner>>);
+
+/// How recently a device must have advertised to count as visible
+const VISIBLE: Duration = Duration::from_secs(120);
+
+struct Inner {
+    started: Instant,
+    advertisements: u64,
+    /// Advertisements with manufacturer data too short for the reading they started
+    malformed: u64,
+    last_advertisement: Option<Instant>,
+    /// When each device was last heard, by peripheral ID, including devices not matching --device
+    heard: HashMap<String, Instant>,
+    connects: u64,
+    /// Connections to a device that had been connected to before
+    reconnects: u64,
+    /// The peripheral IDs of the devices connected to
+    connected: HashSet<String>,
+    /// By peripheral ID
+    devices: BTreeMap<String, Device>,
+    /// By option, such as `--output`
+    sinks: BTreeMap<&'static str, Sink>,
+    /// Errors other than those of sinks, by what failed
+    errors: BTreeMap<&'static str, u64>,
+}
+
+struct Device {
+    name: Option<String>,
+    last_seen: Instant,
+    readings: u64,
+    last_reading: Option<CurrentReadingDetailed>,
+}
+
+#[derive(Default)]
+struct Sink {
+    sent: u64,
+    errors: u64,
+    /// The most recent error, and when it happened
+    last_error: Option<(Instant, String)>,
+}
+
+impl State {
+    pub fn new() -> State {
+        State(Arc::new(Mutex::new(Inner {
+            started: Instant::now(),
+            advertisements: 0,
+            malformed: 0,
+            last_advertisement: None,
+            heard: HashMap::new(),
+            connects: 0,
+            reconnects: 0,
+            connected: HashSet::new(),
+            devices: BTreeMap::new(),
+            sinks: BTreeMap::new(),
+            errors: BTreeMap::new(),
+        })))
+    }
+
+    fn inner(&self) -> std::sync::MutexGuard<'_, Inner> {
+        // the state is only for debugging, so a panic while updating it shouldn't stop it being dumped
+        self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Counts an advertisement received from any device
+    pub fn advertisement(&self, adv: &DiscoveredAranet) {
+        let mut inner = self.inner();
+        inner.advertisements += 1;
+        // 7 bytes of device info, a byte, and then 13 bytes of reading when it's included
+        let len = adv.raw_manufacturer_data.len();
+        if len < 7 || (len > 8 && len < 21) {
+            inner.malformed += 1;
+        }
+        let now = Instant::now();
+        inner.last_advertisement = Some(now);
+        inner.heard.insert(adv.peripheral_id.to_string(), now);
+    }
+
+    /// How long ago the last advertisement was received, if any have been
+    pub fn since_advertisement(&self) -> Option<Duration> {
+        self.inner().last_advertisement.map(|at| at.elapsed())
+    }
+
+    /// Records a reading being used from the device
+    pub fn reading(&self, adv: &DiscoveredAranet) {
+        let mut inner = self.inner();
+        let device = inner.devices.entry(adv.peripheral_id.to_string()).or_insert_with(|| Device {
+            name: None,
+            last_seen: Instant::now(),
+            readings: 0,
+            last_reading: None,
+        });
+        device.name = crate::alias::name(adv).map(str::to_owned);
+        device.last_seen = Instant::now();
+        device.readings += 1;
+        if adv.current_reading.is_some() {
+            device.last_reading = adv.current_reading;
+        }
+    }
+
+    /// Counts a connection to the device, and whether it failed
+    pub fn connect(&self, adv: &DiscoveredAranet, ok: bool) {
+        let mut inner = self.inner();
+        inner.connects += 1;
+        if ! inner.connected.insert(adv.peripheral_id.to_string()) {
+            inner.reconnects += 1;
+        }
+        if ! ok {
+            *inner.errors.entry("connect").or_default() += 1;
+        }
+    }
+
+    /// Records the result of sending a reading to a sink, passing it on
+    pub fn sink<E: fmt::Display>(&self, name: &'static str, result: Result<(), E>) -> Result<(), E> {
+        let mut inner = self.inner();
+        let sink = inner.sinks.entry(name).or_default();
+        match &result {
+            Ok(()) => sink.sent += 1,
+            Err(e) => {
+                sink.errors += 1;
+                sink.last_error = Some((Instant::now(), e.to_string()));
+            },
+        }
+        result
+    }
+
+    /// The state as text, one line per device, sink, and kind of error
+    pub fn dump(&self) -> String {
+        let inner = self.inner();
+        let mut dump = String::new();
+        let _ = writeln!(dump, "aranet state, process {}, running for {}s", std::process::id(), inner.started.elapsed().as_secs());
+        let _ = writeln!(dump, "Advertisements received: {} ({} malformed)", inner.advertisements, inner.malformed);
+        let _ = writeln!(dump, "Devices visible: {}", inner.visible());
+        let _ = writeln!(dump, "Connections: {} ({} reconnects)", inner.connects, inner.reconnects);
+
+        let _ = writeln!(dump, "Devices:");
+        if inner.devices.is_empty() {
+            let _ = writeln!(dump, "  none heard");
+        }
+        for (id, device) in &inner.devices {
+            let name = match &device.name {
+                Some(name) => format!("{} ({})", name, id),
+                None => id.clone(),
+            };
+            let last = device.last_reading.as_ref().map_or_else(|| "none".to_owned(), summary);
+            let _ = writeln!(
+                dump, "  {}: last seen {}s ago, {} readings, last reading: {}",
+                name, device.last_seen.elapsed().as_secs(), device.readings, last,
+            );
+        }
+
+        let _ = writeln!(dump, "Sinks:");
+        if inner.sinks.is_empty() {
+            let _ = writeln!(dump, "  none used");
+        }
+        for (name, sink) in &inner.sinks {
+            let _ = write!(dump, "  {}: {} sent, {} errors", name, sink.sent, sink.errors);
+            if let Some((at, e)) = &sink.last_error {
+                let _ = write!(dump, ", last error {}s ago: {}", at.elapsed().as_secs(), e);
+            }
+            dump.push('\n');
+        }
+
+        let _ = writeln!(dump, "Errors:");
+        if inner.errors.is_empty() {
+            let _ = writeln!(dump, "  none");
+        }
+        for (what, count) in &inner.errors {
+            let _ = writeln!(dump, "  {}: {}", what, count);
+        }
+        dump
+    }
+
+    /// The state as Prometheus metrics, in the text exposition format
+    pub fn metrics(&self) -> String {
+        let inner = self.inner();
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, f64)]| {
+            let _ = writeln!(out, "# HELP aranet_collector_{} {}", name, help);
+            let _ = writeln!(out, "# TYPE aranet_collector_{} {}", name, kind);
+            for (labels, value) in samples {
+                let _ = writeln!(out, "aranet_collector_{}{} {}", name, labels, value);
+            }
+        };
+        let one = |value: f64| [(String::new(), value)];
+
+        let started = SystemTime::now().checked_sub(inner.started.elapsed()).unwrap_or(UNIX_EPOCH);
+        let started = started.duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0);
+        metric(
+            "start_time_seconds", "gauge", "When the collector started, in seconds since the Unix epoch",
+            &one(started.floor()),
+        );
+        metric(
+            "advertisements_total", "counter", "Advertisements received from any device",
+            &one(inner.advertisements as f64),
+        );
+        metric(
+            "malformed_advertisements_total", "counter", "Advertisements whose manufacturer data couldn't be parsed",
+            &one(inner.malformed as f64),
+        );
+        if let Some(at) = inner.last_advertisement {
+            metric(
+                "last_advertisement_age_seconds", "gauge", "How long ago the last advertisement was received",
+                &one(at.elapsed().as_secs_f64().floor()),
+            );
+        }
+        metric(
+            "devices_visible", "gauge", &format!("Devices heard within the last {}s", VISIBLE.as_secs()),
+            &one(inner.visible() as f64),
+        );
+        metric("connects_total", "counter", "Connections attempted to devices", &one(inner.connects as f64));
+        metric(
+            "connect_failures_total", "counter", "Connections to devices that failed",
+            &one(inner.errors.get("connect").copied().unwrap_or(0) as f64),
+        );
+        metric(
+            "reconnects_total", "counter", "Connections to a device that had been connected to before",
+            &one(inner.reconnects as f64),
+        );
+
+        let sink = |f: fn(&Sink) -> u64| -> Vec<(String, f64)> {
+            inner.sinks.iter()
+                .map(|(name, sink)| (format!("{{sink=\"{}\"}}", name.trim_start_matches('-')), f(sink) as f64))
+                .collect()
+        };
+        metric("sink_sent_total", "counter", "Readings sent to each sink", &sink(|sink| sink.sent));
+        metric("sink_errors_total", "counter", "Readings that failed to send to each sink", &sink(|sink| sink.errors));
+        out
+    }
+}
+
+impl Inner {
+    /// The number of devices heard recently
+    fn visible(&self) -> usize {
+        self.heard.values().filter(|at| at.elapsed() <= VISIBLE).count()
+    }
+}
+
+/// A reading on one line, in metric units
+fn summary(r: &CurrentReadingDetailed) -> String {
+    let mut values = Vec::new();
+    if let Some(ppm) = r.co2_ppm {
+        values.push(format!("{} ppm", ppm));
+    }
+    if let Some(c) = r.temperature_c {
+        values.push(format!("{:.1}°C", c));
+    }
+    values.push(format!("{:.0}% humidity", r.humidity * 100.0));
+    if let Some(hpa) = r.pressure_hpa {
+        values.push(format!("{:.1} hPa", hpa));
+    }
+    values.push(format!("{:.0}% battery", r.battery * 100.0));
+    values.push(format!("{}s old when heard", r.age));
+    values.join(", ")
+}
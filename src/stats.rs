@@ -0,0 +1,167 @@
+//! Summary statistics over readings logged with --output, for `aranet stats`.
+//!
+//! For each device, the minimum, maximum, mean, and percentiles of each metric, and how long its readings matched
+//! conditions such as `co2>1000`. Each logged reading stands for one measurement interval of the device, so time is
+//! its interval multiplied by the number of matching readings.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::filter::DeviceFilter;
+use crate::metric::{Condition, Metric};
+use crate::output;
+
+/// The metrics summarized for each device
+const METRICS: [Metric; 5] = [Metric::Co2, Metric::TemperatureC, Metric::Humidity, Metric::Pressure, Metric::Battery];
+
+/// The percentiles reported for each metric
+const PERCENTILES: [u8; 3] = [50, 90, 95];
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct StatsArgs {
+    /// Files of readings logged with --output. Rotated files compressed with --rotate-gzip are decompressed with
+    /// gzip. Use `-` for stdin
+    #[arg(value_name = "FILE", required = true)]
+    files: Vec<PathBuf>,
+    /// Only include readings measured within this long before now: seconds, or a duration such as `7d`. Requires
+    /// that the readings were logged with timestamps
+    #[arg(long, value_name = "DURATION", value_parser = crate::parse_duration)]
+    since: Option<Duration>,
+    /// Report how long each device's readings matched these conditions. Defaults to `co2>1000`. May be repeated
+    #[arg(long, value_name = "CONDITION")]
+    threshold: Vec<Condition>,
+}
+
+#[derive(Default)]
+struct DeviceStats {
+    name: Option<String>,
+    readings: usize,
+    /// Seconds covered by the readings, as the sum of their intervals
+    seconds: u64,
+    values: BTreeMap<&'static str, Vec<f64>>,
+    /// Seconds matching each --threshold
+    matching: Vec<u64>,
+}
+
+/// The value at the percentile of sorted values, by the nearest-rank method
+fn percentile(sorted: &[f64], p: u8) -> f64 {
+    let rank = (p as f64 / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+pub fn run(args: StatsArgs, filter: DeviceFilter, json: bool) -> Result<(), Box<dyn Error>> {
+    let thresholds = match args.threshold.is_empty() {
+        true => vec!["co2>1000".parse::<Condition>()?],
+        false => args.threshold,
+    };
+    let mut devices: BTreeMap<String, DeviceStats> = BTreeMap::new();
+    for logged in output::read(&args.files, &filter, args.since)? {
+        let values = logged.values;
+        let device = devices.entry(logged.device).or_default();
+        if logged.name.is_some() {
+            device.name = logged.name;
+        }
+        device.readings += 1;
+        device.seconds += values.interval as u64;
+        for metric in METRICS {
+            if let Some(value) = values.value(metric) {
+                device.values.entry(metric.name()).or_default().push(value);
+            }
+        }
+        device.matching.resize(thresholds.len(), 0);
+        for (condition, seconds) in thresholds.iter().zip(&mut device.matching) {
+            if values.value(condition.metric).is_some_and(|v| condition.comparison.test(v, condition.value)) {
+                *seconds += values.interval as u64;
+            }
+        }
+    }
+
+    let report: Vec<Value> = devices.into_iter()
+        .map(|(id, mut device)| {
+            let metrics: serde_json::Map<String, Value> = device.values.iter_mut()
+                .map(|(metric, values)| {
+                    values.sort_by(f64::total_cmp);
+                    let mut summary = json!({
+                        "min": values[0],
+                        "max": values[values.len() - 1],
+                        "mean": values.iter().sum::<f64>() / values.len() as f64,
+                    });
+                    for p in PERCENTILES {
+                        summary[format!("p{}", p)] = json!(percentile(values, p));
+                    }
+                    (metric.to_string(), summary)
+                })
+                .collect();
+            let thresholds: Vec<Value> = thresholds.iter().zip(&device.matching)
+                .map(|(condition, seconds)| json!({
+                    "condition": condition.to_string(),
+                    "seconds": seconds,
+                    "fraction": *seconds as f64 / device.seconds.max(1) as f64,
+                }))
+                .collect();
+            let mut entry = json!({
+                "peripheral_id": id,
+                "readings": device.readings,
+                "seconds": device.seconds,
+                "metrics": metrics,
+                "thresholds": thresholds,
+            });
+            if let Some(name) = device.name {
+                entry["name"] = json!(name);
+            }
+            entry
+        })
+        .collect();
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+    if report.is_empty() {
+        println!("No matching readings.");
+    }
+    for (i, device) in report.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        print_text(device);
+    }
+    Ok(())
+}
+
+fn print_text(device: &Value) {
+    let id = device["peripheral_id"].as_str().unwrap_or_default();
+    match device["name"].as_str() {
+        Some(name) => println!("{} ({})", name, id),
+        None => println!("{}", id),
+    }
+    println!("  {} readings over {:.1} hours", device["readings"], device["seconds"].as_f64().unwrap_or(0.0) / 3600.0);
+
+    println!("  {:<10} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8}", "", "min", "mean", "p50", "p90", "p95", "max");
+    for metric in METRICS {
+        let summary = &device["metrics"][metric.name()];
+        if summary.is_null() {
+            continue;
+        }
+        let value = |key: &str| format!("{:.*}", metric.precision(), summary[key].as_f64().unwrap_or(f64::NAN));
+        println!(
+            "  {:<10} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8}",
+            metric.name(), value("min"), value("mean"), value("p50"), value("p90"), value("p95"), value("max"),
+        );
+    }
+
+    let thresholds = device["thresholds"].as_array().map(Vec::as_slice).unwrap_or_default();
+    let width = thresholds.iter().filter_map(|t| t["condition"].as_str()).map(str::len).max().unwrap_or(0).max(10);
+    for threshold in thresholds {
+        println!(
+            "  {:<width$} {:.1} hours ({:.0}%)",
+            threshold["condition"].as_str().unwrap_or_default(),
+            threshold["seconds"].as_f64().unwrap_or(0.0) / 3600.0,
+            threshold["fraction"].as_f64().unwrap_or(0.0) * 100.0,
+        );
+    }
+}
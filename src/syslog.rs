@@ -0,0 +1,201 @@
+//! Sends readings and alerts as RFC 5424 syslog messages, with the values as structured data.
+//!
+//! Messages go to the local syslog socket, or a remote collector over UDP or TCP (with RFC 6587 octet counting).
+
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use aranet::DiscoveredAranet;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpStream, UdpSocket};
+
+use crate::metric::Metric;
+use crate::seen::SeenMeasurements;
+
+/// Default port for remote syslog collectors
+pub const DEFAULT_PORT: u16 = 514;
+
+/// Private enterprise number for structured data IDs, as reserved for documentation by RFC 5612
+const ENTERPRISE_ID: u32 = 32473;
+
+const SEVERITY_WARNING: u8 = 4;
+const SEVERITY_NOTICE: u8 = 5;
+const SEVERITY_INFO: u8 = 6;
+
+/// Where to send syslog messages
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyslogTarget {
+    /// The local syslog daemon's socket
+    Local,
+    Udp(String),
+    Tcp(String),
+}
+
+impl FromStr for SyslogTarget {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let with_port = |addr: &str| match addr.rsplit_once(':') {
+            Some((_, port)) if port.parse::<u16>().is_ok() => addr.to_owned(),
+            _ => format!("{}:{}", addr, DEFAULT_PORT),
+        };
+        if s.eq_ignore_ascii_case("local") {
+            Ok(SyslogTarget::Local)
+        } else if let Some(addr) = s.strip_prefix("udp://") {
+            Ok(SyslogTarget::Udp(with_port(addr)))
+        } else if let Some(addr) = s.strip_prefix("tcp://") {
+            Ok(SyslogTarget::Tcp(with_port(addr)))
+        } else {
+            Err(format!("expected 'local', 'udp://HOST[:PORT]', or 'tcp://HOST[:PORT]', got {:?}", s))
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Facility {
+    User,
+    Daemon,
+    Local0,
+    Local1,
+    Local2,
+    Local3,
+    Local4,
+    Local5,
+    Local6,
+    Local7,
+}
+
+impl Facility {
+    fn code(&self) -> u8 {
+        match self {
+            Facility::User => 1,
+            Facility::Daemon => 3,
+            Facility::Local0 => 16,
+            Facility::Local1 => 17,
+            Facility::Local2 => 18,
+            Facility::Local3 => 19,
+            Facility::Local4 => 20,
+            Facility::Local5 => 21,
+            Facility::Local6 => 22,
+            Facility::Local7 => 23,
+        }
+    }
+}
+
+/// A structured data parameter value, escaped per RFC 5424 section 6.3.3
+struct ParamValue<'a>(&'a str);
+impl fmt::Display for ParamValue<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for c in self.0.chars() {
+            if matches!(c, '"' | '\\' | ']') {
+                write!(f, "\\")?;
+            }
+            write!(f, "{}", c)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Syslog {
+    target: SyslogTarget,
+    facility: Facility,
+    hostname: String,
+    /// To only log each measurement once
+    seen: SeenMeasurements,
+}
+
+impl Syslog {
+    pub fn new(target: SyslogTarget, facility: Facility) -> Syslog {
+        Syslog { target, facility, hostname: hostname(), seen: SeenMeasurements::new() }
+    }
+
+    /// Logs the advertisement's reading, if it's a new measurement
+    pub async fn reading(&mut self, adv: &DiscoveredAranet) -> io::Result<()> {
+        if ! self.seen.is_new(adv) {
+            return Ok(());
+        }
+        let Some(r) = &adv.current_reading else { return Ok(()) };
+
+        let mut data = format!("[reading@{} device=\"{}\"", ENTERPRISE_ID, ParamValue(&adv.peripheral_id.to_string()));
+        if let Some(name) = crate::alias::name(adv) {
+            data.push_str(&format!(" name=\"{}\"", ParamValue(name)));
+        }
+        let mut msg = format!("Reading from {}:", crate::alias::display(adv));
+        for metric in Metric::ALL {
+            if let Some(value) = metric.format(r) {
+                data.push_str(&format!(" {}=\"{}\"", metric, value));
+                msg.push_str(&format!(" {}={}", metric, value));
+            }
+        }
+        data.push(']');
+
+        self.send(SEVERITY_INFO, "reading", &data, &msg).await
+    }
+
+    /// Logs an alert from the config file firing or resolving
+    #[cfg(feature = "config")]
+    pub async fn alert(&self, event: &crate::alerts::AlertEvent) -> io::Result<()> {
+        let severity = match event.transition {
+            crate::alerts::Transition::Firing => SEVERITY_WARNING,
+            crate::alerts::Transition::Resolved => SEVERITY_NOTICE,
+        };
+        let data = format!(
+            "[alert@{} name=\"{}\" device=\"{}\" state=\"{}\" metric=\"{}\" value=\"{:.*}\" threshold=\"{}\"]",
+            ENTERPRISE_ID, ParamValue(&event.name), ParamValue(&event.device), event.transition,
+            event.metric, event.metric.precision(), event.value, event.threshold,
+        );
+        self.send(severity, "alert", &data, &event.to_string()).await
+    }
+
+    async fn send(&self, severity: u8, msgid: &str, data: &str, msg: &str) -> io::Result<()> {
+        let message = format!(
+            "<{}>1 {} {} aranet {} {} {} {}",
+            self.facility.code() * 8 + severity,
+            humantime::format_rfc3339_micros(SystemTime::now()),
+            self.hostname,
+            std::process::id(),
+            msgid,
+            data,
+            msg,
+        );
+        log::trace!("syslog message: {}", message);
+
+        match &self.target {
+            SyslogTarget::Local => send_local(message.as_bytes()).await,
+            SyslogTarget::Udp(addr) => {
+                let socket = UdpSocket::bind(if addr.starts_with('[') { "[::]:0" } else { "0.0.0.0:0" }).await?;
+                socket.send_to(message.as_bytes(), addr).await.map(|_| ())
+            },
+            SyslogTarget::Tcp(addr) => {
+                let mut stream = TcpStream::connect(addr).await?;
+                // octet counting framing, so messages may contain newlines
+                stream.write_all(format!("{} {}", message.len(), message).as_bytes()).await?;
+                stream.shutdown().await
+            },
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn send_local(message: &[u8]) -> io::Result<()> {
+    let socket = tokio::net::UnixDatagram::unbound()?;
+    let path = if cfg!(target_os = "macos") { "/var/run/syslog" } else { "/dev/log" };
+    socket.send_to(message, path).await.map(|_| ())
+}
+
+#[cfg(not(unix))]
+async fn send_local(_message: &[u8]) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "no local syslog on this platform, use udp:// or tcp://"))
+}
+
+/// The machine's host name, or the RFC 5424 nil value if unknown
+fn hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|h| h.trim().to_owned())
+        .or_else(|_| std::env::var("COMPUTERNAME"))
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .ok()
+        .filter(|h| ! h.is_empty() && ! h.contains(' '))
+        .unwrap_or_else(|| "-".to_owned())
+}
@@ -0,0 +1,72 @@
+//! Tags (such as `site=office`) attached to each device's readings in sinks, so multi-site deployments can slice
+//! their metrics.
+//!
+//! Tags given with --tag apply to every device, and those in the `[tags]` section of the config file to one device
+//! each, overriding --tag tags of the same key.
+
+// the outputs using these are optional features
+#![allow(dead_code)]
+
+use std::sync::OnceLock;
+
+use aranet::DiscoveredAranet;
+
+use crate::alias::Device;
+
+/// A tag's key and value
+pub type Tag = (String, String);
+
+struct Tags {
+    all: Vec<Tag>,
+    devices: Vec<(Device, Vec<Tag>)>,
+}
+
+static TAGS: OnceLock<Tags> = OnceLock::new();
+
+/// Checks a tag key is usable in every sink: a letter or `_`, followed by letters, digits, and `_`, as for
+/// Prometheus labels
+pub fn validate_key(key: &str) -> Result<(), String> {
+    let mut chars = key.chars();
+    let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    match valid {
+        true => Ok(()),
+        false => Err(format!("invalid tag key {:?}, expected letters, digits, and underscores", key)),
+    }
+}
+
+/// Parses a `KEY=VALUE` --tag
+pub fn parse(s: &str) -> Result<Tag, String> {
+    let (key, value) = s.split_once('=').ok_or_else(|| format!("invalid tag {:?}, expected KEY=VALUE", s))?;
+    let (key, value) = (key.trim(), value.trim());
+    validate_key(key)?;
+    Ok((key.to_owned(), value.to_owned()))
+}
+
+/// Sets the tags for the rest of the process. Only the first call has any effect.
+pub fn init(all: Vec<Tag>, devices: Vec<(Device, Vec<Tag>)>) {
+    let _ = TAGS.set(Tags { all, devices });
+}
+
+/// Whether any device's tags are keyed by serial number, which can only be matched with --registry
+pub fn any_serials() -> bool {
+    TAGS.get().is_some_and(|tags| tags.devices.iter().any(|(device, _)| matches!(device, Device::Serial(_))))
+}
+
+/// The tags of the device that sent this advertisement, sorted by key
+pub fn of(adv: &DiscoveredAranet) -> Vec<(&'static str, &'static str)> {
+    let Some(tags) = TAGS.get() else { return Vec::new() };
+    let device = tags.devices.iter()
+        .filter(|(device, _)| device.matches(adv))
+        .flat_map(|(_, tags)| tags);
+    let mut of: Vec<(&'static str, &'static str)> = Vec::new();
+    for (key, value) in tags.all.iter().chain(device) {
+        // later tags override earlier ones, so the device's own override those for every device
+        match of.iter_mut().find(|(k, _)| k == key) {
+            Some(tag) => tag.1 = value,
+            None => of.push((key, value)),
+        }
+    }
+    of.sort();
+    of
+}
@@ -0,0 +1,113 @@
+//! When each advertisement was received and its reading was measured, as set by --timestamp-format and --timezone.
+//!
+//! The measurement time is the receive time minus the reading's age, so it's only as precise as the age (seconds).
+
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use aranet::DiscoveredAranet;
+use jiff::tz::{Offset, TimeZone};
+use jiff::Timestamp;
+
+static SETTINGS: OnceLock<(TimestampFormat, Zone)> = OnceLock::new();
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampFormat {
+    /// RFC 3339 / ISO 8601, such as `2024-05-01T14:03:27+02:00`
+    Iso8601,
+    /// Seconds since the Unix epoch
+    Unix,
+    /// Don't output timestamps
+    None,
+}
+
+/// The time zone ISO 8601 timestamps are shown in: `local`, `UTC`, an IANA name such as `Europe/Berlin`, or a fixed
+/// offset such as `+02:00`
+#[derive(Debug, Clone)]
+pub struct Zone(TimeZone);
+
+impl FromStr for Zone {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Zone, String> {
+        if s.eq_ignore_ascii_case("local") {
+            return Ok(Zone(TimeZone::system()));
+        }
+        if let Some(sign) = s.chars().next().filter(|c| *c == '+' || *c == '-') {
+            let (hours, minutes) = s[1..].split_once(':').unwrap_or((&s[1..], "0"));
+            let offset = hours.parse::<i32>().ok()
+                .zip(minutes.parse::<i32>().ok())
+                .filter(|(h, m)| *h <= 25 && *m < 60)
+                .and_then(|(h, m)| Offset::from_seconds((h * 3600 + m * 60) * if sign == '-' { -1 } else { 1 }).ok())
+                .ok_or_else(|| format!("invalid UTC offset {:?}, expected one such as +02:00", s))?;
+            return Ok(Zone(TimeZone::fixed(offset)));
+        }
+        TimeZone::get(s).map(Zone).map_err(|e| format!("unknown time zone {:?}: {}", s, e))
+    }
+}
+
+/// A timestamp as output in JSON, depending on --timestamp-format
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(untagged))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum Stamp {
+    Iso8601(String),
+    Unix(i64),
+}
+
+impl std::fmt::Display for Stamp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Stamp::Iso8601(s) => f.write_str(s),
+            Stamp::Unix(secs) => write!(f, "{}", secs),
+        }
+    }
+}
+
+/// Sets the timestamp format and time zone for the rest of the process. Only the first call has any effect.
+pub fn init(format: TimestampFormat, zone: Zone) {
+    let _ = SETTINGS.set((format, zone));
+}
+
+/// The time zone set by --timezone, or the system's if it hasn't been set
+#[cfg(feature = "plot")]
+pub fn zone() -> TimeZone {
+    SETTINGS.get().map(|(_, Zone(zone))| zone.clone()).unwrap_or_else(TimeZone::system)
+}
+
+fn stamp(at: Timestamp) -> Option<Stamp> {
+    let (format, Zone(zone)) = SETTINGS.get()?;
+    match format {
+        TimestampFormat::Iso8601 => Some(Stamp::Iso8601(at.display_with_offset(zone.to_offset(at)).to_string())),
+        TimestampFormat::Unix => Some(Stamp::Unix(at.as_second())),
+        TimestampFormat::None => None,
+    }
+}
+
+/// A time as seconds since the Unix epoch, such as a logged measurement time, if timestamps are enabled
+#[cfg(feature = "serde_json")]
+pub fn at(secs: i64) -> Option<Stamp> {
+    stamp(Timestamp::from_second(secs).ok()?)
+}
+
+/// When the advertisement was received, if timestamps are enabled
+pub fn received(adv: &DiscoveredAranet) -> Option<Stamp> {
+    stamp(Timestamp::try_from(adv.received).ok()?.round(jiff::Unit::Second).ok()?)
+}
+
+/// When the advertisement's reading was measured, if timestamps are enabled and it has one
+pub fn measured(adv: &DiscoveredAranet) -> Option<Stamp> {
+    let age = adv.current_reading?.age;
+    let received = Timestamp::try_from(adv.received).ok()?.round(jiff::Unit::Second).ok()?;
+    stamp(received.checked_sub(jiff::SignedDuration::from_secs(age as i64)).ok()?)
+}
+
+/// Prints when the advertisement was received and measured, for text output
+pub fn print(adv: &DiscoveredAranet) {
+    if let Some(received) = received(adv) {
+        println!("Received: {}", received);
+    }
+    if let Some(measured) = measured(adv) {
+        println!("Measured: {}", measured);
+    }
+}
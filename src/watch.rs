@@ -0,0 +1,102 @@
+//! A table of the latest reading from every device, redrawn in place as advertisements arrive, for `--repeat --all`
+//! in a terminal.
+//!
+//! Uses plain ANSI escapes (cursor up, clear to end of screen) rather than a TUI library, so it works in any terminal
+//! that scrolling output would.
+
+use std::io::{self, Write};
+use std::time::Instant;
+
+use aranet::{temperature_c_to_f, pressure_hpa_to_atm, DiscoveredAranet, Units};
+
+const HEADER: [&str; 8] = ["Device", "CO2", "Temperature", "Humidity", "Pressure", "Battery", "Status", "Age"];
+
+pub struct WatchTable {
+    units: Units,
+    /// The latest advertisement from each device, in the order they were first heard, and when it was received
+    devices: Vec<(DiscoveredAranet, Instant)>,
+    /// Lines drawn last time, to move back over
+    drawn: usize,
+}
+
+impl WatchTable {
+    pub fn new(units: Units) -> WatchTable {
+        WatchTable { units, devices: Vec::new(), drawn: 0 }
+    }
+
+    /// Records the advertisement as the latest from its device. Advertisements without a measurement don't replace
+    /// one that had it.
+    pub fn update(&mut self, adv: DiscoveredAranet) {
+        let now = Instant::now();
+        match self.devices.iter_mut().find(|(prev, _)| prev.peripheral_id == adv.peripheral_id) {
+            Some((prev, received)) => if adv.current_reading.is_some() || prev.current_reading.is_none() {
+                *prev = adv;
+                *received = now;
+            },
+            None => self.devices.push((adv, now)),
+        }
+    }
+
+    fn row(&self, adv: &DiscoveredAranet, received: Instant) -> [String; 8] {
+        let name = crate::alias::display(adv);
+        let Some(r) = adv.current_reading else {
+            return [name, "-".into(), "-".into(), "-".into(), "-".into(), "-".into(), "-".into(), "-".into()];
+        };
+        let precision = crate::precision::get();
+        let places = precision.temperature.unwrap_or(1);
+        let temperature = r.temperature_c.map_or_else(|| "-".to_owned(), |c| match self.units {
+            Units::Both => format!("{:.*}°C {:.*}°F", places, c, places, temperature_c_to_f(c)),
+            Units::Metric => format!("{:.*}°C", places, c),
+            Units::Imperial => format!("{:.*}°F", places, temperature_c_to_f(c)),
+        });
+        let pressure = r.pressure_hpa.map_or_else(|| "-".to_owned(), |hpa| match self.units {
+            Units::Both | Units::Metric => format!("{:.*} hPa", precision.pressure.unwrap_or(0), hpa),
+            Units::Imperial => format!("{:.*} atm", precision.pressure.unwrap_or(3), pressure_hpa_to_atm(hpa)),
+        });
+        // the measurement keeps ageing after it was advertised
+        let age = r.age as u64 + received.elapsed().as_secs();
+        [
+            name,
+            r.co2_ppm.map_or_else(|| "-".to_owned(), |ppm| format!("{} ppm", ppm)),
+            temperature,
+            format!("{:.*}%", precision.humidity.unwrap_or(0), r.humidity * 100.0),
+            pressure,
+            format!("{:.*}%", precision.battery.unwrap_or(0), r.battery * 100.0),
+            format!("{:?}", r.status),
+            // including devices that have since stopped advertising
+            match age as f64 > r.interval as f64 * crate::stale::intervals() {
+                true => format!("{}/{}s (stale)", age, r.interval),
+                false => format!("{}/{}s", age, r.interval),
+            },
+        ]
+    }
+
+    /// Draws the table over the previously drawn one
+    pub fn draw(&mut self) -> io::Result<()> {
+        let rows: Vec<[String; 8]> = self.devices.iter().map(|(adv, received)| self.row(adv, *received)).collect();
+        let mut widths = HEADER.map(|h| h.chars().count());
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+
+        let mut out = io::stdout().lock();
+        if self.drawn > 0 {
+            // back to the start of the previous table, clearing it
+            write!(out, "\x1b[{}A\r\x1b[J", self.drawn)?;
+        }
+        let line = |out: &mut io::StdoutLock, cells: &mut dyn Iterator<Item = &str>| -> io::Result<()> {
+            let cells: Vec<String> = cells.zip(widths).map(|(cell, width)| {
+                format!("{}{}", cell, " ".repeat(width - cell.chars().count()))
+            }).collect();
+            writeln!(out, "{}", cells.join("  ").trim_end())
+        };
+        line(&mut out, &mut HEADER.iter().copied())?;
+        for row in &rows {
+            line(&mut out, &mut row.iter().map(String::as_str))?;
+        }
+        self.drawn = rows.len() + 1;
+        out.flush()
+    }
+}
@@ -0,0 +1,119 @@
+//! POSTs readings and alerts as JSON to a URL, for IFTTT, Slack, ntfy, or home-grown integrations.
+//!
+//! Requests can be signed with HMAC-SHA256 over the body, sent as `X-Aranet-Signature: sha256=<hex>`.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use aranet::DiscoveredAranet;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::seen::SeenMeasurements;
+
+/// Header carrying the HMAC-SHA256 signature of the body, if a secret is configured
+pub const SIGNATURE_HEADER: &str = "X-Aranet-Signature";
+
+/// Delay before the first retry, doubling for each one after
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Which events are POSTed to the webhook
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebhookEvents {
+    /// Each new reading
+    Readings,
+    /// Alerts from the config file firing or resolving
+    Alerts,
+    /// Both readings and alerts
+    All,
+}
+
+#[derive(serde::Serialize)]
+struct Payload<'a> {
+    /// `reading` or `alert`
+    event: &'static str,
+    device: String,
+    /// The device's alias, if it has one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<&'static str>,
+    /// Unix timestamp the event was sent at
+    timestamp: u64,
+    advertisement: &'a DiscoveredAranet,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    alert: Option<serde_json::Value>,
+}
+
+pub struct Webhook {
+    client: reqwest::Client,
+    url: String,
+    secret: Option<String>,
+    retries: u32,
+    events: WebhookEvents,
+    /// To only POST each measurement once
+    seen: SeenMeasurements,
+}
+
+impl Webhook {
+    pub fn new(url: String, secret: Option<String>, retries: u32, events: WebhookEvents) -> Webhook {
+        let client = reqwest::Client::builder()
+            .user_agent(concat!("aranet/", env!("CARGO_PKG_VERSION")))
+            .timeout(Duration::from_secs(10))
+            .build()
+            .expect("unable to initialize HTTP client");
+        Webhook { client, url, secret, retries, events, seen: SeenMeasurements::new() }
+    }
+
+    /// POSTs the advertisement's reading, if it's a new measurement
+    pub async fn reading(&mut self, adv: &DiscoveredAranet) -> Result<(), reqwest::Error> {
+        if self.events == WebhookEvents::Alerts || ! self.seen.is_new(adv) {
+            return Ok(());
+        }
+        self.post(&Payload { event: "reading", device: adv.peripheral_id.to_string(), name: crate::alias::name(adv), timestamp: now(), advertisement: adv, alert: None }).await
+    }
+
+    /// POSTs an alert from the config file firing or resolving, alongside the reading that caused it
+    #[cfg(feature = "config")]
+    pub async fn alert(&self, adv: &DiscoveredAranet, event: &crate::alerts::AlertEvent) -> Result<(), reqwest::Error> {
+        if self.events == WebhookEvents::Readings {
+            return Ok(());
+        }
+        let alert = serde_json::to_value(event).expect("unable to serialize alert as JSON");
+        self.post(&Payload { event: "alert", device: adv.peripheral_id.to_string(), name: crate::alias::name(adv), timestamp: now(), advertisement: adv, alert: Some(alert) }).await
+    }
+
+    /// POSTs the payload, retrying failed requests with exponential backoff
+    async fn post(&self, payload: &Payload<'_>) -> Result<(), reqwest::Error> {
+        let body = serde_json::to_vec(payload).expect("unable to serialize webhook payload as JSON");
+        let signature = self.secret.as_ref().map(|secret| {
+            let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+            mac.update(&body);
+            format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+        });
+
+        let mut backoff = INITIAL_BACKOFF;
+        let mut attempt = 0;
+        loop {
+            let mut request = self.client.post(&self.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body.clone());
+            if let Some(signature) = &signature {
+                request = request.header(SIGNATURE_HEADER, signature);
+            }
+
+            log::debug!("posting {} event to webhook {}", payload.event, self.url);
+            match request.send().await.and_then(|r| r.error_for_status()) {
+                Ok(_) => return Ok(()),
+                Err(e) if attempt < self.retries => {
+                    log::debug!("webhook request failed, retrying in {:?}: {}", backoff, e);
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                },
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
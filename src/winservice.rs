@@ -0,0 +1,202 @@
+//! Runs `aranet daemon` as a Windows service, for `aranet service install`, `uninstall` and `run`.
+//!
+//! The installed service starts on boot as LocalSystem, and runs `aranet service run` with the current configuration,
+//! as `aranet service generate` does for systemd: the config file, `ARANET_OPT_*` environment variables (set in the
+//! service's `Environment` registry value), and options given after `--`. It's restarted 10s after failing.
+//!
+//! `aranet service run` connects to the service control manager and then runs as `aranet daemon` does, stopping when
+//! the service is stopped. The dispatcher has to run on a thread of its own, which calls [`service_main`]; that
+//! reports the service running, and then waits for [`stopped`] to report the daemon's exit status.
+//!
+//! Services have no console, so stderr is lost: use --eventlog or --output to see what the collector is doing.
+
+use std::error::Error;
+use std::ffi::OsString;
+use std::io;
+use std::sync::{mpsc, Arc, Mutex, OnceLock};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use tokio::sync::{oneshot, Notify};
+use windows_service::service::{
+    ServiceAccess, ServiceAction, ServiceActionType, ServiceControl, ServiceControlAccept, ServiceErrorControl,
+    ServiceExitCode, ServiceFailureActions, ServiceFailureResetPeriod, ServiceInfo, ServiceStartType, ServiceState,
+    ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{self, ServiceControlHandlerResult, ServiceStatusHandle};
+use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+use windows_service::{define_windows_service, service_dispatcher};
+use windows_sys::Win32::Foundation::ERROR_SUCCESS;
+use windows_sys::Win32::System::Registry::{
+    RegCloseKey, RegOpenKeyExW, RegSetValueExW, HKEY, HKEY_LOCAL_MACHINE, KEY_SET_VALUE, REG_MULTI_SZ,
+};
+
+const SERVICE_NAME: &str = "aranet";
+const REGISTRY_KEY: &str = r"SYSTEM\CurrentControlSet\Services\aranet";
+
+/// How long to wait before restarting the service after it fails, as the Bluetooth stack may still be recovering
+const RESTART_DELAY: Duration = Duration::from_secs(10);
+
+/// Notified when the service control manager asks the service to stop
+static STOP: OnceLock<Arc<Notify>> = OnceLock::new();
+
+/// Handed to [`service_main`], to report back once the service is running
+static STARTED: Mutex<Option<oneshot::Sender<Result<mpsc::Sender<i32>, String>>>> = Mutex::new(None);
+
+/// Set once running as a service: where to send the daemon's exit status, and the dispatcher's thread
+static RUNNING: Mutex<Option<(mpsc::Sender<i32>, JoinHandle<()>)>> = Mutex::new(None);
+
+/// A nul-terminated UTF-16 string
+fn wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(Some(0)).collect()
+}
+
+/// Sets the service's environment variables. `REG_MULTI_SZ` is a list of nul-terminated strings, ending with an
+/// empty one.
+fn set_environment(env: &[(String, String)]) -> io::Result<()> {
+    let mut key: HKEY = 0;
+    let status = unsafe { RegOpenKeyExW(HKEY_LOCAL_MACHINE, wide(REGISTRY_KEY).as_ptr(), 0, KEY_SET_VALUE, &mut key) };
+    if status != ERROR_SUCCESS {
+        return Err(io::Error::from_raw_os_error(status as i32));
+    }
+    let mut value: Vec<u16> = env.iter().flat_map(|(name, value)| wide(&format!("{}={}", name, value))).collect();
+    value.push(0);
+    let bytes: Vec<u8> = value.iter().flat_map(|c| c.to_le_bytes()).collect();
+    let status = unsafe {
+        RegSetValueExW(key, wide("Environment").as_ptr(), 0, REG_MULTI_SZ, bytes.as_ptr(), bytes.len() as u32)
+    };
+    unsafe { RegCloseKey(key) };
+    match status {
+        ERROR_SUCCESS => Ok(()),
+        status => Err(io::Error::from_raw_os_error(status as i32)),
+    }
+}
+
+/// Installs the service, running this binary with these arguments (without the program name) and environment.
+/// Requires Administrator privileges.
+pub fn install(args: &[String], env: &[(String, String)]) -> Result<(), Box<dyn Error>> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE)?;
+    let info = ServiceInfo {
+        name: OsString::from(SERVICE_NAME),
+        display_name: OsString::from("Aranet4 collector"),
+        service_type: ServiceType::OWN_PROCESS,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path: std::env::current_exe().map_err(|e| format!("unable to find the aranet binary: {}", e))?,
+        launch_arguments: args.iter().map(OsString::from).collect(),
+        dependencies: Vec::new(),
+        // LocalSystem
+        account_name: None,
+        account_password: None,
+    };
+    let service = manager.create_service(&info, ServiceAccess::CHANGE_CONFIG | ServiceAccess::START)?;
+    service.set_description("Listens for Aranet4 advertisements and sends their readings on")?;
+    service.update_failure_actions(ServiceFailureActions {
+        reset_period: ServiceFailureResetPeriod::After(Duration::from_secs(24 * 60 * 60)),
+        reboot_msg: None,
+        command: None,
+        actions: Some(vec![ServiceAction { action_type: ServiceActionType::Restart, delay: RESTART_DELAY }; 3]),
+    })?;
+    // a non-zero exit status is a failure too, not only a crash
+    service.set_failure_actions_on_non_crash_failures(true)?;
+    if ! env.is_empty() {
+        set_environment(env).map_err(|e| format!("unable to set the service's environment: {}", e))?;
+    }
+    Ok(())
+}
+
+/// Stops the service if it's running, and deletes it. Requires Administrator privileges.
+pub fn uninstall() -> Result<(), Box<dyn Error>> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+    let service = manager.open_service(SERVICE_NAME, ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE)?;
+    // only marked for deletion until it has stopped
+    service.delete()?;
+    if service.query_status()?.current_state != ServiceState::Stopped {
+        service.stop()?;
+    }
+    Ok(())
+}
+
+/// The status of the service in this state
+fn status(state: ServiceState, exit_code: ServiceExitCode) -> ServiceStatus {
+    ServiceStatus {
+        service_type: ServiceType::OWN_PROCESS,
+        current_state: state,
+        controls_accepted: match state {
+            ServiceState::Running => ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+            _ => ServiceControlAccept::empty(),
+        },
+        exit_code,
+        checkpoint: 0,
+        wait_hint: Duration::default(),
+        process_id: None,
+    }
+}
+
+define_windows_service!(ffi_service_main, service_main);
+
+/// Run by the dispatcher once the service has started, reporting it running until the daemon has stopped
+fn service_main(_arguments: Vec<OsString>) {
+    let Some(started) = STARTED.lock().unwrap().take() else { return };
+    let stop = STOP.get_or_init(Default::default).clone();
+    let handler = move |control| match control {
+        ServiceControl::Stop | ServiceControl::Shutdown => {
+            stop.notify_one();
+            ServiceControlHandlerResult::NoError
+        },
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    };
+    let handle: ServiceStatusHandle = match service_control_handler::register(SERVICE_NAME, handler) {
+        Ok(handle) => handle,
+        Err(e) => {
+            let _ = started.send(Err(format!("unable to handle service controls: {}", e)));
+            return;
+        },
+    };
+    if let Err(e) = handle.set_service_status(status(ServiceState::Running, ServiceExitCode::NO_ERROR)) {
+        let _ = started.send(Err(format!("unable to report the service running: {}", e)));
+        return;
+    }
+
+    let (exited, exit_status) = mpsc::channel();
+    if started.send(Ok(exited)).is_err() {
+        return;
+    }
+    // the sender is dropped without a status if the process is exiting some other way
+    let exit_code = match exit_status.recv().unwrap_or(1) {
+        0 => ServiceExitCode::NO_ERROR,
+        code => ServiceExitCode::ServiceSpecific(code as u32),
+    };
+    let _ = handle.set_service_status(status(ServiceState::Stopped, exit_code));
+}
+
+/// Connects to the service control manager as the `aranet` service, returning once it's running. Fails when not
+/// started by the service control manager, such as from a prompt.
+pub async fn start() -> Result<(), Box<dyn Error>> {
+    let (started, running) = oneshot::channel();
+    *STARTED.lock().unwrap() = Some(started);
+    let dispatcher = std::thread::spawn(|| {
+        if let Err(e) = service_dispatcher::start(SERVICE_NAME, ffi_service_main) {
+            if let Some(started) = STARTED.lock().unwrap().take() {
+                let _ = started.send(Err(format!("unable to run as a service, as it wasn't started as one: {}", e)));
+            }
+        }
+    });
+    let exited = running.await.map_err(|_| "the service stopped before it started")??;
+    *RUNNING.lock().unwrap() = Some((exited, dispatcher));
+    Ok(())
+}
+
+/// Resolves once the service control manager asks the service to stop, or never when not running as a service
+pub async fn stop_requested() {
+    STOP.get_or_init(Default::default).notified().await
+}
+
+/// Reports the daemon's exit status to the service control manager, if running as a service, and waits for the
+/// dispatcher to finish
+pub fn stopped(exit_status: i32) {
+    let Some((exited, dispatcher)) = RUNNING.lock().unwrap().take() else { return };
+    let _ = exited.send(exit_status);
+    let _ = dispatcher.join();
+}
@@ -0,0 +1,88 @@
+//! XML encoding of the JSON output, for --format xml.
+//!
+//! Each document has an `<aranet>` root, with a `<reading>` element for each reading. Within those, every field of
+//! the JSON output is an element of the same name, so the structure follows `aranet schema`:
+//!
+//! ```xml
+//! <aranet>
+//!   <reading>
+//!     <current_reading>
+//!       <co2_ppm>1000</co2_ppm>
+//!       ...
+//!     </current_reading>
+//!     <name>office</name>
+//!     ...
+//!   </reading>
+//! </aranet>
+//! ```
+//!
+//! Values that are absent (`null`) are empty elements, and arrays repeat their element for each item.
+
+use std::io::Write;
+
+use serde_json::Value;
+
+use crate::error::ErrorKind;
+
+/// Encodes the readings as an XML document, then writes and flushes it. Exits if stdout has been closed.
+pub fn print<T: serde::Serialize>(value: &T) {
+    let value = serde_json::to_value(value).expect("unable to serialize output");
+    let mut doc = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<aranet>\n");
+    match &value {
+        Value::Array(readings) => for reading in readings {
+            element(&mut doc, "reading", reading, 1);
+        },
+        reading => element(&mut doc, "reading", reading, 1),
+    }
+    doc.push_str("</aranet>\n");
+
+    let mut stdout = std::io::stdout().lock();
+    if let Err(e) = stdout.write_all(doc.as_bytes()).and_then(|()| stdout.flush()) {
+        log::debug!("unable to write to stdout, exiting: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// An error response, as a document with an `<error>` root. Errors ending a run have their kind and exit status as
+/// attributes.
+pub fn error(msg: &str, kind: Option<ErrorKind>) -> String {
+    let attrs = kind.map(|kind| format!(" kind=\"{}\" code=\"{}\"", kind.name(), kind.exit_code())).unwrap_or_default();
+    format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<error{}>{}</error>", attrs, escape(msg))
+}
+
+/// Escapes text for use within an element or attribute value
+pub fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn element(out: &mut String, name: &str, value: &Value, depth: usize) {
+    let indent = "  ".repeat(depth);
+    match value {
+        Value::Null => out.push_str(&format!("{}<{}/>\n", indent, name)),
+        Value::Bool(b) => out.push_str(&format!("{}<{}>{}</{}>\n", indent, name, b, name)),
+        Value::Number(n) => out.push_str(&format!("{}<{}>{}</{}>\n", indent, name, crate::yaml::number(n), name)),
+        Value::String(s) => out.push_str(&format!("{}<{}>{}</{}>\n", indent, name, escape(s), name)),
+        Value::Array(items) => for item in items {
+            element(out, name, item, depth);
+        },
+        Value::Object(fields) if fields.is_empty() => out.push_str(&format!("{}<{}/>\n", indent, name)),
+        Value::Object(fields) => {
+            out.push_str(&format!("{}<{}>\n", indent, name));
+            for (key, value) in fields {
+                element(out, key, value, depth + 1);
+            }
+            out.push_str(&format!("{}</{}>\n", indent, name));
+        },
+    }
+}
@@ -0,0 +1,113 @@
+//! YAML encoding of the JSON output, for --format yaml.
+//!
+//! Each value is written as its own block style document, starting with `---`, so repeated readings form a stream of
+//! documents. Strings that could be read back as another type (such as `true`, numbers, or timestamps) are quoted.
+
+use std::io::Write;
+
+use serde_json::{Number, Value};
+
+/// Words YAML 1.1 parsers read as booleans or null when unquoted
+const RESERVED: [&str; 11] = ["true", "false", "yes", "no", "on", "off", "y", "n", "null", "~", ""];
+
+/// Encodes the value as a YAML document, then writes and flushes it. Exits if stdout has been closed.
+pub fn print<T: serde::Serialize>(value: &T) {
+    let value = serde_json::to_value(value).expect("unable to serialize output");
+    let mut doc = String::from("---\n");
+    match is_block(&value) {
+        true => block(&mut doc, &value, 0, false),
+        false => {
+            doc.push_str(&scalar(&value));
+            doc.push('\n');
+        },
+    }
+
+    let mut stdout = std::io::stdout().lock();
+    if let Err(e) = stdout.write_all(doc.as_bytes()).and_then(|()| stdout.flush()) {
+        log::debug!("unable to write to stdout, exiting: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// A number as written in the JSON output, so single precision values aren't written as their double precision
+/// expansion (eg: `0.87` rather than `0.8700000047683716`)
+pub fn number(n: &Number) -> String {
+    match n.as_f64() {
+        Some(f) if ! n.is_i64() && ! n.is_u64() && (f as f32) as f64 == f => (f as f32).to_string(),
+        _ => n.to_string(),
+    }
+}
+
+fn string(s: &str) -> String {
+    let plain = s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '/')
+        && ! s.ends_with(' ')
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || " _./-".contains(c))
+        && ! RESERVED.iter().any(|word| s.eq_ignore_ascii_case(word));
+    match plain {
+        true => s.to_owned(),
+        // JSON strings are valid YAML double quoted scalars
+        false => serde_json::to_string(s).expect("unable to quote string"),
+    }
+}
+
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_owned(),
+        Value::Bool(b) => b.to_string(),
+        Value::Number(n) => number(n),
+        Value::String(s) => string(s),
+        Value::Array(_) => "[]".to_owned(),
+        Value::Object(_) => "{}".to_owned(),
+    }
+}
+
+fn is_block(value: &Value) -> bool {
+    match value {
+        Value::Object(fields) => ! fields.is_empty(),
+        Value::Array(items) => ! items.is_empty(),
+        _ => false,
+    }
+}
+
+/// Writes the entries of a non-empty map or sequence, indented by `indent`. With `inline_first`, the first entry
+/// continues the current line, as after a sequence's `- `.
+fn block(out: &mut String, value: &Value, indent: usize, inline_first: bool) {
+    let pad = |out: &mut String, i: usize| if i > 0 || ! inline_first {
+        out.push_str(&" ".repeat(indent));
+    };
+    match value {
+        Value::Object(fields) => for (i, (key, value)) in fields.iter().enumerate() {
+            pad(out, i);
+            out.push_str(&string(key));
+            out.push(':');
+            match value {
+                // nested maps are indented, but sequences may be at the same level as their key
+                Value::Object(_) if is_block(value) => {
+                    out.push('\n');
+                    block(out, value, indent + 2, false);
+                },
+                Value::Array(_) if is_block(value) => {
+                    out.push('\n');
+                    block(out, value, indent, false);
+                },
+                _ => {
+                    out.push(' ');
+                    out.push_str(&scalar(value));
+                    out.push('\n');
+                },
+            }
+        },
+        Value::Array(items) => for (i, item) in items.iter().enumerate() {
+            pad(out, i);
+            out.push_str("- ");
+            match is_block(item) {
+                true => block(out, item, indent + 2, true),
+                false => {
+                    out.push_str(&scalar(item));
+                    out.push('\n');
+                },
+            }
+        },
+        _ => unreachable!("only maps and sequences are written as blocks"),
+    }
+}
@@ -0,0 +1,312 @@
+//! Pushes readings to a Zabbix server/proxy using the sender (trapper) protocol.
+//!
+//! Each metric of each device is sent as its own item, keyed by the device's alias or address (eg:
+//! `aranet.co2[office]`), so readings from several devices can be pushed under the same Zabbix host. The items
+//! are `aranet.co2`, `aranet.temperature` (°C), `aranet.humidity` (%), `aranet.pressure` (hPa),
+//! `aranet.battery` (%), `aranet.status` (1-3), and `aranet.age` (seconds).
+//!
+//! When a device is first seen, the list of devices is also pushed to `aranet.discovery` as low-level discovery
+//! data with a `{#DEVICE}` macro, so a "Zabbix trapper" discovery rule with item prototypes such as
+//! `aranet.co2[{#DEVICE}]` creates the items for each device.
+//!
+//! Protocol reference: https://www.zabbix.com/documentation/current/en/manual/appendix/protocols/header_datalen
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use aranet::DiscoveredAranet;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::seen::SeenMeasurements;
+
+/// Default port of the Zabbix server/proxy trapper
+pub const DEFAULT_PORT: u16 = 10051;
+
+const HEADER: &[u8; 4] = b"ZBXD";
+const FLAG_ZABBIX_PROTOCOL: u8 = 0x01;
+
+/// How long to wait to connect, and for each read and write
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// The longest response accepted. Replies to a sender are a short summary, so anything longer isn't from Zabbix.
+const MAX_RESPONSE: usize = 4 * 1024 * 1024;
+/// Key of the low-level discovery item listing the devices
+const DISCOVERY_KEY: &str = "aranet.discovery";
+
+#[derive(Debug)]
+pub enum ZabbixError {
+    Io(io::Error),
+    /// The server replied with something other than a ZBXD packet
+    MalformedResponse,
+    /// The server parsed the request, but did not report success
+    Rejected(String),
+}
+impl fmt::Display for ZabbixError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ZabbixError::Io(e) => write!(f, "unable to communicate with zabbix server: {}", e),
+            ZabbixError::MalformedResponse => write!(f, "received malformed response from zabbix server"),
+            ZabbixError::Rejected(info) => write!(f, "zabbix server rejected pushed values: {}", info),
+        }
+    }
+}
+impl std::error::Error for ZabbixError {}
+impl From<io::Error> for ZabbixError {
+    fn from(e: io::Error) -> Self {
+        ZabbixError::Io(e)
+    }
+}
+
+#[derive(serde::Serialize)]
+struct SenderItem<'a> {
+    host: &'a str,
+    key: String,
+    value: String,
+    clock: u64,
+}
+
+#[derive(serde::Serialize)]
+struct SenderRequest<'a> {
+    request: &'static str,
+    data: Vec<SenderItem<'a>>,
+    clock: u64,
+}
+
+#[derive(Debug)]
+pub struct ZabbixSender {
+    /// `host:port` of the Zabbix server or proxy
+    server: String,
+    /// Host name the items are registered under within Zabbix
+    host: String,
+    /// Devices that have been pushed to the discovery item
+    devices: BTreeSet<String>,
+    seen: SeenMeasurements,
+}
+
+impl ZabbixSender {
+    /// Creates a new sender. If `server` does not contain a port, [`DEFAULT_PORT`] is used.
+    pub fn new(server: &str, host: String) -> ZabbixSender {
+        let has_port = match server.rsplit_once(':') {
+            // bare IPv6 addresses contain colons, but are bracketed when a port is included
+            Some((host, port)) => port.parse::<u16>().is_ok() && (!host.contains(':') || host.starts_with('[')),
+            None => false,
+        };
+        let server = if has_port { server.to_owned() } else { format!("{}:{}", server, DEFAULT_PORT) };
+        ZabbixSender { server, host, devices: BTreeSet::new(), seen: SeenMeasurements::new() }
+    }
+
+    /// Item values for a reading. Keys for unavailable values (eg: CO2 during calibration) are skipped.
+    fn items(&self, adv: &DiscoveredAranet, device: &str, clock: u64) -> Vec<SenderItem<'_>> {
+        let Some(r) = adv.current_reading else { return Vec::new() };
+
+        let values: Vec<(&'static str, Option<String>)> = vec![
+            ("aranet.co2", r.co2_ppm.map(|v| v.to_string())),
+            ("aranet.temperature", r.temperature_c.map(|v| format!("{:.2}", v))),
+            ("aranet.humidity", Some(format!("{:.0}", r.humidity * 100.0))),
+            ("aranet.pressure", r.pressure_hpa.map(|v| format!("{:.1}", v))),
+            ("aranet.battery", Some(format!("{:.0}", r.battery * 100.0))),
+            ("aranet.status", Some((r.status as u8).to_string())),
+            ("aranet.age", Some(r.age.to_string())),
+        ];
+
+        values.into_iter()
+            .filter_map(|(metric, value)| value.map(|value| {
+                SenderItem { host: &self.host, key: key(metric, device), value, clock }
+            }))
+            .collect()
+    }
+
+    /// The discovery item's value, listing the devices with `device` added
+    fn discovery(&self, device: &str, clock: u64) -> SenderItem<'_> {
+        let devices: Vec<_> = self.devices.iter().map(String::as_str).chain([device])
+            .map(|device| serde_json::json!({ "{#DEVICE}": device }))
+            .collect();
+        let value = serde_json::Value::from(devices).to_string();
+        SenderItem { host: &self.host, key: DISCOVERY_KEY.to_owned(), value, clock }
+    }
+
+    /// Pushes the advertisement's reading to the Zabbix server, if it's a new measurement, timestamped with when it
+    /// was taken.
+    pub async fn send(&mut self, adv: &DiscoveredAranet) -> Result<(), ZabbixError> {
+        let Some(r) = adv.current_reading else {
+            log::debug!("advertisement from {} has no reading, not pushing to zabbix", adv.peripheral_id);
+            return Ok(());
+        };
+        if ! self.seen.is_new(adv) {
+            return Ok(());
+        }
+        let now = unix_secs(SystemTime::now());
+        let measured = adv.received.checked_sub(Duration::from_secs(r.age.into())).map(unix_secs).unwrap_or(now);
+
+        let device = crate::alias::display(adv);
+        let discovered = ! self.devices.contains(&device);
+        let mut data = self.items(adv, &device, measured);
+        if discovered {
+            data.insert(0, self.discovery(&device, now));
+        }
+
+        let request = SenderRequest { request: "sender data", data, clock: now };
+        let body = serde_json::to_vec(&request).expect("unable to serialize zabbix request");
+
+        log::debug!("pushing {} items to zabbix server {}", request.data.len(), self.server);
+        let mut stream = timed("connect", TcpStream::connect(&self.server)).await?;
+        timed("send", stream.write_all(&packet(&body))).await?;
+        let response = read_packet(&mut stream).await?;
+
+        let response: serde_json::Value = serde_json::from_slice(&response)
+            .map_err(|_| ZabbixError::MalformedResponse)?;
+        log::trace!("zabbix response: {}", response);
+
+        let info = response.get("info").and_then(|i| i.as_str()).unwrap_or_default().to_owned();
+        match response.get("response").and_then(|r| r.as_str()) {
+            Some("success") => {
+                // items that don't exist on the host are 'failed', but the request as a whole still succeeds
+                if !info.contains("failed: 0") {
+                    log::warn!("zabbix did not accept all pushed items: {}", info);
+                }
+                if discovered {
+                    self.devices.insert(device);
+                }
+                Ok(())
+            },
+            _ => Err(ZabbixError::Rejected(info)),
+        }
+    }
+}
+
+/// The key of a device's item, quoting the device as a key parameter if needed
+fn key(metric: &str, device: &str) -> String {
+    let quote = device.starts_with([' ', '"']) || device.contains([',', ']']);
+    if quote {
+        format!("{}[\"{}\"]", metric, device.replace('"', "\\\""))
+    } else {
+        format!("{}[{}]", metric, device)
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Frames a request body with the ZBXD header
+fn packet(body: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(13 + body.len());
+    packet.extend_from_slice(HEADER);
+    packet.push(FLAG_ZABBIX_PROTOCOL);
+    packet.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    packet.extend_from_slice(&0u32.to_le_bytes()); // reserved
+    packet.extend_from_slice(body);
+    packet
+}
+
+/// Reads a ZBXD packet, returning its body. Its length is checked before anything is allocated for it.
+async fn read_packet(stream: &mut (impl AsyncRead + Unpin)) -> Result<Vec<u8>, ZabbixError> {
+    let mut header = [0u8; 13];
+    timed("receive", stream.read_exact(&mut header)).await?;
+    if &header[..4] != HEADER {
+        return Err(ZabbixError::MalformedResponse);
+    }
+    let len = u32::from_le_bytes(header[5..9].try_into().unwrap()) as usize;
+    if len > MAX_RESPONSE {
+        return Err(ZabbixError::MalformedResponse);
+    }
+    let mut body = vec![0u8; len];
+    timed("receive", stream.read_exact(&mut body)).await?;
+    Ok(body)
+}
+
+/// Fails the operation if it doesn't complete within [`TIMEOUT`]
+async fn timed<T>(what: &str, op: impl Future<Output = io::Result<T>>) -> io::Result<T> {
+    tokio::time::timeout(TIMEOUT, op).await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, format!("unable to {} within {:?}", what, TIMEOUT)))?
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// Runs a server that reads one request, and replies with `response`, returning the request's body
+    async fn server(response: Vec<u8>) -> (String, tokio::task::JoinHandle<serde_json::Value>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = tokio::spawn(async move {
+            let (mut connection, _) = listener.accept().await.unwrap();
+            let request = read_packet(&mut connection).await.unwrap();
+            connection.write_all(&response).await.unwrap();
+            serde_json::from_slice(&request).unwrap()
+        });
+        (addr, server)
+    }
+
+    fn reading() -> DiscoveredAranet {
+        let data = [
+            0x20, 0x13, 0x04, 0x01, 0x00, 0x0c, 0x0f, 0x01,
+            0x64, 0x02, 0xc9, 0x01, 0x8b, 0x27, 0x29, 0x57, 0x01, 0x2c, 0x01, 0x2a, 0x00,
+        ];
+        DiscoveredAranet::parse(None, crate::simulate::peripheral_id(0), &data).unwrap()
+    }
+
+    #[tokio::test]
+    async fn sends_items() {
+        let reply = packet(br#"{"response":"success","info":"processed: 8; failed: 0; total: 8"}"#);
+        let (addr, received) = server(reply.clone()).await;
+        let mut sender = ZabbixSender::new(&addr, "office".to_owned());
+        let adv = reading();
+        sender.send(&adv).await.unwrap();
+
+        let request = received.await.unwrap();
+        assert_eq!(request["request"], "sender data");
+        let device = crate::alias::display(&adv);
+        let items = request["data"].as_array().unwrap();
+        assert_eq!(items.len(), 8);
+        assert_eq!(items[0]["key"], DISCOVERY_KEY);
+        assert_eq!(items[0]["value"], serde_json::json!([{ "{#DEVICE}": device }]).to_string());
+        assert_eq!(items[1]["host"], "office");
+        assert_eq!(items[1]["key"], format!("aranet.co2[{}]", device));
+        assert_eq!(items[1]["value"], "612");
+        assert_eq!(items[1]["clock"], unix_secs(adv.received) - 42);
+
+        // the same measurement isn't pushed again, and the device is only discovered once
+        sender.send(&adv).await.unwrap();
+        let mut next = reading();
+        next.received += Duration::from_secs(300);
+        let (addr, received) = server(reply).await;
+        sender.server = addr;
+        sender.send(&next).await.unwrap();
+        let request = received.await.unwrap();
+        let items = request["data"].as_array().unwrap();
+        assert_eq!(items.len(), 7);
+        assert_eq!(items[0]["key"], format!("aranet.co2[{}]", device));
+    }
+
+    #[test]
+    fn quotes_keys() {
+        assert_eq!(key("aranet.co2", "office"), "aranet.co2[office]");
+        assert_eq!(key("aranet.co2", "12:34:56:78:9A:BC"), "aranet.co2[12:34:56:78:9A:BC]");
+        assert_eq!(key("aranet.co2", "office, 2nd floor"), r#"aranet.co2["office, 2nd floor"]"#);
+        assert_eq!(key("aranet.co2", r#""quoted""#), r#"aranet.co2["\"quoted\""]"#);
+    }
+
+    #[tokio::test]
+    async fn reports_rejections() {
+        let (addr, _server) = server(packet(br#"{"response":"failed","info":"invalid request"}"#)).await;
+        let result = ZabbixSender::new(&addr, "office".to_owned()).send(&reading()).await;
+        assert!(matches!(result, Err(ZabbixError::Rejected(info)) if info == "invalid request"));
+    }
+
+    #[tokio::test]
+    async fn rejects_malformed_responses() {
+        let mut oversized = packet(b"");
+        oversized[5..9].copy_from_slice(&u32::MAX.to_le_bytes());
+        for response in [oversized, b"HTTP/1.1 400 Bad Request\r\n\r\n".to_vec(), packet(b"not json")] {
+            let (addr, _server) = server(response).await;
+            let result = ZabbixSender::new(&addr, "office".to_owned()).send(&reading()).await;
+            assert!(matches!(result, Err(ZabbixError::MalformedResponse)), "{:?}", result);
+        }
+    }
+}